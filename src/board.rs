@@ -2,8 +2,9 @@ use std::io::{stdin, Read};
 
 use crate::piece::{Piece, move_sliding_squares};
 use crate::base_types::{Position, Color, PieceType};
-use crate::precompute::get_direction_index;
+use crate::precompute::{get_direction_index, DIRECTION_OFFSETS, NUM_SQUARES_TO_EDGE};
 
+#[derive(Clone)]
 pub struct Board {
     pub pieces: [Option<Piece>; 64],
 }
@@ -189,4 +190,73 @@ impl Board {
 
         enemy_attacks
     }
-}
\ No newline at end of file
+
+    pub fn occupied(&self) -> u64 {
+        let mut occupied = 0;
+        for piece in self.pieces.iter().flatten() {
+            occupied |= piece.position.bitboard();
+        }
+        occupied
+    }
+
+    /**
+     * Returns a bitboard of all pieces (of both colors) attacking the given square.
+     * Only pieces on the `occupied` bitboard are considered and only they block sliding pieces,
+     * so removing a piece from `occupied` reveals the x-ray attackers behind it.
+     */
+    pub fn attackers_to(&self, square: Position, occupied: u64) -> u64 {
+        let mut attackers = 0;
+        let col = square.get_col() as i8;
+        let row = square.get_row() as i8;
+
+        // Sliding pieces. The first 4 directions are straight, the last 4 diagonal
+        for dir in 0..8 {
+            let mut current = square;
+            for _ in 0..NUM_SQUARES_TO_EDGE[square.index()][dir] {
+                current = current.get_change(DIRECTION_OFFSETS[dir]);
+                if occupied & current.bitboard() == 0 {
+                    continue;
+                }
+                if let Some(piece) = self.get_piece(current) {
+                    let attacks = match piece.piece_type {
+                        PieceType::Queen => true,
+                        PieceType::Rook => dir < 4,
+                        PieceType::Bishop => dir >= 4,
+                        _ => false,
+                    };
+                    if attacks {
+                        attackers |= current.bitboard();
+                    }
+                }
+                break;
+            }
+        }
+
+        let knight_squares = [(1, 2), (1, -2), (2, 1), (2, -1), (-1, 2), (-1, -2), (-2, 1), (-2, -1)];
+        let king_squares = [(1, 0), (1, 1), (0, 1), (-1, 1), (-1, 0), (-1, -1), (0, -1), (1, -1)];
+        for (offsets, piece_type) in [(knight_squares, PieceType::Knight), (king_squares, PieceType::King)] {
+            for (col_change, row_change) in offsets {
+                let position = Position::from((col + col_change, row + row_change));
+                if let Some(piece) = self.get_piece(position) {
+                    if piece.piece_type == piece_type {
+                        attackers |= position.bitboard();
+                    }
+                }
+            }
+        }
+
+        // White pawns attack upwards, so they stand one row below the square. Black pawns one row above
+        for (row_change, color) in [(-1, Color::White), (1, Color::Black)] {
+            for col_change in [-1, 1] {
+                let position = Position::from((col + col_change, row + row_change));
+                if let Some(piece) = self.get_piece(position) {
+                    if piece.piece_type == PieceType::Pawn && piece.color == color {
+                        attackers |= position.bitboard();
+                    }
+                }
+            }
+        }
+
+        attackers & occupied
+    }
+}
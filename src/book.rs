@@ -0,0 +1,320 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    base_types::{Color, PieceType, Position},
+    game::Game,
+    moves::{Move, MoveType},
+    polyglot_keys::POLYGLOT_KEYS,
+};
+
+const POLYGLOT_CASTLING: usize = 768;
+const POLYGLOT_EN_PASSANT: usize = 772;
+const POLYGLOT_WHITE_TO_MOVE: usize = 780;
+// Size of an entry in the book file: key, move, weight and learn data
+pub const ENTRY_SIZE: usize = 16;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BookSelection {
+    Best, // Always the move with the highest weight
+    Weighted, // Random move, more likely the higher the weight
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct BookEntry {
+    pub key: u64,
+    pub mov: u16, // Polyglot encoded move
+    pub weight: u16,
+}
+
+/**
+ * Opening book in the Polyglot format. The whole file is loaded into memory,
+ * the entries are sorted by key so the moves of a position are found with a binary search.
+ */
+pub struct OpeningBook {
+    entries: Vec<BookEntry>,
+    pub max_ply: usize, // The book is not used after this many plies from the start of the game
+    pub selection: BookSelection,
+}
+
+impl OpeningBook {
+    pub fn load(path: &str) -> Result<OpeningBook, String> {
+        let data = std::fs::read(path).map_err(|err| format!("Cant read book {}: {}", path, err))?;
+        if data.len() % ENTRY_SIZE != 0 {
+            return Err(format!("Invalid book {}: size is not a multiple of {}", path, ENTRY_SIZE));
+        }
+        let mut entries: Vec<BookEntry> = data
+            .chunks_exact(ENTRY_SIZE)
+            .map(|entry| BookEntry {
+                key: u64::from_be_bytes(entry[0..8].try_into().unwrap()),
+                mov: u16::from_be_bytes(entry[8..10].try_into().unwrap()),
+                weight: u16::from_be_bytes(entry[10..12].try_into().unwrap()),
+            })
+            .collect();
+        // Books should be sorted already, but binary search does not work if one is not
+        entries.sort_by_key(|entry| entry.key);
+        println!("Loaded book {} with {} entries", path, entries.len());
+        Ok(OpeningBook {
+            entries,
+            max_ply: 20,
+            selection: BookSelection::Weighted,
+        })
+    }
+
+    /**
+     * All legal book moves of the position with their weights.
+     */
+    pub fn moves(&self, game: &Game) -> Vec<(Move, u16)> {
+        let key = polyglot_hash(game);
+        let start = self.entries.partition_point(|entry| entry.key < key);
+        let legal_moves = game.get_possible_team_moves(game.turn);
+        self.entries[start..]
+            .iter()
+            .take_while(|entry| entry.key == key)
+            .filter_map(|entry| {
+                let mov = decode_move(game, entry.mov)?;
+                let legal = legal_moves.iter().find(|m| {
+                    m.from == mov.from
+                        && m.to == mov.to
+                        && m.move_type.get_promotion_piece() == mov.move_type.get_promotion_piece()
+                })?;
+                Some((*legal, entry.weight))
+            })
+            .collect()
+    }
+
+    /**
+     * The book move for the position, if the game is not past the max ply and the position is in the book.
+     * `ply` is the number of plies played since the start of the game.
+     */
+    pub fn probe(&self, game: &Game, ply: usize) -> Option<Move> {
+        if ply >= self.max_ply {
+            return None;
+        }
+        let moves = self.moves(game);
+        match self.selection {
+            BookSelection::Best => moves.iter().max_by_key(|(_, weight)| *weight).map(|(mov, _)| *mov),
+            BookSelection::Weighted => {
+                let total: u32 = moves.iter().map(|(_, weight)| *weight as u32).sum();
+                if total == 0 {
+                    return moves.first().map(|(mov, _)| *mov);
+                }
+                let mut pick = random(game.hash) % total;
+                for (mov, weight) in moves {
+                    if pick < weight as u32 {
+                        return Some(mov);
+                    }
+                    pick -= weight as u32;
+                }
+                None
+            }
+        }
+    }
+}
+
+/**
+ * Hash of the position as defined by the Polyglot format. Other than in our own hash, the en passant file
+ * only counts if a pawn of the side to move can actually capture en passant.
+ */
+pub fn polyglot_hash(game: &Game) -> u64 {
+    let mut hash = 0;
+    for row in 0..8u8 {
+        for col in 0..8u8 {
+            let position = Position::from((col, row));
+            if let Some(piece) = game.board.get_piece(position) {
+                let color_offset = if let Color::White = piece.color { 1 } else { 0 };
+                let kind = piece.piece_type as usize * 2 + color_offset;
+                hash ^= POLYGLOT_KEYS[kind * 64 + position.index()];
+            }
+        }
+    }
+
+    let castling_rights = [
+        game.state.white_can_castle_kingside,
+        game.state.white_can_castle_queenside,
+        game.state.black_can_castle_kingside,
+        game.state.black_can_castle_queenside,
+    ];
+    for (i, can_castle) in castling_rights.iter().enumerate() {
+        if *can_castle {
+            hash ^= POLYGLOT_KEYS[POLYGLOT_CASTLING + i];
+        }
+    }
+
+    if let Some(target) = game.state.en_passant_target {
+        // The capturing pawns stand next to the pawn that moved, on the rank below (white) or above (black) the target
+        let pawn_row = if let Color::White = game.turn { target.get_row() as i8 - 1 } else { target.get_row() as i8 + 1 };
+        let can_capture = [-1, 1].iter().any(|offset| {
+            let position = Position::from((target.get_col() as i8 + offset, pawn_row));
+            position.is_valid()
+                && game
+                    .board
+                    .get_piece(position)
+                    .is_some_and(|piece| piece.piece_type == PieceType::Pawn && piece.color == game.turn)
+        });
+        if can_capture {
+            hash ^= POLYGLOT_KEYS[POLYGLOT_EN_PASSANT + target.get_col() as usize];
+        }
+    }
+
+    if let Color::White = game.turn {
+        hash ^= POLYGLOT_KEYS[POLYGLOT_WHITE_TO_MOVE];
+    }
+    hash
+}
+
+/**
+ * Decodes a Polyglot move: to file, to row, from file, from row and promotion piece, three bits each.
+ * Castling is stored as the king capturing its own rook and is changed to the king moving two squares.
+ */
+fn decode_move(game: &Game, encoded: u16) -> Option<Move> {
+    let bits = |shift: u16| ((encoded >> shift) & 0x7) as u8;
+    let from = Position::from((bits(6), bits(9)));
+    let mut to = Position::from((bits(0), bits(3)));
+    let move_type = match bits(12) {
+        0 => MoveType::Quite,
+        1 => MoveType::KnightPromotion,
+        2 => MoveType::BishopPromotion,
+        3 => MoveType::RookPromotion,
+        4 => MoveType::QueenPromotion,
+        _ => return None,
+    };
+
+    let piece = game.board.get_piece(from)?;
+    if piece.piece_type == PieceType::King && from.get_col() == 4 && from.get_row() == to.get_row() {
+        match to.get_col() {
+            7 => to = Position::from((6, to.get_row())),
+            0 => to = Position::from((2, to.get_row())),
+            _ => {}
+        }
+    }
+    Some(Move { from, to, move_type })
+}
+
+/**
+ * Encodes a move the way `decode_move` reads it, with castling as the king capturing its own rook.
+ */
+pub fn encode_move(mov: Move) -> u16 {
+    let to = match mov.move_type {
+        MoveType::KingCastle => Position::from((7, mov.to.get_row())),
+        MoveType::QueenCastle => Position::from((0, mov.to.get_row())),
+        _ => mov.to,
+    };
+    let promotion = match mov.move_type.get_promotion_piece() {
+        PieceType::Knight => 1,
+        PieceType::Bishop => 2,
+        PieceType::Rook => 3,
+        PieceType::Queen => 4,
+        _ => 0,
+    };
+    to.get_col() as u16
+        | (to.get_row() as u16) << 3
+        | (mov.from.get_col() as u16) << 6
+        | (mov.from.get_row() as u16) << 9
+        | promotion << 12
+}
+
+/**
+ * Random number for picking a book move. Does not need to be good, only different between games.
+ */
+pub fn random(seed: u64) -> u32 {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64);
+    let mut x = seed ^ nanos ^ 0x9E37_79B9_7F4A_7C15;
+    x ^= x >> 30;
+    x = x.wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x ^= x >> 27;
+    x = x.wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^= x >> 31;
+    (x >> 32) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Start position and the position after 1.e4 with two moves each, and an illegal move that gets skipped.
+    // The entries are not sorted by key
+    fn small_book() -> OpeningBook {
+        OpeningBook::load(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/book/small.bin")).unwrap()
+    }
+
+    fn play(moves: &[&str]) -> Game {
+        let mut game = Game::from_fen(crate::STARTING_POS_FEN);
+        for mov in moves {
+            let mov = game.get_possible_team_moves(game.turn).into_iter().find(|m| m.to_string() == *mov).unwrap();
+            game.make_move(mov);
+        }
+        game
+    }
+
+    #[test]
+    fn hash_matches_the_reference_keys() {
+        // The examples of the Polyglot book format documentation
+        let keys: [(&[&str], u64); 9] = [
+            (&[], 0x463b96181691fc9c),
+            (&["e2e4"], 0x823c9b50fd114196),
+            (&["e2e4", "d7d5"], 0x0756b94461c50fb0),
+            (&["e2e4", "d7d5", "e4e5"], 0x662fafb965db29d4),
+            (&["e2e4", "d7d5", "e4e5", "f7f5"], 0x22a48b5a8e47ff78),
+            (&["e2e4", "d7d5", "e4e5", "f7f5", "e1e2"], 0x652a607ca3f242c1),
+            (&["e2e4", "d7d5", "e4e5", "f7f5", "e1e2", "e8f7"], 0x00fdd303c946bdd9),
+            (&["a2a4", "b7b5", "h2h4", "b5b4", "c2c4"], 0x3c8123ea7b067637),
+            (&["a2a4", "b7b5", "h2h4", "b5b4", "c2c4", "b4c3", "a1a3"], 0x5c3f9b829b279560),
+        ];
+        for (moves, key) in keys {
+            assert_eq!(polyglot_hash(&play(moves)), key, "{:?}", moves);
+        }
+    }
+
+    #[test]
+    fn castling_is_decoded_as_the_king_move() {
+        let game = Game::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1");
+        // e1h1 and e1a1 in the book are the king taking its own rook
+        assert_eq!(decode_move(&game, 0x107).unwrap().to_string(), "e1g1");
+        assert_eq!(decode_move(&game, 0x100).unwrap().to_string(), "e1c1");
+        let game = Game::from_fen("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1");
+        assert_eq!(decode_move(&game, 0xF3F).unwrap().to_string(), "e8g8");
+        assert_eq!(decode_move(&game, 0xF38).unwrap().to_string(), "e8c8");
+
+        for mov in game.get_possible_team_moves(game.turn) {
+            let decoded = decode_move(&game, encode_move(mov)).unwrap();
+            assert_eq!(decoded.to_string(), mov.to_string());
+        }
+        // A rook move to the corner is not castling
+        assert_eq!(decode_move(&game, encode_move(Move::from_string("a8a1"))).unwrap().to_string(), "a8a1");
+    }
+
+    #[test]
+    fn decodes_promotions() {
+        let game = Game::from_fen("8/1P6/8/8/8/k7/8/6K1 w - - 0 1");
+        // b7b8 with a knight, bishop, rook and queen
+        for (promotion, name) in [(1, "b7b8n"), (2, "b7b8b"), (3, "b7b8r"), (4, "b7b8q")] {
+            let encoded = 1 | 7 << 3 | 1 << 6 | 6 << 9 | promotion << 12;
+            assert_eq!(decode_move(&game, encoded).unwrap().to_string(), name);
+        }
+        assert!(decode_move(&game, 1 | 7 << 3 | 1 << 6 | 6 << 9 | 5 << 12).is_none());
+    }
+
+    #[test]
+    fn probes_the_book_file() {
+        let mut book = small_book();
+        let start = play(&[]);
+        let moves: Vec<(String, u16)> = book.moves(&start).iter().map(|(mov, weight)| (mov.to_string(), *weight)).collect();
+        assert_eq!(moves, vec![("e2e4".to_string(), 10), ("d2d4".to_string(), 5)]);
+        let after_e4 = play(&["e2e4"]);
+        assert_eq!(book.moves(&after_e4).iter().map(|(mov, _)| mov.to_string()).collect::<Vec<_>>(), vec!["c7c5"]);
+        assert!(book.moves(&play(&["d2d4"])).is_empty());
+
+        book.selection = BookSelection::Best;
+        assert_eq!(book.probe(&start, 0).unwrap().to_string(), "e2e4");
+        assert!(book.probe(&start, book.max_ply).is_none());
+        // Weighted picks are always one of the book moves
+        book.selection = BookSelection::Weighted;
+        for _ in 0..20 {
+            let mov = book.probe(&start, 0).unwrap().to_string();
+            assert!(mov == "e2e4" || mov == "d2d4", "{}", mov);
+        }
+    }
+}
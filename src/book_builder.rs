@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::{
+    base_types::Color,
+    book::{encode_move, polyglot_hash, ENTRY_SIZE},
+    game::Game,
+    pgn::{parse_pgn, PgnGame},
+    STARTING_POS_FEN,
+};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ResultFilter {
+    All,
+    Decisive, // Skip drawn games
+    Winner, // Only the moves of the side that won
+}
+
+#[derive(Clone, Debug)]
+pub struct BookBuilderSettings {
+    pub max_ply: usize, // Positions after this many plies are not added
+    pub min_elo: u32, // Both players need at least this rating. Games without ratings are skipped if set
+    pub results: ResultFilter,
+    pub min_games: u32, // Moves played in fewer games are left out of the book
+}
+
+impl Default for BookBuilderSettings {
+    fn default() -> Self {
+        BookBuilderSettings {
+            max_ply: 20,
+            min_elo: 0,
+            results: ResultFilter::All,
+            min_games: 1,
+        }
+    }
+}
+
+/**
+ * Results of a move in one position, from the view of the side that played it.
+ */
+#[derive(Clone, Debug, Default)]
+struct MoveStats {
+    uci: String,
+    games: u32,
+    wins: u32,
+    draws: u32,
+    losses: u32,
+}
+
+impl MoveStats {
+    fn weight(&self) -> u32 {
+        2 * self.wins + self.draws
+    }
+}
+
+/**
+ * Builds an opening book from PGN games. Every game is replayed with `Game::make_move`
+ * and the moves of the positions up to the max ply are counted.
+ *
+ * The book is written as Polyglot .bin (weight = 2 * wins + draws) or, for any other file extension,
+ * as text with one line per move: polyglot key, move, games, wins, draws, losses and the FEN.
+ */
+pub struct BookBuilder {
+    settings: BookBuilderSettings,
+    moves: HashMap<(u64, u16), MoveStats>,
+    fens: HashMap<u64, String>, // First position seen with each key, for the text format
+    pub games_read: usize,
+    pub games_used: usize,
+}
+
+impl BookBuilder {
+    pub fn new(settings: BookBuilderSettings) -> BookBuilder {
+        BookBuilder {
+            settings,
+            moves: HashMap::new(),
+            fens: HashMap::new(),
+            games_read: 0,
+            games_used: 0,
+        }
+    }
+
+    /**
+     * Adds all games of a PGN file, or of all .pgn files if the path is a directory.
+     */
+    pub fn add_pgn(&mut self, path: &str) -> Result<(), String> {
+        if Path::new(path).is_dir() {
+            let entries = std::fs::read_dir(path).map_err(|err| format!("Cant read {}: {}", path, err))?;
+            let mut files: Vec<String> = entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|file| file.extension().is_some_and(|extension| extension == "pgn"))
+                .map(|file| file.to_string_lossy().to_string())
+                .collect();
+            files.sort();
+            for file in files {
+                self.add_pgn(&file)?;
+            }
+            return Ok(());
+        }
+
+        // PGN files are not always UTF-8, player names are the only non ASCII parts anyway
+        let content = std::fs::read(path).map_err(|err| format!("Cant read {}: {}", path, err))?;
+        for game in parse_pgn(&String::from_utf8_lossy(&content)) {
+            self.add_game(&game);
+        }
+        println!("Read {}: {} games, {} used so far", path, self.games_read, self.games_used);
+        Ok(())
+    }
+
+    /**
+     * Returns true if the game passed the filters.
+     */
+    pub fn add_game(&mut self, pgn_game: &PgnGame) -> bool {
+        self.games_read += 1;
+        let winner = match pgn_game.result.as_str() {
+            "1-0" => Some(Color::White),
+            "0-1" => Some(Color::Black),
+            "1/2-1/2" => None,
+            _ => return false,
+        };
+        if winner.is_none() && self.settings.results != ResultFilter::All {
+            return false;
+        }
+        if self.settings.min_elo > 0 {
+            let elo = |header| pgn_game.header(header).and_then(|elo| elo.parse::<u32>().ok()).unwrap_or(0);
+            if elo("WhiteElo") < self.settings.min_elo || elo("BlackElo") < self.settings.min_elo {
+                return false;
+            }
+        }
+        self.games_used += 1;
+
+        let mut game = Game::from_fen(pgn_game.header("FEN").unwrap_or(STARTING_POS_FEN));
+        for fide in pgn_game.moves.iter().take(self.settings.max_ply) {
+            let mov = game.fide_to_move(fide);
+            if !mov.is_valid() {
+                break;
+            }
+
+            let mover = game.turn;
+            if self.settings.results != ResultFilter::Winner || winner == Some(mover) {
+                let key = polyglot_hash(&game);
+                self.fens.entry(key).or_insert_with(|| game.to_fen());
+                let stats = self.moves.entry((key, encode_move(mov))).or_default();
+                stats.uci = mov.to_string();
+                stats.games += 1;
+                match winner {
+                    Some(color) if color == mover => stats.wins += 1,
+                    Some(_) => stats.losses += 1,
+                    None => stats.draws += 1,
+                }
+            }
+
+            if !game.make_move(mov) {
+                break;
+            }
+        }
+        true
+    }
+
+    /**
+     * Writes the book and returns the number of entries written.
+     */
+    pub fn write(&self, path: &str) -> Result<usize, String> {
+        let mut entries: Vec<(&(u64, u16), &MoveStats)> = self
+            .moves
+            .iter()
+            .filter(|(_, stats)| stats.games >= self.settings.min_games)
+            .collect();
+        // Sorted by key as Polyglot requires, the best moves of a position first
+        entries.sort_by(|(a_key, a), (b_key, b)| a_key.0.cmp(&b_key.0).then(b.weight().cmp(&a.weight())));
+
+        let data = if path.ends_with(".bin") {
+            let max_weight = entries.iter().map(|(_, stats)| stats.weight()).max().unwrap_or(0);
+            let mut data = Vec::with_capacity(entries.len() * ENTRY_SIZE);
+            for ((key, mov), stats) in &entries {
+                // Moves that only lost get no weight and are left out
+                if stats.weight() == 0 {
+                    continue;
+                }
+                let weight = if max_weight > u16::MAX as u32 {
+                    (stats.weight() as u64 * u16::MAX as u64 / max_weight as u64).max(1) as u16
+                } else {
+                    stats.weight() as u16
+                };
+                data.extend_from_slice(&key.to_be_bytes());
+                data.extend_from_slice(&mov.to_be_bytes());
+                data.extend_from_slice(&weight.to_be_bytes());
+                data.extend_from_slice(&0u32.to_be_bytes());
+            }
+            data
+        } else {
+            let mut text = String::from("# key move games wins draws losses fen\n");
+            for ((key, _), stats) in &entries {
+                text.push_str(&format!(
+                    "{:016x} {} {} {} {} {} {}\n",
+                    key, stats.uci, stats.games, stats.wins, stats.draws, stats.losses, self.fens[key]
+                ));
+            }
+            text.into_bytes()
+        };
+
+        std::fs::write(path, &data).map_err(|err| format!("Cant write {}: {}", path, err))?;
+        Ok(if path.ends_with(".bin") { data.len() / ENTRY_SIZE } else { entries.len() })
+    }
+}
@@ -0,0 +1,167 @@
+use serde::{Deserialize, Serialize};
+
+use crate::book::BookSelection;
+use crate::search::Score;
+
+/**
+ * How the Lichess bot behaves during a game. Loaded from a JSON file, every field is optional:
+ *
+ * {
+ *     "resign": true, "resign_score": 800, "resign_moves": 5,
+ *     "offer_draws": true, "accept_draws": true,
+ *     "draw_score": 25, "draw_moves": 6, "draw_min_ply": 60,
+ *     "takebacks": "casual", "max_takebacks": 1,
+ *     "greeting": "Good luck!", "goodbye": "Thanks for the game!",
+ *     "chat_rooms": ["player", "spectator"],
+ *     "book": "book.bin", "book_max_ply": 20, "book_selection": "weighted",
+ *     "tablebase": "syzygy"
+ * }
+ *
+ * Scores are in centipawns from our view. The bot resigns when the score stays below -resign_score
+ * for resign_moves of our moves in a row. It offers and accepts draws when the score stayed within
+ * draw_score for draw_moves moves after draw_min_ply plies, or when the position repeats without
+ * us being better. Empty chat messages are not sent.
+ * The book is used for the first book_max_ply plies, picking the best or a weighted random move.
+ * The tablebase is a directory with Syzygy files, used by the search.
+ */
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct BotBehaviour {
+    pub resign: bool,
+    pub resign_score: i32,
+    pub resign_moves: u32,
+    pub offer_draws: bool,
+    pub accept_draws: bool,
+    pub draw_score: i32,
+    pub draw_moves: u32,
+    pub draw_min_ply: usize,
+    pub takebacks: TakebackPolicy,
+    pub max_takebacks: u32, // Per game
+    pub greeting: String,
+    pub goodbye: String,
+    pub chat_rooms: Vec<String>, // player and/or spectator
+    pub book: Option<String>, // Path of a Polyglot opening book
+    pub book_max_ply: usize,
+    pub book_selection: BookSelection,
+    pub tablebase: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TakebackPolicy {
+    Never,
+    Casual, // Only in casual games
+    Always,
+}
+
+impl Default for BotBehaviour {
+    fn default() -> Self {
+        BotBehaviour {
+            resign: true,
+            resign_score: 800,
+            resign_moves: 5,
+            offer_draws: true,
+            accept_draws: true,
+            draw_score: 25,
+            draw_moves: 6,
+            draw_min_ply: 60,
+            takebacks: TakebackPolicy::Casual,
+            max_takebacks: 1,
+            greeting: "Good luck, have fun!".to_string(),
+            goodbye: "Thanks for the game!".to_string(),
+            chat_rooms: vec!["player".to_string(), "spectator".to_string()],
+            book: None,
+            book_max_ply: 20,
+            book_selection: BookSelection::Weighted,
+            tablebase: None,
+        }
+    }
+}
+
+/**
+ * Scores of our last moves in a game, used to decide about resigning and draws.
+ */
+#[derive(Default)]
+pub struct GameTracker {
+    losing_moves: u32, // Our moves in a row scored below the resign score
+    drawn_moves: u32, // Our moves in a row scored within the draw score
+    last_score: Option<Score>,
+    takebacks: u32,
+    draw_offered_at: Option<usize>, // Ply of our last draw offer
+}
+
+// Plies to wait before offering a draw again
+const DRAW_OFFER_INTERVAL: usize = 20;
+
+impl BotBehaviour {
+    pub fn load(path: &str) -> Result<BotBehaviour, String> {
+        let content = std::fs::read_to_string(path).map_err(|err| format!("Cant read {}: {}", path, err))?;
+        serde_json::from_str(&content).map_err(|err| format!("Invalid behaviour {}: {}", path, err))
+    }
+
+    fn is_drawn(&self, score: Score) -> bool {
+        matches!(score, Score::Cp(cp) if cp.abs() <= self.draw_score)
+    }
+
+    /**
+     * Records the score of the move we are about to play.
+     */
+    pub fn record_score(&self, tracker: &mut GameTracker, score: Score) {
+        let losing = match score {
+            Score::Cp(cp) => cp < -self.resign_score,
+            Score::Mate(moves) => moves < 0,
+        };
+        tracker.losing_moves = if losing { tracker.losing_moves + 1 } else { 0 };
+        tracker.drawn_moves = if self.is_drawn(score) { tracker.drawn_moves + 1 } else { 0 };
+        tracker.last_score = Some(score);
+    }
+
+    pub fn should_resign(&self, tracker: &GameTracker) -> bool {
+        self.resign && tracker.losing_moves >= self.resign_moves.max(1)
+    }
+
+    /**
+     * Whether the game is dead drawn, or the position repeats and we are not better.
+     */
+    fn wants_draw(&self, tracker: &GameTracker, ply: usize, repetition: bool) -> bool {
+        let not_better = tracker.last_score.is_some_and(|score| match score {
+            Score::Cp(cp) => cp <= self.draw_score,
+            Score::Mate(moves) => moves < 0,
+        });
+        (repetition && not_better) || (ply >= self.draw_min_ply && tracker.drawn_moves >= self.draw_moves.max(1))
+    }
+
+    /**
+     * Whether to offer a draw with our move. Offers are repeated only every few moves.
+     */
+    pub fn should_offer_draw(&self, tracker: &mut GameTracker, ply: usize, repetition: bool) -> bool {
+        if !self.offer_draws || !self.wants_draw(tracker, ply, repetition) {
+            return false;
+        }
+        if tracker.draw_offered_at.is_some_and(|offered| ply < offered + DRAW_OFFER_INTERVAL) {
+            return false;
+        }
+        tracker.draw_offered_at = Some(ply);
+        true
+    }
+
+    pub fn should_accept_draw(&self, tracker: &GameTracker, ply: usize, repetition: bool) -> bool {
+        self.accept_draws && self.wants_draw(tracker, ply, repetition)
+    }
+
+    /**
+     * Whether to accept a takeback request. Accepted takebacks count against the limit of the game.
+     */
+    pub fn accept_takeback(&self, tracker: &mut GameTracker, rated: bool) -> bool {
+        let allowed = match self.takebacks {
+            TakebackPolicy::Never => false,
+            TakebackPolicy::Casual => !rated,
+            TakebackPolicy::Always => true,
+        };
+        if !allowed || tracker.takebacks >= self.max_takebacks {
+            return false;
+        }
+        tracker.takebacks += 1;
+        true
+    }
+}
@@ -0,0 +1,321 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::lichess::Challenge;
+
+/**
+ * Decides which Lichess challenges the bot accepts. Loaded from a JSON file, every field is optional:
+ *
+ * {
+ *     "variants": ["standard", "fromPosition"],
+ *     "min_initial": 60, "max_initial": 1800,
+ *     "min_increment": 0, "max_increment": 30,
+ *     "allow_unlimited": false, "allow_correspondence": false,
+ *     "allow_rated": true, "allow_casual": true,
+ *     "min_rating": 1000, "max_rating": 2500,
+ *     "allow_bots": true, "allow_humans": true,
+ *     "max_games": 2,
+ *     "blocklist": ["someone"]
+ * }
+ *
+ * Times are in seconds. The blocklist contains user names and is not case sensitive.
+ */
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct ChallengePolicy {
+    pub variants: Vec<String>,
+    pub min_initial: u64,
+    pub max_initial: u64,
+    pub min_increment: u64,
+    pub max_increment: u64,
+    pub allow_unlimited: bool,
+    pub allow_correspondence: bool,
+    pub allow_rated: bool,
+    pub allow_casual: bool,
+    pub min_rating: Option<u32>,
+    pub max_rating: Option<u32>,
+    pub allow_bots: bool,
+    pub allow_humans: bool,
+    pub max_games: usize, // Number of games played at the same time
+    pub blocklist: Vec<String>,
+}
+
+impl Default for ChallengePolicy {
+    fn default() -> Self {
+        ChallengePolicy {
+            variants: vec!["standard".to_string()],
+            min_initial: 0,
+            max_initial: u64::MAX,
+            min_increment: 0,
+            max_increment: u64::MAX,
+            allow_unlimited: true,
+            allow_correspondence: false,
+            allow_rated: true,
+            allow_casual: true,
+            min_rating: None,
+            max_rating: None,
+            allow_bots: true,
+            allow_humans: true,
+            max_games: 2,
+            blocklist: Vec::new(),
+        }
+    }
+}
+
+/**
+ * Reasons for declining a challenge, as understood by the Lichess decline endpoint.
+ */
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DeclineReason {
+    Generic,
+    Later,
+    TooFast,
+    TooSlow,
+    TimeControl,
+    Rated,
+    Casual,
+    Standard,
+    Variant,
+    NoBot,
+    OnlyBot,
+}
+
+impl DeclineReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeclineReason::Generic => "generic",
+            DeclineReason::Later => "later",
+            DeclineReason::TooFast => "tooFast",
+            DeclineReason::TooSlow => "tooSlow",
+            DeclineReason::TimeControl => "timeControl",
+            DeclineReason::Rated => "rated",
+            DeclineReason::Casual => "casual",
+            DeclineReason::Standard => "standard",
+            DeclineReason::Variant => "variant",
+            DeclineReason::NoBot => "noBot",
+            DeclineReason::OnlyBot => "onlyBot",
+        }
+    }
+}
+
+impl fmt::Display for DeclineReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl ChallengePolicy {
+    pub fn load(path: &str) -> Result<ChallengePolicy, String> {
+        let content = std::fs::read_to_string(path).map_err(|err| format!("Cant read {}: {}", path, err))?;
+        serde_json::from_str(&content).map_err(|err| format!("Invalid policy {}: {}", path, err))
+    }
+
+    /**
+     * Returns why the challenge should be declined, or None if it can be accepted.
+     * `active_games` is the number of games the bot is currently playing.
+     */
+    pub fn check(&self, challenge: &Challenge, active_games: usize) -> Option<DeclineReason> {
+        let challenger = challenge.challenger.as_ref();
+        let challenger_name = challenger.map(|c| c.name.to_lowercase()).unwrap_or_default();
+        if self.blocklist.iter().any(|name| name.to_lowercase() == challenger_name) {
+            return Some(DeclineReason::Generic);
+        }
+
+        if active_games >= self.max_games {
+            return Some(DeclineReason::Later);
+        }
+
+        if !self.variants.contains(&challenge.variant.key) {
+            // Tell the challenger to send a standard game if that is all we play
+            if self.variants.len() == 1 && self.variants[0] == "standard" {
+                return Some(DeclineReason::Standard);
+            }
+            return Some(DeclineReason::Variant);
+        }
+
+        let time_control = &challenge.time_control;
+        match time_control.control_type.as_str() {
+            "clock" => {
+                let initial = time_control.limit.unwrap_or(0);
+                let increment = time_control.increment.unwrap_or(0);
+                if initial < self.min_initial || increment < self.min_increment {
+                    return Some(DeclineReason::TooFast);
+                }
+                if initial > self.max_initial || increment > self.max_increment {
+                    return Some(DeclineReason::TooSlow);
+                }
+            }
+            "unlimited" if !self.allow_unlimited => return Some(DeclineReason::TimeControl),
+            "correspondence" if !self.allow_correspondence => return Some(DeclineReason::TimeControl),
+            _ => {}
+        }
+
+        // The reasons ask for the other kind of game
+        if challenge.rated && !self.allow_rated {
+            return Some(DeclineReason::Casual);
+        }
+        if !challenge.rated && !self.allow_casual {
+            return Some(DeclineReason::Rated);
+        }
+
+        let is_bot = challenger.is_some_and(|c| c.title.as_deref() == Some("BOT"));
+        if is_bot && !self.allow_bots {
+            return Some(DeclineReason::NoBot);
+        }
+        if !is_bot && !self.allow_humans {
+            return Some(DeclineReason::OnlyBot);
+        }
+
+        if let Some(rating) = challenger.and_then(|c| c.rating) {
+            if self.min_rating.is_some_and(|min| rating < min) || self.max_rating.is_some_and(|max| rating > max) {
+                return Some(DeclineReason::Generic);
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn challenge(rated: bool, title: Option<&str>, rating: u32, variant: &str, time_control: serde_json::Value) -> Challenge {
+        serde_json::from_value(serde_json::json!({
+            "id": "c1",
+            "challenger": { "name": "Someone", "rating": rating, "title": title },
+            "rated": rated,
+            "variant": { "key": variant },
+            "timeControl": time_control,
+        }))
+        .unwrap()
+    }
+
+    fn blitz(rated: bool, title: Option<&str>, rating: u32) -> Challenge {
+        challenge(rated, title, rating, "standard", serde_json::json!({ "type": "clock", "limit": 180, "increment": 2 }))
+    }
+
+    #[test]
+    fn accepts_by_default() {
+        let policy = ChallengePolicy::default();
+        assert_eq!(policy.check(&blitz(true, None, 1500), 0), None);
+        assert_eq!(policy.check(&blitz(false, Some("BOT"), 1500), 1), None);
+    }
+
+    #[test]
+    fn declines_outside_the_rating_range() {
+        let policy = ChallengePolicy {
+            min_rating: Some(1200),
+            max_rating: Some(2000),
+            ..Default::default()
+        };
+        assert_eq!(policy.check(&blitz(true, None, 1100), 0), Some(DeclineReason::Generic));
+        assert_eq!(policy.check(&blitz(true, None, 2100), 0), Some(DeclineReason::Generic));
+        assert_eq!(policy.check(&blitz(true, None, 1500), 0), None);
+    }
+
+    #[test]
+    fn declines_rated_or_casual() {
+        let casual_only = ChallengePolicy {
+            allow_rated: false,
+            ..Default::default()
+        };
+        assert_eq!(casual_only.check(&blitz(true, None, 1500), 0), Some(DeclineReason::Casual));
+        assert_eq!(casual_only.check(&blitz(false, None, 1500), 0), None);
+
+        let rated_only = ChallengePolicy {
+            allow_casual: false,
+            ..Default::default()
+        };
+        assert_eq!(rated_only.check(&blitz(false, None, 1500), 0), Some(DeclineReason::Rated));
+        assert_eq!(rated_only.check(&blitz(true, None, 1500), 0), None);
+    }
+
+    #[test]
+    fn declines_bots_or_humans() {
+        let humans_only = ChallengePolicy {
+            allow_bots: false,
+            ..Default::default()
+        };
+        assert_eq!(humans_only.check(&blitz(true, Some("BOT"), 1500), 0), Some(DeclineReason::NoBot));
+        assert_eq!(humans_only.check(&blitz(true, Some("GM"), 1500), 0), None);
+
+        let bots_only = ChallengePolicy {
+            allow_humans: false,
+            ..Default::default()
+        };
+        assert_eq!(bots_only.check(&blitz(true, None, 1500), 0), Some(DeclineReason::OnlyBot));
+        assert_eq!(bots_only.check(&blitz(true, Some("BOT"), 1500), 0), None);
+    }
+
+    #[test]
+    fn declines_the_blocklist() {
+        let policy = ChallengePolicy {
+            blocklist: vec!["someone".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(policy.check(&blitz(true, None, 1500), 0), Some(DeclineReason::Generic));
+    }
+
+    #[test]
+    fn declines_above_the_max_games() {
+        let policy = ChallengePolicy::default();
+        assert_eq!(policy.check(&blitz(true, None, 1500), 1), None);
+        assert_eq!(policy.check(&blitz(true, None, 1500), 2), Some(DeclineReason::Later));
+    }
+
+    #[test]
+    fn declines_variants() {
+        let standard_only = ChallengePolicy::default();
+        let chess960 = challenge(true, None, 1500, "chess960", serde_json::json!({ "type": "unlimited" }));
+        assert_eq!(standard_only.check(&chess960, 0), Some(DeclineReason::Standard));
+
+        let policy = ChallengePolicy {
+            variants: vec!["standard".to_string(), "fromPosition".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(policy.check(&chess960, 0), Some(DeclineReason::Variant));
+    }
+
+    #[test]
+    fn declines_time_controls_out_of_bounds() {
+        let policy = ChallengePolicy {
+            min_initial: 60,
+            max_initial: 600,
+            min_increment: 1,
+            max_increment: 10,
+            allow_unlimited: false,
+            ..Default::default()
+        };
+        let clock = |limit: u64, increment: u64| {
+            challenge(true, None, 1500, "standard", serde_json::json!({ "type": "clock", "limit": limit, "increment": increment }))
+        };
+        assert_eq!(policy.check(&clock(30, 2), 0), Some(DeclineReason::TooFast));
+        assert_eq!(policy.check(&clock(180, 0), 0), Some(DeclineReason::TooFast));
+        assert_eq!(policy.check(&clock(900, 2), 0), Some(DeclineReason::TooSlow));
+        assert_eq!(policy.check(&clock(180, 15), 0), Some(DeclineReason::TooSlow));
+        assert_eq!(policy.check(&clock(180, 2), 0), None);
+
+        let unlimited = challenge(true, None, 1500, "standard", serde_json::json!({ "type": "unlimited" }));
+        assert_eq!(policy.check(&unlimited, 0), Some(DeclineReason::TimeControl));
+        let correspondence = challenge(true, None, 1500, "standard", serde_json::json!({ "type": "correspondence" }));
+        assert_eq!(policy.check(&correspondence, 0), Some(DeclineReason::TimeControl));
+    }
+
+    #[test]
+    fn loads_missing_fields_as_defaults() {
+        let path = std::env::temp_dir().join(format!("challenge_policy_{}.json", std::process::id()));
+        std::fs::write(&path, "{ \"max_games\": 5, \"allow_bots\": false }").unwrap();
+        let policy = ChallengePolicy::load(path.to_str().unwrap());
+        let _ = std::fs::remove_file(&path);
+
+        let policy = policy.unwrap();
+        assert_eq!(policy.max_games, 5);
+        assert!(!policy.allow_bots);
+        assert_eq!(policy.variants, vec!["standard".to_string()]);
+        assert!(policy.allow_humans && policy.allow_rated && policy.allow_casual && !policy.allow_correspondence);
+        assert_eq!(policy.max_initial, u64::MAX);
+        assert!(ChallengePolicy::load("no_such_policy.json").is_err());
+    }
+}
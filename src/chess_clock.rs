@@ -0,0 +1,316 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::{base_types::Color, player::Clock};
+
+/**
+ * Time added to the clock for every move. All times are in milliseconds.
+ */
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TimeBonus {
+    None,
+    Increment(u64), // Fischer: added after every move
+    Delay(u64), // Simple (US) delay: the clock only starts running after the delay
+    Bronstein(u64), // The time used is given back after the move, at most the delay
+}
+
+/**
+ * One period of a time control, e.g. 40 moves in 90 minutes.
+ */
+#[derive(Copy, Clone, Debug)]
+pub struct Period {
+    pub moves: Option<u32>, // None for the rest of the game
+    pub time: u64, // Milliseconds
+    pub bonus: TimeBonus,
+}
+
+/**
+ * Periods of a time control. When the moves of the last period are played it starts again.
+ */
+#[derive(Clone, Debug)]
+pub struct TimeControl {
+    pub periods: Vec<Period>,
+}
+
+impl TimeControl {
+    /**
+     * Parses periods separated by commas. A period is `[moves/]minutes[+increment|d<delay>|b<delay>]`
+     * with the bonus in seconds, e.g. "5+3", "15d5", "40/90+30" or "40/90,30+30".
+     * Only the last period can be for the rest of the game.
+     */
+    pub fn parse(text: &str) -> Result<TimeControl, String> {
+        let mut periods = Vec::new();
+        for part in text.split(',') {
+            periods.push(parse_period(part).ok_or(format!("Invalid time control period {}", part))?);
+        }
+        if periods[..periods.len() - 1].iter().any(|period| period.moves.is_none()) {
+            return Err(format!("Only the last period of {} can be for the rest of the game", text));
+        }
+        Ok(TimeControl { periods })
+    }
+
+    /**
+     * The period a side is in after making `moves` moves, and the moves played in that period.
+     */
+    fn period(&self, moves: u32) -> (&Period, u32) {
+        let mut moves = moves;
+        let mut i = 0;
+        while let Some(period_moves) = self.periods[i].moves {
+            if moves < period_moves {
+                break;
+            }
+            moves -= period_moves;
+            i = (i + 1).min(self.periods.len() - 1);
+        }
+        (&self.periods[i], moves)
+    }
+}
+
+fn parse_period(text: &str) -> Option<Period> {
+    let (moves, rest) = match text.trim().split_once('/') {
+        Some((moves, rest)) => (Some(moves.parse::<u32>().ok().filter(|moves| *moves > 0)?), rest),
+        None => (None, text.trim()),
+    };
+    let split = rest.find(['+', 'd', 'b']).unwrap_or(rest.len());
+    let (minutes, bonus) = rest.split_at(split);
+    let millis = |value: &str, unit: f64| value.parse::<f64>().ok().filter(|value| *value >= 0.0).map(|value| (value * unit) as u64);
+    let time = millis(minutes, 60000.0).filter(|time| *time > 0)?;
+    let bonus = match bonus.chars().next() {
+        None => TimeBonus::None,
+        Some('+') => TimeBonus::Increment(millis(&bonus[1..], 1000.0)?),
+        Some('d') => TimeBonus::Delay(millis(&bonus[1..], 1000.0)?),
+        Some(_) => TimeBonus::Bronstein(millis(&bonus[1..], 1000.0)?),
+    };
+    Some(Period { moves, time, bonus })
+}
+
+impl fmt::Display for TimeControl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, period) in self.periods.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            if let Some(moves) = period.moves {
+                write!(f, "{}/", moves)?;
+            }
+            write!(f, "{}", period.time as f64 / 60000.0)?;
+            match period.bonus {
+                TimeBonus::None => {}
+                TimeBonus::Increment(time) => write!(f, "+{}", time as f64 / 1000.0)?,
+                TimeBonus::Delay(time) => write!(f, "d{}", time as f64 / 1000.0)?,
+                TimeBonus::Bronstein(time) => write!(f, "b{}", time as f64 / 1000.0)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Side {
+    time_left: u64, // Milliseconds, without the time of a running move
+    moves: u32,
+}
+
+/**
+ * Chess clock of a game. The side to move starts its clock with `start` and presses it with `press`
+ * after the move. A side that ran out of time has flagged, which can also happen while it is thinking.
+ */
+pub struct ChessClock {
+    pub control: TimeControl,
+    sides: [Side; 2],
+    running: Option<(Color, Instant)>, // Side that is thinking and since when
+}
+
+impl ChessClock {
+    pub fn new(control: TimeControl) -> ChessClock {
+        let side = Side {
+            time_left: control.periods[0].time,
+            moves: 0,
+        };
+        ChessClock {
+            control,
+            sides: [side; 2],
+            running: None,
+        }
+    }
+
+    fn side(&self, color: Color) -> &Side {
+        &self.sides[color as usize]
+    }
+
+    pub fn start(&mut self, color: Color) {
+        self.running = Some((color, Instant::now()));
+    }
+
+    /**
+     * Milliseconds the side has been thinking on its current move, 0 if its clock is not running.
+     */
+    fn thinking_time(&self, color: Color) -> u64 {
+        match self.running {
+            Some((running, since)) if running == color => since.elapsed().as_millis() as u64,
+            _ => 0,
+        }
+    }
+
+    /**
+     * Milliseconds the side may still think on its current move before it flags.
+     */
+    fn allowance(&self, color: Color) -> u64 {
+        let side = self.side(color);
+        match self.control.period(side.moves).0.bonus {
+            TimeBonus::Delay(delay) => side.time_left + delay,
+            _ => side.time_left,
+        }
+    }
+
+    /**
+     * Milliseconds left on the clock of the side, counting down while it thinks.
+     */
+    pub fn time_left(&self, color: Color) -> u64 {
+        let allowance = self.allowance(color).saturating_sub(self.thinking_time(color));
+        allowance.min(self.side(color).time_left)
+    }
+
+    /**
+     * Time until the side to move flags, if its clock is running.
+     */
+    pub fn time_to_flag(&self) -> Option<Duration> {
+        let (color, _) = self.running?;
+        Some(Duration::from_millis(self.allowance(color).saturating_sub(self.thinking_time(color))))
+    }
+
+    /**
+     * Stops the clock of the side that just moved and adds the bonus of its period.
+     * Returns false if the side flagged before it moved.
+     */
+    pub fn press(&mut self) -> bool {
+        let (color, since) = match self.running.take() {
+            Some(running) => running,
+            None => return true,
+        };
+        let thinking_time = since.elapsed().as_millis() as u64;
+        let (period, moves_in_period) = self.control.period(self.side(color).moves);
+        let (bonus, used) = match period.bonus {
+            TimeBonus::None => (0, thinking_time),
+            TimeBonus::Increment(increment) => (increment, thinking_time),
+            TimeBonus::Delay(delay) => (0, thinking_time.saturating_sub(delay)),
+            TimeBonus::Bronstein(delay) => (delay.min(thinking_time), thinking_time),
+        };
+        let period_done = period.moves == Some(moves_in_period + 1);
+
+        let side = &mut self.sides[color as usize];
+        if used > side.time_left {
+            side.time_left = 0;
+            return false;
+        }
+        side.time_left = side.time_left - used + bonus;
+        side.moves += 1;
+        if period_done {
+            let moves = side.moves;
+            self.sides[color as usize].time_left += self.control.period(moves).0.time;
+        }
+        true
+    }
+
+    /**
+     * The clock of the side as players see it.
+     */
+    pub fn clock(&self, color: Color) -> Clock {
+        let side = self.side(color);
+        let (period, moves_in_period) = self.control.period(side.moves);
+        Clock {
+            time_left: self.time_left(color),
+            // A delay saves about the same time per move as an increment
+            increment: match period.bonus {
+                TimeBonus::None => 0,
+                TimeBonus::Increment(time) | TimeBonus::Delay(time) | TimeBonus::Bronstein(time) => time,
+            },
+            moves_to_go: period.moves.map(|moves| moves - moves_in_period),
+        }
+    }
+}
+
+impl fmt::Display for ChessClock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "White {} | Black {}",
+            format_time(self.time_left(Color::White)),
+            format_time(self.time_left(Color::Black))
+        )
+    }
+}
+
+/**
+ * Time like on a chess clock: h:mm:ss, m:ss, and with tenths below 20 seconds.
+ */
+pub fn format_time(millis: u64) -> String {
+    let seconds = millis / 1000;
+    if seconds >= 3600 {
+        format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+    } else if seconds >= 20 {
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    } else {
+        format!("0:{:02}.{}", seconds, millis / 100 % 10)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_time_controls() {
+        let control = TimeControl::parse("40/90,30+30").unwrap();
+        assert_eq!(control.periods.len(), 2);
+        assert_eq!(control.periods[0].moves, Some(40));
+        assert_eq!(control.periods[0].time, 90 * 60000);
+        assert_eq!(control.periods[0].bonus, TimeBonus::None);
+        assert_eq!(control.periods[1].moves, None);
+        assert_eq!(control.periods[1].time, 30 * 60000);
+        assert_eq!(control.periods[1].bonus, TimeBonus::Increment(30000));
+        assert_eq!(control.to_string(), "40/90,30+30");
+
+        let delay = TimeControl::parse("15d5").unwrap();
+        assert_eq!(delay.periods[0].time, 15 * 60000);
+        assert_eq!(delay.periods[0].bonus, TimeBonus::Delay(5000));
+
+        let bronstein = TimeControl::parse("5b3").unwrap();
+        assert_eq!(bronstein.periods[0].time, 5 * 60000);
+        assert_eq!(bronstein.periods[0].bonus, TimeBonus::Bronstein(3000));
+
+        assert!(TimeControl::parse("90,40/30").is_err());
+        assert!(TimeControl::parse("0+3").is_err());
+        assert!(TimeControl::parse("5+x").is_err());
+    }
+
+    #[test]
+    fn adds_the_next_period_after_its_moves() {
+        let mut clock = ChessClock::new(TimeControl::parse("2/1,1+2").unwrap());
+        for moves_to_go in [2, 1] {
+            assert_eq!(clock.clock(Color::White).moves_to_go, Some(moves_to_go));
+            clock.start(Color::White);
+            assert!(clock.press());
+        }
+        // The second period is for the rest of the game and has an increment
+        let white = clock.clock(Color::White);
+        assert_eq!(white.moves_to_go, None);
+        assert_eq!(white.increment, 2000);
+        assert!(white.time_left > 119_000 && white.time_left <= 120_000);
+        assert_eq!(clock.time_left(Color::Black), 60_000);
+
+        clock.start(Color::White);
+        assert!(clock.press());
+        assert!(clock.time_left(Color::White) > 121_000);
+    }
+
+    #[test]
+    fn flags_after_the_time_is_used() {
+        let mut clock = ChessClock::new(TimeControl::parse("0.0001").unwrap());
+        clock.start(Color::White);
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(clock.time_to_flag(), Some(Duration::ZERO));
+        assert!(!clock.press());
+        assert_eq!(clock.time_left(Color::White), 0);
+    }
+}
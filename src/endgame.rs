@@ -0,0 +1,643 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use crate::{
+    base_types::{Color, PieceType, Position},
+    game::Game,
+    square_table::{ST_PUSH_TO_CORNER, ST_PUSH_TO_EDGE},
+};
+
+// Score of an endgame that is won, but not yet a mate. Above any material difference
+pub const KNOWN_WIN: i32 = 10000;
+// Endgames with more pieces have no evaluator
+const MAX_ENDGAME_PIECES: usize = 4;
+
+/**
+ * Evaluates a position of an endgame from the view of the strong side, the first side of the signature.
+ */
+type EndgameEval = fn(&Game, Color) -> i32;
+
+/**
+ * Endgames with their own evaluation, by material signature with the strong side first.
+ */
+static ENDGAMES: [(&str, EndgameEval); 5] = [
+    ("KPvK", evaluate_kpk),
+    ("KBNvK", evaluate_kbnk),
+    ("KQvK", evaluate_kxk),
+    ("KRvK", evaluate_kxk),
+    ("KRvKP", evaluate_krkp),
+];
+
+/**
+ * Number of pieces of each type (pawn to king) of both colors. Used to look up the evaluator of an endgame.
+ */
+#[derive(Clone, Copy)]
+pub struct Material {
+    pub white: [u8; 6],
+    pub black: [u8; 6],
+}
+
+impl Material {
+    pub fn new(game: &Game) -> Material {
+        let mut material = Material { white: [0; 6], black: [0; 6] };
+        for piece in game.board.pieces.iter().flatten() {
+            material.side_mut(piece.color)[piece.piece_type as usize] += 1;
+        }
+        material
+    }
+
+    /**
+     * Material of a signature like KRPvKR, white first. Each side starts with its king.
+     */
+    pub fn from_signature(signature: &str) -> Option<Material> {
+        let (white, black) = signature.split_once('v')?;
+        let counts = |side: &str| -> Option<[u8; 6]> {
+            let mut counts = [0; 6];
+            for c in side.chars() {
+                counts["PNBRQK".find(c)?] += 1;
+            }
+            (side.starts_with('K') && counts[5] == 1).then_some(counts)
+        };
+        Some(Material {
+            white: counts(white)?,
+            black: counts(black)?,
+        })
+    }
+
+    pub fn side(&self, color: Color) -> &[u8; 6] {
+        match color {
+            Color::White => &self.white,
+            Color::Black => &self.black,
+        }
+    }
+
+    fn side_mut(&mut self, color: Color) -> &mut [u8; 6] {
+        match color {
+            Color::White => &mut self.white,
+            Color::Black => &mut self.black,
+        }
+    }
+
+    pub fn piece_count(&self) -> usize {
+        self.white.iter().chain(self.black.iter()).map(|count| *count as usize).sum()
+    }
+
+    /**
+     * Unique key of the material, four bits for every piece count.
+     */
+    pub fn key(&self) -> u64 {
+        self.white.iter().chain(self.black.iter()).enumerate().fold(0, |key, (i, count)| key | (*count as u64) << (4 * i))
+    }
+
+    /**
+     * The same material with the colors switched.
+     */
+    pub fn flipped(&self) -> Material {
+        Material {
+            white: self.black,
+            black: self.white,
+        }
+    }
+
+    /**
+     * Whether the side has enough material to mate: a pawn, a rook, a queen or two minor pieces.
+     */
+    pub fn can_mate(&self, color: Color) -> bool {
+        let side = self.side(color);
+        side[PieceType::Pawn as usize] + side[PieceType::Rook as usize] + side[PieceType::Queen as usize] > 0
+            || side[PieceType::Knight as usize] + side[PieceType::Bishop as usize] > 1
+    }
+
+    /**
+     * Whether the side could mate at all if the opponent helps, as needed when the opponent runs out of time.
+     * Only a lone minor piece against a bare king can never mate, otherwise the opponent's own pawns
+     * or pieces can block its king in. The squares of bishops are not known here, see `can_helpmate`.
+     */
+    pub fn can_helpmate(&self, color: Color) -> bool {
+        if self.can_mate(color) {
+            return true;
+        }
+        let side = self.side(color);
+        let opponent = self.side(color.opposite());
+        let opponent_has_material = opponent.iter().enumerate().any(|(piece, count)| piece != PieceType::King as usize && *count > 0);
+        side[PieceType::Knight as usize] + side[PieceType::Bishop as usize] > 0 && opponent_has_material
+    }
+
+    /**
+     * No queens, or a side with a queen has at most one minor piece besides it. The king then
+     * belongs in the center instead of behind its pawns.
+     */
+    pub fn is_endgame(&self) -> bool {
+        [&self.white, &self.black].iter().all(|side| {
+            side[PieceType::Queen as usize] == 0
+                || (side[PieceType::Rook as usize] == 0 && side[PieceType::Knight as usize] + side[PieceType::Bishop as usize] <= 1)
+        })
+    }
+
+    /**
+     * Neither side can mate.
+     */
+    pub fn is_insufficient(&self) -> bool {
+        !self.can_mate(Color::White) && !self.can_mate(Color::Black)
+    }
+
+    /**
+     * Pieces other than pawns and the king.
+     */
+    fn has_pieces(&self, color: Color) -> bool {
+        self.side(color)[PieceType::Knight as usize..PieceType::King as usize].iter().any(|count| *count > 0)
+    }
+}
+
+/**
+ * Whether the side could mate at all if the opponent helps. Besides the material, with only kings and
+ * bishops left that all stand on squares of the same color, no side can ever mate.
+ */
+pub fn can_helpmate(game: &Game, material: &Material, color: Color) -> bool {
+    if !material.can_helpmate(color) {
+        return false;
+    }
+    let only_bishops = [Color::White, Color::Black].iter().all(|side| {
+        let side = material.side(*side);
+        side[PieceType::Pawn as usize] + side[PieceType::Knight as usize] + side[PieceType::Rook as usize] + side[PieceType::Queen as usize] == 0
+    });
+    if !only_bishops {
+        return true;
+    }
+    let mut bishops = find_pieces(game, Color::White, PieceType::Bishop);
+    bishops.extend(find_pieces(game, Color::Black, PieceType::Bishop));
+    bishops.iter().any(|bishop| is_dark(*bishop) != is_dark(bishops[0]))
+}
+
+/**
+ * Score of the position from the view of the side to move if it is a known endgame.
+ */
+pub fn evaluate(game: &Game, material: &Material) -> Option<i32> {
+    if material.piece_count() > MAX_ENDGAME_PIECES {
+        return None;
+    }
+    let (evaluator, strong) = endgames().get(&material.key())?;
+    let score = evaluator(game, *strong);
+    Some(if game.turn == *strong { score } else { -score })
+}
+
+/**
+ * Scales down the score of endgames that are hard or impossible to win despite more material:
+ * opposite colored bishops, and rook pawns that can't promote because the defending king holds the corner.
+ */
+pub fn scale(game: &Game, material: &Material, score: i32) -> i32 {
+    for strong in [Color::White, Color::Black] {
+        if is_rook_pawn_draw(game, material, strong) {
+            return 0;
+        }
+    }
+    if is_opposite_bishops(game, material) {
+        return score / 4;
+    }
+    score
+}
+
+fn endgames() -> &'static HashMap<u64, (EndgameEval, Color)> {
+    static ENDGAME_MAP: OnceLock<HashMap<u64, (EndgameEval, Color)>> = OnceLock::new();
+    ENDGAME_MAP.get_or_init(|| {
+        let mut map = HashMap::new();
+        for (signature, evaluator) in ENDGAMES.iter() {
+            let material = Material::from_signature(signature).expect("Invalid endgame signature");
+            map.insert(material.key(), (*evaluator, Color::White));
+            map.insert(material.flipped().key(), (*evaluator, Color::Black));
+        }
+        map
+    })
+}
+
+/**
+ * Only bishops of different square colors and pawns.
+ */
+fn is_opposite_bishops(game: &Game, material: &Material) -> bool {
+    let only_bishop = |color: Color| {
+        let side = material.side(color);
+        side[PieceType::Bishop as usize] == 1
+            && side[PieceType::Knight as usize] == 0
+            && side[PieceType::Rook as usize] == 0
+            && side[PieceType::Queen as usize] == 0
+    };
+    if !only_bishop(Color::White) || !only_bishop(Color::Black) {
+        return false;
+    }
+    let white_bishop = find_pieces(game, Color::White, PieceType::Bishop)[0];
+    let black_bishop = find_pieces(game, Color::Black, PieceType::Bishop)[0];
+    is_dark(white_bishop) != is_dark(black_bishop)
+}
+
+/**
+ * Pawns that are all on the same rook file, with at most a bishop that does not cover the promotion square,
+ * are a draw when the defending king is next to the promotion square in front of them.
+ */
+fn is_rook_pawn_draw(game: &Game, material: &Material, strong: Color) -> bool {
+    let weak = strong.opposite();
+    let side = material.side(strong);
+    let bishops = side[PieceType::Bishop as usize];
+    if side[PieceType::Pawn as usize] == 0
+        || bishops > 1
+        || side[PieceType::Knight as usize] + side[PieceType::Rook as usize] + side[PieceType::Queen as usize] > 0
+        || material.has_pieces(weak)
+    {
+        return false;
+    }
+
+    let pawns = find_pieces(game, strong, PieceType::Pawn);
+    let file = pawns[0].get_col();
+    if (file != 0 && file != 7) || pawns.iter().any(|pawn| pawn.get_col() != file) {
+        return false;
+    }
+    let promotion_row = if let Color::White = strong { 7 } else { 0 };
+    let promotion = Position::from((file, promotion_row));
+    if bishops == 1 && is_dark(find_pieces(game, strong, PieceType::Bishop)[0]) == is_dark(promotion) {
+        return false;
+    }
+
+    // The defending king has to be in front of the most advanced pawn
+    let weak_king = king_position(game, weak);
+    let ahead = |pawn: &Position| relative_row(weak_king, strong) > relative_row(*pawn, strong);
+    distance(weak_king, promotion) <= 1 && pawns.iter().all(ahead)
+}
+
+fn evaluate_kpk(game: &Game, strong: Color) -> i32 {
+    let pawn = find_pieces(game, strong, PieceType::Pawn)[0];
+    let strong_king = king_position(game, strong);
+    let weak_king = king_position(game, strong.opposite());
+    if !kpk_probe(strong, strong_king, pawn, weak_king, game.turn == strong) {
+        return 0;
+    }
+    KNOWN_WIN + PieceType::Pawn.get_value() + 10 * relative_row(pawn, strong) as i32
+}
+
+/**
+ * Mate with bishop and knight only works in a corner of the bishop's color.
+ */
+fn evaluate_kbnk(game: &Game, strong: Color) -> i32 {
+    let strong_king = king_position(game, strong);
+    let weak_king = king_position(game, strong.opposite());
+    let bishop = find_pieces(game, strong, PieceType::Bishop)[0];
+    // The table drives to a1 and h8, mirrored for a bishop on the light squares
+    let corner_square = if is_dark(bishop) { weak_king } else { Position::from((7 - weak_king.get_col(), weak_king.get_row())) };
+    KNOWN_WIN + push_close(strong_king, weak_king) + ST_PUSH_TO_CORNER[corner_square.index()]
+}
+
+/**
+ * Queen or rook against the king: the defending king is driven to the edge.
+ */
+fn evaluate_kxk(game: &Game, strong: Color) -> i32 {
+    let strong_king = king_position(game, strong);
+    let weak_king = king_position(game, strong.opposite());
+    let material: i32 = game
+        .board
+        .pieces
+        .iter()
+        .flatten()
+        .filter(|piece| piece.color == strong && piece.piece_type != PieceType::King)
+        .map(|piece| piece.piece_type.get_value())
+        .sum();
+    KNOWN_WIN + material + push_close(strong_king, weak_king) + ST_PUSH_TO_EDGE[weak_king.index()]
+}
+
+/**
+ * Rook against pawn. Won if our king stops the pawn or the defending king is too far away,
+ * drawish if the pawn is far advanced with its king next to it.
+ */
+fn evaluate_krkp(game: &Game, strong: Color) -> i32 {
+    let weak = strong.opposite();
+    // Rows from the view of the strong side, the pawn moves to row 0
+    let flip = |position: Position| {
+        if let Color::White = strong { position } else { Position::from((position.get_col(), 7 - position.get_row())) }
+    };
+    let strong_king = flip(king_position(game, strong));
+    let weak_king = flip(king_position(game, weak));
+    let rook = flip(find_pieces(game, strong, PieceType::Rook)[0]);
+    let pawn = flip(find_pieces(game, weak, PieceType::Pawn)[0]);
+    let promotion = Position::from((pawn.get_col(), 0));
+    let in_front = Position::from((pawn.get_col(), pawn.get_row() - 1));
+    let rook_value = PieceType::Rook.get_value();
+
+    let king_in_front = strong_king.get_col() == pawn.get_col() && strong_king.get_row() < pawn.get_row();
+    let weak_king_far = distance(weak_king, pawn) >= 3 + (game.turn == weak) as i32 && distance(weak_king, rook) >= 3;
+    if king_in_front || weak_king_far {
+        rook_value - distance(strong_king, pawn)
+    } else if weak_king.get_row() <= 2
+        && distance(weak_king, pawn) == 1
+        && strong_king.get_row() >= 3
+        && distance(strong_king, pawn) > 2 + (game.turn == strong) as i32
+    {
+        30 - 3 * distance(strong_king, pawn)
+    } else {
+        80 - 3 * (distance(strong_king, in_front) - distance(weak_king, in_front) - distance(pawn, promotion))
+    }
+}
+
+/**
+ * Bonus for the attacking king being close to the defending king.
+ */
+fn push_close(a: Position, b: Position) -> i32 {
+    140 - 20 * distance(a, b)
+}
+
+fn distance(a: Position, b: Position) -> i32 {
+    (a.get_row() as i32 - b.get_row() as i32).abs().max((a.get_col() as i32 - b.get_col() as i32).abs())
+}
+
+fn is_dark(position: Position) -> bool {
+    (position.get_row() + position.get_col()) & 1 == 0
+}
+
+/**
+ * Row counted from the side of the color, 0 is its back rank.
+ */
+fn relative_row(position: Position, color: Color) -> u8 {
+    if let Color::White = color { position.get_row() } else { 7 - position.get_row() }
+}
+
+fn king_position(game: &Game, color: Color) -> Position {
+    if let Color::White = color { game.white_king_position } else { game.black_king_position }
+}
+
+fn find_pieces(game: &Game, color: Color, piece_type: PieceType) -> Vec<Position> {
+    game.board
+        .pieces
+        .iter()
+        .flatten()
+        .filter(|piece| piece.color == color && piece.piece_type == piece_type)
+        .map(|piece| piece.position)
+        .collect()
+}
+
+/**
+ * KPK bitbase: one bit for every position of king and pawn against king, set if the pawn side wins.
+ * It is generated by retrograde analysis on first use, which takes a moment.
+ * Positions are stored with white as the pawn side and the pawn on the a to d file.
+ */
+const KPK_SIZE: usize = 2 * 24 * 64 * 64;
+const KPK_INVALID: u8 = 0;
+const KPK_UNKNOWN: u8 = 1;
+const KPK_DRAW: u8 = 2;
+const KPK_WIN: u8 = 4;
+
+/**
+ * Whether the pawn side wins. `strong_to_move` is true if the pawn side is to move.
+ */
+pub fn kpk_probe(strong: Color, strong_king: Position, pawn: Position, weak_king: Position, strong_to_move: bool) -> bool {
+    let mut squares = [strong_king.index(), pawn.index(), weak_king.index()];
+    if let Color::Black = strong {
+        squares.iter_mut().for_each(|square| *square ^= 56);
+    }
+    if squares[1] % 8 > 3 {
+        squares.iter_mut().for_each(|square| *square ^= 7);
+    }
+    let index = kpk_index(!strong_to_move, squares[2], squares[0], squares[1]);
+    kpk_bitbase()[index / 64] & (1 << (index % 64)) != 0
+}
+
+fn kpk_bitbase() -> &'static Vec<u64> {
+    static KPK_BITBASE: OnceLock<Vec<u64>> = OnceLock::new();
+    KPK_BITBASE.get_or_init(generate_kpk)
+}
+
+fn kpk_index(black_to_move: bool, black_king: usize, white_king: usize, pawn: usize) -> usize {
+    white_king | black_king << 6 | (black_to_move as usize) << 12 | (pawn % 8) << 13 | (6 - pawn / 8) << 15
+}
+
+fn generate_kpk() -> Vec<u64> {
+    let mut results: Vec<u8> = (0..KPK_SIZE).map(kpk_initial).collect();
+    // Positions are decided once one move (for white) or all moves (for black) lead to a decided win,
+    // until nothing changes anymore. The rest are draws
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for index in 0..KPK_SIZE {
+            if results[index] == KPK_UNKNOWN {
+                results[index] = kpk_classify(&results, index);
+                changed |= results[index] != KPK_UNKNOWN;
+            }
+        }
+    }
+
+    let mut bits = vec![0u64; KPK_SIZE.div_ceil(64)];
+    for (index, result) in results.iter().enumerate() {
+        if *result == KPK_WIN {
+            bits[index / 64] |= 1 << (index % 64);
+        }
+    }
+    bits
+}
+
+/**
+ * Squares of the position: black to move, white king, black king and pawn.
+ */
+fn kpk_decode(index: usize) -> (bool, usize, usize, usize) {
+    let pawn = (6 - ((index >> 15) & 0x7)) * 8 + ((index >> 13) & 0x3);
+    ((index >> 12) & 1 == 1, index & 0x3F, (index >> 6) & 0x3F, pawn)
+}
+
+fn kpk_initial(index: usize) -> u8 {
+    let (black_to_move, white_king, black_king, pawn) = kpk_decode(index);
+    let promotion = pawn + 8;
+    let king_distance = |a: usize, b: usize| distance(Position::new(a as u8), Position::new(b as u8));
+
+    if king_distance(white_king, black_king) <= 1
+        || white_king == pawn
+        || black_king == pawn
+        || (!black_to_move && pawn_attacks(pawn) & (1 << black_king) != 0)
+    {
+        KPK_INVALID
+    } else if !black_to_move
+        && pawn / 8 == 6
+        && white_king != promotion
+        && (king_distance(black_king, promotion) > 1 || king_distance(white_king, promotion) == 1)
+    {
+        // The pawn promotes and the queen can't be taken
+        KPK_WIN
+    } else if black_to_move
+        && (king_attacks(black_king) & !(king_attacks(white_king) | pawn_attacks(pawn)) == 0
+            || king_attacks(black_king) & !king_attacks(white_king) & (1 << pawn) != 0)
+    {
+        // Stalemate or the pawn gets taken
+        KPK_DRAW
+    } else {
+        KPK_UNKNOWN
+    }
+}
+
+fn kpk_classify(results: &[u8], index: usize) -> u8 {
+    let (black_to_move, white_king, black_king, pawn) = kpk_decode(index);
+    let (good, bad) = if black_to_move { (KPK_DRAW, KPK_WIN) } else { (KPK_WIN, KPK_DRAW) };
+
+    let mut reachable = KPK_INVALID;
+    let mut attacks = king_attacks(if black_to_move { black_king } else { white_king });
+    while attacks != 0 {
+        let to = attacks.trailing_zeros() as usize;
+        attacks &= attacks - 1;
+        reachable |= if black_to_move {
+            results[kpk_index(false, to, white_king, pawn)]
+        } else {
+            results[kpk_index(true, black_king, to, pawn)]
+        };
+    }
+    if !black_to_move {
+        // A blocked push leads to an invalid position
+        if pawn / 8 < 6 {
+            reachable |= results[kpk_index(true, black_king, white_king, pawn + 8)];
+        }
+        if pawn / 8 == 1 && pawn + 8 != white_king && pawn + 8 != black_king {
+            reachable |= results[kpk_index(true, black_king, white_king, pawn + 16)];
+        }
+    }
+
+    if reachable & good != 0 {
+        good
+    } else if reachable & KPK_UNKNOWN != 0 {
+        KPK_UNKNOWN
+    } else {
+        bad
+    }
+}
+
+fn king_attacks(square: usize) -> u64 {
+    let (row, col) = ((square / 8) as i32, (square % 8) as i32);
+    let mut attacks = 0;
+    for (d_row, d_col) in [(-1, -1), (-1, 0), (-1, 1), (0, -1), (0, 1), (1, -1), (1, 0), (1, 1)] {
+        let position = Position::from((col + d_col, row + d_row));
+        if position.is_valid() {
+            attacks |= position.bitboard();
+        }
+    }
+    attacks
+}
+
+/**
+ * Squares attacked by a white pawn.
+ */
+fn pawn_attacks(square: usize) -> u64 {
+    let (row, col) = ((square / 8) as i32, (square % 8) as i32);
+    [-1, 1]
+        .iter()
+        .map(|d_col| Position::from((col + d_col, row + 1)))
+        .filter(|position| position.is_valid())
+        .fold(0, |attacks, position| attacks | position.bitboard())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn material(signature: &str) -> Material {
+        Material::from_signature(signature).unwrap()
+    }
+
+    #[test]
+    fn lone_minor_piece_can_only_helpmate_against_material() {
+        assert!(!material("KNvK").can_helpmate(Color::White));
+        assert!(!material("KBvK").can_helpmate(Color::White));
+        assert!(material("KNvKP").can_helpmate(Color::White));
+        assert!(material("KBvKN").can_helpmate(Color::White));
+        assert!(!material("KNvKP").can_mate(Color::White));
+        assert!(material("KPvK").can_helpmate(Color::White));
+        assert!(!material("KvKQ").can_helpmate(Color::White));
+    }
+
+    fn can_helpmate_fen(fen: &str, color: Color) -> bool {
+        let game = Game::from_fen(fen);
+        can_helpmate(&game, &Material::new(&game), color)
+    }
+
+    #[test]
+    fn bishops_on_the_same_color_can_never_mate() {
+        // c1 and d2 are dark, e2 is light
+        let same_color = "4k3/8/8/8/8/8/3b4/2B1K3 w - - 0 1";
+        assert!(material("KBvKB").can_helpmate(Color::White));
+        assert!(!can_helpmate_fen(same_color, Color::White));
+        assert!(!can_helpmate_fen(same_color, Color::Black));
+        let opposite_colors = "4k3/8/8/8/8/8/4b3/2B1K3 w - - 0 1";
+        assert!(can_helpmate_fen(opposite_colors, Color::White));
+        assert!(can_helpmate_fen(opposite_colors, Color::Black));
+        assert!(!can_helpmate_fen("4k3/8/8/8/8/8/3B4/2B1K3 w - - 0 1", Color::White));
+        // Any other piece can block a king in
+        assert!(can_helpmate_fen("4k3/8/8/8/8/8/3n4/2B1K3 w - - 0 1", Color::White));
+        assert!(can_helpmate_fen("4k3/4p3/8/8/8/8/3b4/2B1K3 w - - 0 1", Color::White));
+    }
+
+    #[test]
+    fn knight_alone_can_never_mate() {
+        let fen = "4k3/8/8/8/8/8/8/2N1K3 w - - 0 1";
+        assert!(!can_helpmate_fen(fen, Color::White));
+        assert!(!can_helpmate_fen(fen, Color::Black));
+    }
+
+    fn square(name: &str) -> Position {
+        Position::from(name.to_string())
+    }
+
+    fn evaluate_fen(fen: &str) -> Option<i32> {
+        let game = Game::from_fen(fen);
+        evaluate(&game, &Material::new(&game))
+    }
+
+    #[test]
+    fn kpk_wins_with_the_king_in_front_or_the_pawn_out_of_reach() {
+        // King on the sixth rank in front of the pawn wins with either side to move
+        assert!(kpk_probe(Color::White, square("e6"), square("e5"), square("e8"), true));
+        assert!(kpk_probe(Color::White, square("e6"), square("e5"), square("e8"), false));
+        // The defending king is outside the square of the pawn
+        assert!(kpk_probe(Color::White, square("h1"), square("a5"), square("h8"), true));
+        // Black pawn, mirrored
+        assert!(kpk_probe(Color::Black, square("e3"), square("e4"), square("e1"), false));
+    }
+
+    #[test]
+    fn kpk_draws_with_the_opposition_or_a_rook_pawn() {
+        // The side with the pawn to move loses the opposition, the defender to move gives it up
+        assert!(!kpk_probe(Color::White, square("d5"), square("d4"), square("d7"), true));
+        assert!(kpk_probe(Color::White, square("d5"), square("d4"), square("d7"), false));
+        // Rook pawn with the defending king in the corner in front of it
+        assert!(!kpk_probe(Color::White, square("b6"), square("a6"), square("a8"), true));
+        assert!(!kpk_probe(Color::White, square("b5"), square("a5"), square("b8"), true));
+        assert_eq!(evaluate_fen("k7/8/1K6/P7/8/8/8/8 w - - 0 1"), Some(0));
+        assert!(evaluate_fen("4k3/8/4K3/4P3/8/8/8/8 b - - 0 1").unwrap() < -KNOWN_WIN);
+    }
+
+    #[test]
+    fn kbnk_drives_the_king_to_the_corner_of_the_bishop() {
+        // Dark squared bishop, the same king distance to a1 and h1
+        let right_corner = evaluate_fen("8/8/8/8/8/2K5/8/k3B1N1 w - - 0 1").unwrap();
+        let wrong_corner = evaluate_fen("8/8/8/8/8/5K2/8/1N2B2k w - - 0 1").unwrap();
+        assert!(right_corner > wrong_corner);
+        assert!(wrong_corner > KNOWN_WIN / 2);
+        assert_eq!(evaluate_fen("8/8/8/8/8/2K5/8/k3B1N1 b - - 0 1"), Some(-right_corner));
+    }
+
+    #[test]
+    fn kxk_drives_the_king_to_the_edge() {
+        let edge = evaluate_fen("8/8/8/8/k7/8/2K4Q/8 w - - 0 1").unwrap();
+        let center = evaluate_fen("8/8/8/3k4/8/3K4/7Q/8 w - - 0 1").unwrap();
+        assert!(center > KNOWN_WIN);
+        assert!(edge > center);
+        let rook = evaluate_fen("8/8/8/8/k7/8/2K4R/8 w - - 0 1").unwrap();
+        assert!(rook > KNOWN_WIN && rook < edge);
+    }
+
+    #[test]
+    fn krkp_is_won_with_the_king_in_front_and_drawish_with_an_advanced_pawn() {
+        let king_in_front = evaluate_fen("8/8/8/4k3/3p4/8/8/3K3R w - - 0 1").unwrap();
+        assert!(king_in_front > PieceType::Rook.get_value() - 10);
+        let advanced_pawn = evaluate_fen("R6K/8/8/8/8/8/2kp4/8 w - - 0 1").unwrap();
+        assert!(advanced_pawn < 100);
+        // Black has the rook
+        assert!(evaluate_fen("3k3r/8/8/3P4/4K3/8/8/8 w - - 0 1").unwrap() < -(PieceType::Rook.get_value() - 10));
+    }
+
+    #[test]
+    fn endgame_needs_queens_off_or_little_material() {
+        assert!(!material("KQRRBBNNPPPPPPPPvKQRRBBNNPPPPPPPP").is_endgame());
+        assert!(material("KRRBBNNPPPPPPPPvKRRBBNNPPPPPPPP").is_endgame());
+        assert!(material("KQNPPPvKRPPP").is_endgame());
+        assert!(!material("KQRvKR").is_endgame());
+    }
+}
@@ -0,0 +1,154 @@
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// What the fake engine does when it has to search
+pub static CRASH: &str = "exit 1";
+pub static HANG: &str = ":"; // Never answers, not even after stop
+pub static MOVE: &str = "echo \"info depth 1 score cp 13 pv e2e4\"; echo \"bestmove e2e4\"";
+// Crashes on the first search only, the engine started after it moves
+pub static CRASH_ONCE: &str = "if [ -e \"$DIR/crashed\" ]; then echo \"bestmove e2e4\"; else touch \"$DIR/crashed\"; exit 1; fi";
+
+static ENGINE_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/**
+ * Local stand in for a UCI engine: a shell script that answers the handshake and runs a scripted command
+ * on go. Every start of the engine is logged, so restarts can be counted.
+ */
+pub struct FakeEngine {
+    pub path: String,
+    dir: PathBuf,
+}
+
+impl FakeEngine {
+    pub fn new(on_go: &str) -> FakeEngine {
+        let id = ENGINE_COUNT.fetch_add(1, Ordering::Relaxed);
+        let dir = std::env::temp_dir().join(format!("rust-chess-engine-{}-{}", std::process::id(), id));
+        std::fs::create_dir_all(&dir).expect("Cant create the engine dir");
+        let script = format!(
+            "#!/bin/sh\n\
+             DIR=\"{}\"\n\
+             echo start >> \"$DIR/starts\"\n\
+             while read -r line; do\n\
+             \tcase \"$line\" in\n\
+             \t\tuci) echo \"id name Fake\"; echo \"uciok\" ;;\n\
+             \t\tisready) echo \"readyok\" ;;\n\
+             \t\tgo*) {} ;;\n\
+             \t\tquit) exit 0 ;;\n\
+             \tesac\n\
+             done\n",
+            dir.display(),
+            on_go
+        );
+        let path = dir.join("engine.sh");
+        std::fs::write(&path, script).expect("Cant write the engine");
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).expect("Cant make the engine executable");
+        FakeEngine {
+            path: path.display().to_string(),
+            dir,
+        }
+    }
+
+    pub fn starts(&self) -> usize {
+        std::fs::read_to_string(self.dir.join("starts")).map_or(0, |starts| starts.lines().count())
+    }
+
+    pub fn file(&self, name: &str) -> String {
+        self.dir.join(name).display().to_string()
+    }
+}
+
+impl Drop for FakeEngine {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        base_types::Color,
+        game::Game,
+        game_launcher::play_game,
+        match_runner::{EngineConfig, MatchRunner, MatchSettings},
+        player::{ExternalEnginePlayer, GameContext, Player, PlayerAction, RandomPlayer},
+        search::{Score, SearchSettings},
+        uci_engine::{GoLimit, UciEngine},
+        STARTING_POS_FEN,
+    };
+
+    #[test]
+    fn engine_answers_with_its_move_and_score() {
+        let fake = FakeEngine::new(MOVE);
+        let mut engine = UciEngine::start(&fake.path).unwrap();
+        assert_eq!(engine.name, "Fake");
+        let answer = engine.go(STARTING_POS_FEN, &[], GoLimit::MoveTime(10)).unwrap();
+        assert_eq!(answer.best_move, "e2e4");
+        assert_eq!(answer.score, Some(Score::Cp(13)));
+    }
+
+    #[test]
+    fn engine_that_exits_during_go_crashes() {
+        let fake = FakeEngine::new(CRASH);
+        let mut engine = UciEngine::start(&fake.path).unwrap();
+        let err = engine.go(STARTING_POS_FEN, &[], GoLimit::MoveTime(10)).unwrap_err();
+        assert!(err.contains("crashed"), "{}", err);
+        assert!(!engine.is_running());
+    }
+
+    #[test]
+    fn engine_that_does_not_move_times_out() {
+        let fake = FakeEngine::new(HANG);
+        let mut engine = UciEngine::start(&fake.path).unwrap();
+        let err = engine.go(STARTING_POS_FEN, &[], GoLimit::MoveTime(10)).unwrap_err();
+        assert!(err.contains("did not answer in time"), "{}", err);
+        assert!(engine.is_running());
+    }
+
+    #[tokio::test]
+    async fn player_restarts_a_crashed_engine() {
+        let fake = FakeEngine::new(CRASH_ONCE);
+        let mut player = ExternalEnginePlayer::new(&fake.path, GoLimit::MoveTime(10)).unwrap();
+        let game = Game::from_fen(STARTING_POS_FEN);
+        let context = GameContext::new(Color::White, STARTING_POS_FEN);
+        let decision = player.play(&game, &context).await.unwrap();
+        assert!(matches!(decision.action, PlayerAction::Move(mov) if mov.to_string() == "e2e4"));
+        assert_eq!(fake.starts(), 2);
+    }
+
+    #[tokio::test]
+    async fn game_is_lost_by_an_engine_that_keeps_crashing() {
+        let fake = FakeEngine::new(CRASH);
+        let engine = ExternalEnginePlayer::new(&fake.path, GoLimit::MoveTime(10)).unwrap();
+        let mut players: [Box<dyn Player>; 2] = [Box::new(engine), Box::new(RandomPlayer)];
+        let mut game = Game::from_fen(STARTING_POS_FEN);
+        let (outcome, moves) = play_game(&mut game, &mut players, None).await;
+        assert_eq!(outcome.winner, Some(Color::Black));
+        assert_eq!(outcome.reason, "failure");
+        assert!(moves.is_empty());
+        // Restarted once before giving up
+        assert_eq!(fake.starts(), 2);
+    }
+
+    #[test]
+    fn match_scores_crashes_and_timeouts_as_losses_and_restarts_the_engine() {
+        for (on_go, reason) in [(CRASH, "crash of Fake"), (HANG, "timeout of Fake")] {
+            let fake = FakeEngine::new(on_go);
+            let settings = MatchSettings {
+                games: 2,
+                time_per_move: 10,
+                pgn_path: fake.file("match.pgn"),
+                ..Default::default()
+            };
+            let internal = EngineConfig::Internal(SearchSettings::default());
+            let mut runner = MatchRunner::new(settings, &internal, &EngineConfig::Uci(fake.path.clone())).unwrap();
+            let score = runner.run().unwrap();
+            assert_eq!((score.wins, score.draws, score.losses), (2, 0, 0));
+            // Started for the match, and again for the second game
+            assert_eq!(fake.starts(), 2);
+            let pgn = std::fs::read_to_string(fake.file("match.pgn")).unwrap();
+            assert_eq!(pgn.matches(reason).count(), 2, "{}", pgn);
+        }
+    }
+}
@@ -0,0 +1,327 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::{
+    game::Game,
+    moves::Move,
+    search::{is_same_move, Search, SearchSettings},
+};
+
+#[derive(Clone)]
+pub struct EpdSettings {
+    pub search: SearchSettings, // Time or depth limit for every position
+    pub csv_path: Option<String>, // Write the results of every position to this file
+    pub max_positions: usize, // Only the first positions of the file are run, 0 for all
+}
+
+impl Default for EpdSettings {
+    fn default() -> Self {
+        EpdSettings {
+            search: SearchSettings {
+                time_limit: Some(1000),
+                depth: 64,
+                ..Default::default()
+            },
+            csv_path: None,
+            max_positions: 0,
+        }
+    }
+}
+
+/**
+ * One position of a test suite with its opcodes. Moves are in SAN as they are in the file.
+ */
+#[derive(Clone, Debug, Default)]
+pub struct EpdEntry {
+    pub fen: String,
+    pub best_moves: Vec<String>, // bm
+    pub avoid_moves: Vec<String>, // am
+    pub id: String,
+    pub comment: Option<String>, // c0, STS stores the points of the moves here
+}
+
+impl EpdEntry {
+    /**
+     * The id without the number, e.g. "STS(v1.0) Undermine" for "STS(v1.0) Undermine.001".
+     */
+    pub fn theme(&self) -> &str {
+        match self.id.rsplit_once('.') {
+            Some((theme, number)) if number.chars().all(|c| c.is_ascii_digit()) => theme,
+            _ => &self.id,
+        }
+    }
+
+    /**
+     * Points of the moves in an STS comment like "Qxf5=10, Rxf5=3, Kh1=2".
+     */
+    pub fn move_points(&self) -> Vec<(String, u32)> {
+        let comment = match &self.comment {
+            Some(comment) => comment,
+            None => return Vec::new(),
+        };
+        comment
+            .split(',')
+            .filter_map(|part| {
+                let (mov, points) = part.trim().split_once('=')?;
+                Some((mov.to_string(), points.trim().parse::<u32>().ok()?))
+            })
+            .collect()
+    }
+}
+
+/**
+ * Parses one line of an EPD file: the first four FEN fields followed by opcodes separated by semicolons.
+ */
+pub fn parse_epd(line: &str) -> Option<EpdEntry> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let fields: Vec<&str> = line.splitn(5, ' ').collect();
+    if fields.len() < 4 {
+        return None;
+    }
+    let mut entry = EpdEntry {
+        fen: format!("{} 0 1", fields[..4].join(" ")),
+        ..Default::default()
+    };
+
+    for operation in fields.get(4).unwrap_or(&"").split(';') {
+        let operation = operation.trim();
+        let (opcode, operand) = operation.split_once(' ').unwrap_or((operation, ""));
+        let operand = operand.trim().trim_matches('"');
+        match opcode {
+            "bm" => entry.best_moves = operand.split_whitespace().map(str::to_string).collect(),
+            "am" => entry.avoid_moves = operand.split_whitespace().map(str::to_string).collect(),
+            "id" => entry.id = operand.to_string(),
+            "c0" => entry.comment = Some(operand.to_string()),
+            _ => {}
+        }
+    }
+    if entry.best_moves.is_empty() && entry.avoid_moves.is_empty() {
+        return None;
+    }
+    Some(entry)
+}
+
+pub fn load_epd(path: &str) -> Result<Vec<EpdEntry>, String> {
+    let content = std::fs::read_to_string(path).map_err(|err| format!("Cant read {}: {}", path, err))?;
+    Ok(content.lines().filter_map(parse_epd).collect())
+}
+
+/**
+ * Result of the search of one position.
+ */
+#[derive(Clone, Debug)]
+pub struct EpdResult {
+    pub id: String,
+    pub theme: String,
+    pub found: String, // Best move of the search in UCI notation
+    pub expected: String, // The bm or am opcode as in the file
+    pub solved: bool,
+    pub time_to_solution: Option<u64>, // Milliseconds until the search settled on a correct move
+    pub points: u32, // STS points of the found move
+    pub max_points: u32,
+    pub depth: u8,
+    pub nodes: u64,
+}
+
+/**
+ * Runs every position of a suite with the search and prints the summary. A position is solved
+ * if the best move is one of the bm moves and none of the am moves.
+ */
+pub fn run_epd(entries: &[EpdEntry], settings: &EpdSettings) -> Vec<EpdResult> {
+    let count = if settings.max_positions > 0 { settings.max_positions.min(entries.len()) } else { entries.len() };
+    let mut results = Vec::with_capacity(count);
+    for (i, entry) in entries.iter().take(count).enumerate() {
+        let result = run_position(entry, &settings.search);
+        println!(
+            "{}/{} {}: {} {} (expected {}, depth {}{})",
+            i + 1,
+            count,
+            result.id,
+            result.found,
+            if result.solved { "solved" } else { "failed" },
+            result.expected,
+            result.depth,
+            result.time_to_solution.map_or(String::new(), |time| format!(", found after {}ms", time)),
+        );
+        results.push(result);
+    }
+
+    print_summary(&results);
+    if let Some(path) = &settings.csv_path {
+        match write_csv(&results, path) {
+            Ok(()) => println!("Results written to {}", path),
+            Err(err) => println!("{}", err),
+        }
+    }
+    results
+}
+
+fn run_position(entry: &EpdEntry, settings: &SearchSettings) -> EpdResult {
+    let game = Game::from_fen(&entry.fen);
+    let resolve = |moves: &[String]| -> Vec<Move> {
+        moves.iter().map(|san| game.fide_to_move(san)).filter(|m| m.is_valid()).collect()
+    };
+    let best_moves = resolve(&entry.best_moves);
+    let avoid_moves = resolve(&entry.avoid_moves);
+    let points: Vec<(Move, u32)> = entry
+        .move_points()
+        .into_iter()
+        .map(|(san, points)| (game.fide_to_move(&san), points))
+        .filter(|(m, _)| m.is_valid())
+        .collect();
+    let contains = |moves: &[Move], m: &Move| moves.iter().any(|other| is_same_move(*other, *m));
+    // Without bm any move that is not an am move solves it. A bm move that can't be resolved never does
+    let is_solution = |m: &Move| {
+        (entry.best_moves.is_empty() || contains(&best_moves, m)) && !contains(&avoid_moves, m)
+    };
+
+    // The time to solution is when the search switched to a correct move for the last time
+    let handle = Search::spawn(game.clone(), settings.clone());
+    let mut time_to_solution = None;
+    loop {
+        // Infos sent before the search finished are all read before leaving the loop
+        let finished = handle.is_finished();
+        while let Some(info) = handle.try_info() {
+            if info.multi_pv != 1 {
+                continue;
+            }
+            match info.pv.first() {
+                Some(m) if is_solution(m) => time_to_solution = time_to_solution.or(Some(info.time)),
+                _ => time_to_solution = None,
+            }
+        }
+        if finished {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    let result = handle.join();
+
+    let solved = is_solution(&result.best_move);
+    EpdResult {
+        id: entry.id.clone(),
+        theme: entry.theme().to_string(),
+        found: result.best_move.to_string(),
+        expected: if entry.best_moves.is_empty() {
+            format!("am {}", entry.avoid_moves.join(" "))
+        } else {
+            format!("bm {}", entry.best_moves.join(" "))
+        },
+        solved,
+        time_to_solution: if solved { time_to_solution.or(Some(0)) } else { None },
+        points: points.iter().find(|(m, _)| is_same_move(*m, result.best_move)).map_or(0, |(_, points)| *points),
+        max_points: points.iter().map(|(_, points)| *points).max().unwrap_or(0),
+        depth: result.depth,
+        nodes: result.nodes,
+    }
+}
+
+/**
+ * Prints the number of solved positions, the average time to solution and the STS score of every theme.
+ */
+pub fn print_summary(results: &[EpdResult]) {
+    let solved: Vec<&EpdResult> = results.iter().filter(|result| result.solved).collect();
+    println!("Solved {} of {}", solved.len(), results.len());
+    if !solved.is_empty() {
+        let total_time: u64 = solved.iter().filter_map(|result| result.time_to_solution).sum();
+        println!("Average time to solution: {}ms", total_time / solved.len() as u64);
+    }
+
+    // Themes, sorted by name: (positions, solved, points, max points)
+    let mut themes: BTreeMap<&str, (u32, u32, u32, u32)> = BTreeMap::new();
+    for result in results {
+        let theme = themes.entry(&result.theme).or_default();
+        theme.0 += 1;
+        theme.1 += result.solved as u32;
+        theme.2 += result.points;
+        theme.3 += result.max_points;
+    }
+    if themes.values().any(|theme| theme.3 > 0) {
+        for (name, (positions, solved, points, max_points)) in themes.iter() {
+            println!("{}: {}/{} solved, {}/{} points", name, solved, positions, points, max_points);
+        }
+    }
+}
+
+pub fn write_csv(results: &[EpdResult], path: &str) -> Result<(), String> {
+    let mut csv = String::from("id,theme,found,expected,solved,time_to_solution,points,max_points,depth,nodes\n");
+    for result in results {
+        csv += &format!(
+            "\"{}\",\"{}\",{},\"{}\",{},{},{},{},{},{}\n",
+            result.id.replace('"', "\"\""),
+            result.theme.replace('"', "\"\""),
+            result.found,
+            result.expected,
+            result.solved,
+            result.time_to_solution.map_or(String::new(), |time| time.to_string()),
+            result.points,
+            result.max_points,
+            result.depth,
+            result.nodes,
+        );
+    }
+    std::fs::write(path, csv).map_err(|err| format!("Cant write {}: {}", path, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STS_LINE: &str = "4k3/8/8/3q4/8/8/8/3RK3 w - - bm Rxd5; id \"STS(v1.0) Open Files.012\"; c0 \"Rxd5=10, Kf2=3, Ke2=1\";";
+
+    fn settings() -> SearchSettings {
+        SearchSettings {
+            depth: 3,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn parses_the_opcodes() {
+        let entry = parse_epd(STS_LINE).unwrap();
+        assert_eq!(entry.fen, "4k3/8/8/3q4/8/8/8/3RK3 w - - 0 1");
+        assert_eq!(entry.best_moves, vec!["Rxd5"]);
+        assert!(entry.avoid_moves.is_empty());
+        assert_eq!(entry.id, "STS(v1.0) Open Files.012");
+        assert_eq!(entry.theme(), "STS(v1.0) Open Files");
+        assert_eq!(entry.move_points(), vec![("Rxd5".to_string(), 10), ("Kf2".to_string(), 3), ("Ke2".to_string(), 1)]);
+
+        let entry = parse_epd("r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - am Qh5 Ba6; id \"WAC.1\";").unwrap();
+        assert_eq!(entry.avoid_moves, vec!["Qh5", "Ba6"]);
+        assert!(entry.best_moves.is_empty());
+        assert_eq!(entry.theme(), "WAC");
+        assert_eq!(entry.comment, None);
+
+        // Positions without bm or am and comments are skipped
+        assert!(parse_epd("4k3/8/8/8/8/8/8/4K3 w - - id \"empty\";").is_none());
+        assert!(parse_epd("# 4k3/8/8/8/8/8/8/4K3 w - - bm Kd2;").is_none());
+        assert!(parse_epd("").is_none());
+    }
+
+    #[test]
+    fn scores_the_sts_points_of_the_found_move() {
+        let result = run_position(&parse_epd(STS_LINE).unwrap(), &settings());
+        assert_eq!(result.found, "d1d5");
+        assert!(result.solved);
+        assert_eq!((result.points, result.max_points), (10, 10));
+        assert_eq!(result.theme, "STS(v1.0) Open Files");
+
+        // The found move is not the best move but still gets its points
+        let line = "4k3/8/8/3q4/8/8/8/3RK3 w - - bm Kf2; id \"STS.001\"; c0 \"Kf2=10, Rxd5=4\";";
+        let result = run_position(&parse_epd(line).unwrap(), &settings());
+        assert!(!result.solved);
+        assert_eq!((result.points, result.max_points), (4, 10));
+        assert_eq!(result.time_to_solution, None);
+    }
+
+    #[test]
+    fn avoid_moves_fail_the_position() {
+        let line = "4k3/8/8/3q4/8/8/8/3RK3 w - - am Rxd5; id \"avoid\";";
+        let result = run_position(&parse_epd(line).unwrap(), &settings());
+        assert!(!result.solved);
+        assert_eq!(result.expected, "am Rxd5");
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{board::Board, piece::{Piece, move_sliding_squares}, moves::{Move, MoveType}, base_types::{Color, Position, PieceType}, precompute::get_direction_index, STARTING_POS_FEN, square_table::{square_table_read, self}};
+use crate::{board::Board, piece::{Piece, move_sliding_squares}, moves::{Move, MoveType}, base_types::{Color, Position, PieceType}, precompute::{get_direction_index, ZOBRIST_KEYS, ZOBRIST_BLACK_TO_MOVE, ZOBRIST_CASTLING, ZOBRIST_EN_PASSANT}, STARTING_POS_FEN, square_table::{square_table_read, self}, pgn::parse_pgn, endgame::{self, Material}};
 
 #[derive(Copy, Clone, Debug)]
 pub struct GameState {
@@ -10,6 +10,7 @@ pub struct GameState {
     pub captured_piece: Option<PieceType>,
 }
 
+#[derive(Clone)]
 pub struct Game {
     pub board: Board,
     pub turn: Color,
@@ -17,6 +18,7 @@ pub struct Game {
     pub black_king_position: Position,
     pub state: GameState,
     state_stack: Vec<GameState>,
+    hash_stack: Vec<u64>,
     moves: Vec<Move>,
     pub enemy_attacks: u64,
     pub friendly_attacks: u64,
@@ -24,6 +26,7 @@ pub struct Game {
     pub enemy_king_pins: Vec<u64>,
     pub king_check: u64, // We can only have one check at a time
     pub enemy_king_check: u64,
+    pub hash: u64, // Zobrist hash of the current position
 }
 
 impl Default for Game {
@@ -42,6 +45,7 @@ impl Default for Game {
                 captured_piece: None,
             },
             state_stack: Vec::new(),
+            hash_stack: Vec::new(),
             moves: Vec::new(),
             enemy_attacks: 0,
             friendly_attacks: 0,
@@ -49,14 +53,31 @@ impl Default for Game {
             enemy_king_pins: Vec::new(),
             king_check: 0,
             enemy_king_check: 0,
+            hash: 0,
         };
         result.update_position();
+        result.hash = result.compute_hash();
         result
     }
 }
 
 impl Game {
     pub fn evaluate(&self) -> i32 {
+        self.evaluate_with_knowledge(100)
+    }
+
+    /**
+     * Evaluation with only a percentage of the positional knowledge, used by weaker skill levels.
+     * Below 100 the square tables are scaled down and the special endgame evaluations are left out.
+     */
+    pub fn evaluate_with_knowledge(&self, positional_weight: i32) -> i32 {
+        let material = Material::new(self);
+        if positional_weight >= 100 {
+            if let Some(score) = endgame::evaluate(self, &material) {
+                return score;
+            }
+        }
+
         let mut friendly_score = 0;
         let mut enemy_score = 0;
 
@@ -94,19 +115,21 @@ impl Game {
         enemy_score += if enemy_king_pin_check.1 != 0 { -100 } else { 0 };
 
 
-        friendly_score += self.evaluate_square_table(self.turn);
-        enemy_score += self.evaluate_square_table(self.turn.opposite());
+        let is_endgame = material.is_endgame();
+        friendly_score += self.evaluate_square_table(self.turn, is_endgame) * positional_weight / 100;
+        enemy_score += self.evaluate_square_table(self.turn.opposite(), is_endgame) * positional_weight / 100;
 
         //return score_all_values(count_diff, check_score, pin_score, capture_score);
-        return friendly_score - enemy_score;
+        return endgame::scale(self, &material, friendly_score - enemy_score);
     }
 
-    pub fn evaluate_square_table(&self, color : Color) -> i32 {
+    pub fn evaluate_square_table(&self, color : Color, is_endgame : bool) -> i32 {
         let mut result = 0;
         let pieces = self.board.pieces.iter().filter(|p| p.is_some() && p.unwrap().color == color).map(|p| p.unwrap()).collect::<Vec<Piece>>();
         for piece in pieces {
             let square_table = match piece.piece_type {
                 PieceType::Bishop => &square_table::ST_BISHOPS,
+                PieceType::King if is_endgame => &square_table::ST_KING_END,
                 PieceType::King => &square_table::ST_KING_MID,
                 PieceType::Knight => &square_table::ST_KNIGHTS,
                 PieceType::Pawn => &square_table::ST_PAWNS,
@@ -152,8 +175,14 @@ impl Game {
         let current_found_move = current_found_move_opt.unwrap();
 
         self.state_stack.push(self.state);
+        self.hash_stack.push(self.hash);
         self.moves.push(current_found_move);
 
+        // The hash is updated with every change of the board, the castling rights and en passant are
+        // taken out here and put back in with their new values at the end
+        let mut hash = self.hash ^ self.state_key();
+        let opponent = self.turn.opposite();
+
 
         // Update castling rights
         match moving_piece.piece_type {
@@ -170,17 +199,7 @@ impl Game {
                     Color::Black => self.black_king_position = mov.to,
                 }
             },
-            PieceType::Rook => {
-                if mov.from == Position::from((0 as u8, 0 as u8)) {
-                    self.state.white_can_castle_queenside = false;
-                } else if mov.from == Position::from((7 as u8, 0 as u8)) {
-                    self.state.white_can_castle_kingside = false;
-                } else if mov.from == Position::from((0 as u8, 7 as u8)) {
-                    self.state.black_can_castle_queenside = false;
-                } else if mov.from == Position::from((7 as u8, 7 as u8)) {
-                    self.state.black_can_castle_kingside = false;
-                }
-            },
+            PieceType::Rook => self.remove_castling_right(mov.from),
             _ => {}
         }
 
@@ -191,30 +210,44 @@ impl Game {
 
         match current_found_move.move_type {
             MoveType::DoublePawnPush => self.state.en_passant_target = Some(current_found_move.from.get_change(if let Color::White = self.turn { 8 } else { -8 })),
-            MoveType::EnPassantCapture => self.board.remove_piece(current_found_move.to.get_change(if let Color::White = self.turn { -8 } else { 8 })),
+            MoveType::EnPassantCapture => {
+                let captured_position = current_found_move.to.get_change(if let Color::White = self.turn { -8 } else { 8 });
+                hash ^= piece_key(opponent, PieceType::Pawn, captured_position);
+                self.board.remove_piece(captured_position);
+            }
             MoveType::Capture => {
                 let piece_type = self.board.get_piece(current_found_move.to).unwrap().piece_type;
+                hash ^= piece_key(opponent, piece_type, current_found_move.to);
                 if let PieceType::Rook = piece_type {
-                    if current_found_move.to == Position::from((0 as u8, 0 as u8)) {
-                        self.state.white_can_castle_queenside = false;
-                    } else if current_found_move.to == Position::from((7 as u8, 0 as u8)) {
-                        self.state.white_can_castle_kingside = false;
-                    } else if current_found_move.to == Position::from((0 as u8, 7 as u8)) {
-                        self.state.black_can_castle_queenside = false;
-                    } else if current_found_move.to == Position::from((7 as u8, 7 as u8)) {
-                        self.state.black_can_castle_kingside = false;
-                    }
+                    self.remove_castling_right(current_found_move.to);
                 }
                 self.state.captured_piece = Some(piece_type);
             }
-            MoveType::KingCastle => self.board.move_piece(current_found_move.to.get_change(1), current_found_move.to.get_change(-1)),
-            MoveType::QueenCastle => self.board.move_piece(current_found_move.to.get_change(-2), current_found_move.to.get_change(1)),
+            MoveType::KingCastle => {
+                let (rook_from, rook_to) = (current_found_move.to.get_change(1), current_found_move.to.get_change(-1));
+                hash ^= piece_key(self.turn, PieceType::Rook, rook_from) ^ piece_key(self.turn, PieceType::Rook, rook_to);
+                self.board.move_piece(rook_from, rook_to);
+            }
+            MoveType::QueenCastle => {
+                let (rook_from, rook_to) = (current_found_move.to.get_change(-2), current_found_move.to.get_change(1));
+                hash ^= piece_key(self.turn, PieceType::Rook, rook_from) ^ piece_key(self.turn, PieceType::Rook, rook_to);
+                self.board.move_piece(rook_from, rook_to);
+            }
             MoveType::BishopPromotion | MoveType::KnightPromotion | MoveType::QueenPromotion | MoveType::RookPromotion => {
+                hash ^= piece_key(self.turn, PieceType::Pawn, current_found_move.from);
+                hash ^= piece_key(self.turn, current_found_move.move_type.get_promotion_piece(), current_found_move.to);
                 self.board.remove_piece(current_found_move.from);
                 self.board.add_piece(Piece::new(self.turn, current_found_move.move_type.get_promotion_piece(), current_found_move.to));
             }
             MoveType::BishopPromotionCapture | MoveType::KnightPromotionCapture | MoveType::QueenPromotionCapture | MoveType::RookPromotionCapture => {
-                self.state.captured_piece = Some(self.board.get_piece(current_found_move.to).unwrap().piece_type);
+                let captured_type = self.board.get_piece(current_found_move.to).unwrap().piece_type;
+                self.state.captured_piece = Some(captured_type);
+                if let PieceType::Rook = captured_type {
+                    self.remove_castling_right(current_found_move.to);
+                }
+                hash ^= piece_key(opponent, captured_type, current_found_move.to);
+                hash ^= piece_key(self.turn, PieceType::Pawn, current_found_move.from);
+                hash ^= piece_key(self.turn, current_found_move.move_type.get_promotion_piece(), current_found_move.to);
                 self.board.remove_piece(current_found_move.from);
                 self.board.remove_piece(current_found_move.to);
                 self.board.add_piece(Piece::new(self.turn, current_found_move.move_type.get_promotion_piece(), current_found_move.to));
@@ -223,14 +256,31 @@ impl Game {
         }
 
         if !current_found_move.move_type.is_promotion() {
+            hash ^= piece_key(self.turn, moving_piece.piece_type, mov.from) ^ piece_key(self.turn, moving_piece.piece_type, mov.to);
             self.board.move_piece(mov.from, mov.to);
         }
         self.turn = self.turn.opposite();
+        self.hash = hash ^ self.state_key() ^ ZOBRIST_KEYS[ZOBRIST_BLACK_TO_MOVE];
 
         self.update_position();
         return true;
     }
 
+    /**
+     * A rook that moves away from its corner or gets captured there can't castle any more.
+     */
+    fn remove_castling_right(&mut self, corner : Position) {
+        if corner == Position::from((0 as u8, 0 as u8)) {
+            self.state.white_can_castle_queenside = false;
+        } else if corner == Position::from((7 as u8, 0 as u8)) {
+            self.state.white_can_castle_kingside = false;
+        } else if corner == Position::from((0 as u8, 7 as u8)) {
+            self.state.black_can_castle_queenside = false;
+        } else if corner == Position::from((7 as u8, 7 as u8)) {
+            self.state.black_can_castle_kingside = false;
+        }
+    }
+
     pub fn unmake_move(&mut self) {
         if self.state_stack.len() == 0 || self.moves.len() == 0 {
             println!("No moves to unmake!");
@@ -289,6 +339,7 @@ impl Game {
 
 
         self.state = self.state_stack.pop().unwrap();
+        self.hash = self.hash_stack.pop().unwrap();
         self.turn = self.turn.opposite();
     
         self.update_position();
@@ -306,7 +357,24 @@ impl Game {
         moves
     }
 
+    /**
+     * Legal captures and promotions only, for the quiescence search.
+     */
+    pub fn get_possible_team_captures(&self, c : Color) -> Vec<Move> {
+        let mut moves : Vec<Move> = Vec::with_capacity(64);
+        for piece in self.board.pieces.into_iter().flatten() {
+            if piece.color == c {
+                moves.append(&mut self.generate_piece_moves(piece, true));
+            }
+        }
+        moves
+    }
+
     pub fn get_possible_piece_moves(&self, piece : Piece) -> Vec<Move> {
+        self.generate_piece_moves(piece, false)
+    }
+
+    fn generate_piece_moves(&self, piece : Piece, captures_only : bool) -> Vec<Move> {
         let mut moves : Vec<Move> = Vec::with_capacity(32);
 
         piece.move_all_directions(&mut|move_to_position, _| -> bool {
@@ -316,6 +384,14 @@ impl Game {
                 if piece_on_position.color == piece.color {
                     return false;
                 }
+            } else if captures_only {
+                // Empty squares are only interesting for en passant and promotions
+                let is_pawn = piece.piece_type == PieceType::Pawn;
+                let is_en_passant = is_pawn && self.state.en_passant_target == Some(move_to_position);
+                let is_promotion = is_pawn && (move_to_position.get_row() == 7 || move_to_position.get_row() == 0);
+                if !is_en_passant && !is_promotion {
+                    return true;
+                }
             }
 
             // Check if we dont go out of pins
@@ -565,11 +641,177 @@ impl Game {
         false
     }
 
+    /**
+     * Whether the move can give check, without making it. Exact for normal moves and promotions,
+     * castling and en passant are always reported as they can check through the rook or the removed pawn.
+     */
+    pub fn may_give_check(&self, mov: Move) -> bool {
+        let moving_piece = match self.board.get_piece(mov.from) {
+            Some(piece) => piece,
+            None => return false,
+        };
+        if mov.move_type.is_castle() || mov.move_type == MoveType::EnPassantCapture {
+            return true;
+        }
+        let enemy_king = if let Color::White = moving_piece.color { self.black_king_position } else { self.white_king_position };
+        let occupied = (self.board.occupied() & !mov.from.bitboard()) | mov.to.bitboard();
+
+        // Discovered check by a piece behind the one that moved away
+        let own_pieces = self.board.pieces.iter().flatten().filter(|piece| piece.color == moving_piece.color)
+            .fold(0, |bitboard, piece| bitboard | piece.position.bitboard());
+        if self.board.attackers_to(enemy_king, occupied) & own_pieces & !mov.from.bitboard() != 0 {
+            return true;
+        }
+
+        let piece_type = if mov.move_type.is_promotion() { mov.move_type.get_promotion_piece() } else { moving_piece.piece_type };
+        let col_change = enemy_king.get_col() as i32 - mov.to.get_col() as i32;
+        let row_change = enemy_king.get_row() as i32 - mov.to.get_row() as i32;
+        let forward = if let Color::White = moving_piece.color { 1 } else { -1 };
+        let straight = col_change == 0 || row_change == 0;
+        let diagonal = col_change.abs() == row_change.abs();
+        match piece_type {
+            PieceType::Pawn => row_change == forward && col_change.abs() == 1,
+            PieceType::Knight => col_change.abs() * row_change.abs() == 2,
+            PieceType::King => false,
+            PieceType::Bishop | PieceType::Rook | PieceType::Queen => {
+                let slides = match piece_type {
+                    PieceType::Bishop => diagonal,
+                    PieceType::Rook => straight,
+                    _ => straight || diagonal,
+                };
+                if !slides || (col_change == 0 && row_change == 0) {
+                    return false;
+                }
+                // Every square between the piece and the king has to be empty
+                let distance = col_change.abs().max(row_change.abs());
+                (1..distance).all(|step| {
+                    let square = Position::from((
+                        mov.to.get_col() as i32 + col_change.signum() * step,
+                        mov.to.get_row() as i32 + row_change.signum() * step,
+                    ));
+                    occupied & square.bitboard() == 0
+                })
+            }
+        }
+    }
+
+    /**
+     * Static exchange evaluation. Plays out the full capture sequence on the target square of the move,
+     * always recapturing with the least valuable piece (x-ray attackers included), and returns the
+     * material balance for the moving side. Either side can stop capturing when it would lose material.
+     * Quiet moves are evaluated as well, a negative value means the moved piece gets lost.
+     */
+    pub fn see(&self, mov: Move) -> i32 {
+        let moving_piece = match self.board.get_piece(mov.from) {
+            Some(piece) => piece,
+            None => return 0,
+        };
+        let target = mov.to;
+        let mut occupied = self.board.occupied() & !mov.from.bitboard();
+        let mut gain = [0; 32];
+
+        gain[0] = match self.board.get_piece(target) {
+            Some(captured) => captured.piece_type.get_value(),
+            None => 0,
+        };
+        if let MoveType::EnPassantCapture = mov.move_type {
+            let captured_position = target.get_change(if let Color::White = moving_piece.color { -8 } else { 8 });
+            occupied &= !captured_position.bitboard();
+            gain[0] = PieceType::Pawn.get_value();
+        }
+
+        // The value of the piece standing on the target square, which is the next one to be captured
+        let mut on_square_value = moving_piece.piece_type.get_value();
+        if mov.move_type.is_promotion() {
+            let promotion_value = mov.move_type.get_promotion_piece().get_value();
+            gain[0] += promotion_value - PieceType::Pawn.get_value();
+            on_square_value = promotion_value;
+        }
+
+        let mut side = moving_piece.color.opposite();
+        let mut depth = 0;
+        loop {
+            let attackers = self.board.attackers_to(target, occupied);
+
+            // Find the least valuable attacker of the side to capture
+            let mut least_valuable: Option<Piece> = None;
+            for index in 0..64 {
+                if attackers & (1 << index) == 0 {
+                    continue;
+                }
+                let piece = self.board.pieces[index].unwrap();
+                if piece.color != side {
+                    continue;
+                }
+                if least_valuable.is_none_or(|p| piece.piece_type.get_value() < p.piece_type.get_value()) {
+                    least_valuable = Some(piece);
+                }
+            }
+            let attacker = match least_valuable {
+                Some(attacker) => attacker,
+                None => break,
+            };
+
+            depth += 1;
+            gain[depth] = on_square_value - gain[depth - 1];
+            // Neither side can improve by continuing the exchange
+            if (-gain[depth - 1]).max(gain[depth]) < 0 || depth == gain.len() - 1 {
+                break;
+            }
+
+            on_square_value = attacker.piece_type.get_value();
+            occupied &= !attacker.position.bitboard();
+            side = side.opposite();
+        }
+
+        while depth > 0 {
+            gain[depth - 1] = -(-gain[depth - 1]).max(gain[depth]);
+            depth -= 1;
+        }
+        gain[0]
+    }
+
     fn update_position(&mut self) {
         self.update_attacks();
         self.update_king_pins();
     }
 
+    /**
+     * Zobrist hash of the position from scratch. Moves update the hash incrementally instead.
+     */
+    fn compute_hash(&self) -> u64 {
+        let mut hash = self.state_key();
+        for piece in self.board.pieces.iter().flatten() {
+            hash ^= piece_key(piece.color, piece.piece_type, piece.position);
+        }
+        if let Color::Black = self.turn {
+            hash ^= ZOBRIST_KEYS[ZOBRIST_BLACK_TO_MOVE];
+        }
+        hash
+    }
+
+    /**
+     * Part of the hash for the castling rights and the en passant file.
+     */
+    fn state_key(&self) -> u64 {
+        let mut hash = 0;
+        let castling_rights = [
+            self.state.white_can_castle_kingside,
+            self.state.white_can_castle_queenside,
+            self.state.black_can_castle_kingside,
+            self.state.black_can_castle_queenside,
+        ];
+        for (i, can_castle) in castling_rights.iter().enumerate() {
+            if *can_castle {
+                hash ^= ZOBRIST_KEYS[ZOBRIST_CASTLING + i];
+            }
+        }
+        if let Some(en_passant_target) = self.state.en_passant_target {
+            hash ^= ZOBRIST_KEYS[ZOBRIST_EN_PASSANT + en_passant_target.get_col() as usize];
+        }
+        hash
+    }
+
     fn update_king_pins(&mut self) {
         (self.king_pins, self.king_check) = self.board.get_king_pins(self.turn);
         (self.enemy_king_pins, self.enemy_king_check) = self.board.get_king_pins(self.turn.opposite());
@@ -648,6 +890,7 @@ impl Game {
         game.black_king_position = black_king_position;
 
         game.update_position();
+        game.hash = game.compute_hash();
 
         game
     }
@@ -741,8 +984,118 @@ impl Game {
         pgn
     }
 
+    /**
+     * Finds the legal move written in standard algebraic notation (e.g. "Nbd7", "exd5", "e8=Q+", "O-O").
+     * Returns an invalid move if there is no such move or it is ambiguous.
+     */
     pub fn fide_to_move(&self, fide : &str) -> Move {
-        Move::invalid()
+        let fide = fide.trim_end_matches(['+', '#', '!', '?']);
+        let moves = self.get_possible_team_moves(self.turn);
+
+        let castle = match fide {
+            "O-O" | "0-0" => Some(MoveType::KingCastle),
+            "O-O-O" | "0-0-0" => Some(MoveType::QueenCastle),
+            _ => None,
+        };
+        if let Some(castle) = castle {
+            return moves.into_iter().find(|m| m.move_type == castle).unwrap_or(Move::invalid());
+        }
+
+        // Promotion piece, either "e8=Q" or "e8Q"
+        let (fide, promotion) = match fide.char_indices().last() {
+            Some((i, c)) if "QRBN".contains(c) && i >= 2 => {
+                (fide[..i].trim_end_matches('='), Some(PieceType::from_char(c)))
+            }
+            _ => (fide, None),
+        };
+        let (piece_type, rest) = match fide.chars().next() {
+            Some(c) if "KQRBN".contains(c) => (PieceType::from_char(c), &fide[1..]),
+            _ => (PieceType::Pawn, fide),
+        };
+        let rest = rest.replace(['x', ':', '-'], "");
+        if rest.len() < 2 || !rest.is_char_boundary(rest.len() - 2) {
+            return Move::invalid();
+        }
+        let (disambiguation, to) = rest.split_at(rest.len() - 2);
+        let to = Position::from(to.to_string());
+        let from_col = disambiguation.chars().find(|c| ('a'..='h').contains(c)).map(|c| c as u8 - b'a');
+        let from_row = disambiguation.chars().find(|c| ('1'..='8').contains(c)).map(|c| c as u8 - b'1');
+
+        let mut candidates = moves.into_iter().filter(|m| {
+            let moving_piece = self.board.get_piece(m.from);
+            m.to == to
+                && moving_piece.is_some_and(|p| p.piece_type == piece_type)
+                && from_col.is_none_or(|col| m.from.get_col() == col)
+                && from_row.is_none_or(|row| m.from.get_row() == row)
+                // A promotion without a piece is taken as a queen promotion
+                && m.move_type.get_promotion_piece() == match (piece_type, promotion) {
+                    (PieceType::Pawn, Some(promotion)) => promotion,
+                    (PieceType::Pawn, None) if to.get_row() == 0 || to.get_row() == 7 => PieceType::Queen,
+                    _ => PieceType::Pawn,
+                }
+        });
+        match (candidates.next(), candidates.next()) {
+            (Some(mov), None) => mov,
+            _ => Move::invalid(),
+        }
+    }
+
+    /**
+     * Writes a legal move in standard algebraic notation, with the check or mate suffix.
+     * Moves that are not legal are returned in UCI notation.
+     */
+    pub fn move_to_san(&mut self, mov : Move) -> String {
+        let moves = self.get_possible_team_moves(self.turn);
+        let same_move = |m: &Move| *m == mov && m.move_type.get_promotion_piece() == mov.move_type.get_promotion_piece();
+        let mov = match moves.iter().find(|m| same_move(m)) {
+            Some(mov) => *mov,
+            None => return mov.to_string(),
+        };
+        let piece_type = self.board.get_piece(mov.from).unwrap().piece_type;
+
+        let mut san = String::new();
+        match mov.move_type {
+            MoveType::KingCastle => san.push_str("O-O"),
+            MoveType::QueenCastle => san.push_str("O-O-O"),
+            _ if piece_type == PieceType::Pawn => {
+                if mov.move_type.is_capture() {
+                    san.push((mov.from.get_col() + b'a') as char);
+                    san.push('x');
+                }
+                san.push_str(&mov.to.to_string());
+                if mov.move_type.is_promotion() {
+                    san.push('=');
+                    san.push(mov.move_type.get_promotion_piece().get_char().to_ascii_uppercase());
+                }
+            }
+            _ => {
+                san.push(piece_type.get_char().to_ascii_uppercase());
+                // Other pieces of the same type that can go to the same square
+                let others = moves.iter().filter(|m| {
+                    m.to == mov.to && m.from != mov.from && self.board.get_piece(m.from).is_some_and(|p| p.piece_type == piece_type)
+                }).collect::<Vec<&Move>>();
+                if !others.is_empty() {
+                    if others.iter().all(|m| m.from.get_col() != mov.from.get_col()) {
+                        san.push((mov.from.get_col() + b'a') as char);
+                    } else if others.iter().all(|m| m.from.get_row() != mov.from.get_row()) {
+                        san.push((mov.from.get_row() + b'1') as char);
+                    } else {
+                        san.push_str(&mov.from.to_string());
+                    }
+                }
+                if mov.move_type.is_capture() {
+                    san.push('x');
+                }
+                san.push_str(&mov.to.to_string());
+            }
+        }
+
+        self.make_move(mov);
+        if self.king_check != 0 {
+            san.push(if self.get_possible_team_moves(self.turn).is_empty() { '#' } else { '+' });
+        }
+        self.unmake_move();
+        san
     }
 
     pub fn to_pgn(&self) -> String {
@@ -761,38 +1114,228 @@ impl Game {
         pgn
     }
 
+    /**
+     * Loads the first game of the pgn, starting from its FEN header if there is one.
+     * Stops at the first move that can not be played.
+     */
     pub fn from_pgn(pgn : &str)-> Game {
-        todo!("Not Working");
-        let mut game = Game::from_fen(STARTING_POS_FEN);
-        // Get the move list of the pgn string
-        let moves = pgn.split(' ').collect::<Vec<&str>>();
-        let mut in_comment = false;
-        for mov in moves {
-            if mov.len() == 0 {
-                continue;
-            }
-            if mov.starts_with('{') {
-                in_comment = true;
-            }
-            if in_comment {
-                if mov.ends_with('}') {
-                    in_comment = false;
-                }
-                continue;
+        let pgn_game = parse_pgn(pgn).into_iter().next().unwrap_or_default();
+        let mut game = Game::from_fen(pgn_game.header("FEN").unwrap_or(STARTING_POS_FEN));
+        for fide in &pgn_game.moves {
+            let mov = game.fide_to_move(fide);
+            if !mov.is_valid() || !game.make_move(mov) {
+                println!("Invalid move in pgn: {}", fide);
+                break;
             }
-            let mut mov = mov.to_string();
-            if mov.contains('.') {
-                let dot_pos = mov.find('.').unwrap();
-                mov = mov.chars().skip(dot_pos + 1).collect::<String>();
-                if mov.is_empty() {
-                    continue;
+        }
+        game
+    }
+
+}
+
+fn piece_key(color : Color, piece_type : PieceType, position : Position) -> u64 {
+    let color_offset = if let Color::White = color { 0 } else { 6 };
+    ZOBRIST_KEYS[(color_offset + piece_type as usize) * 64 + position.index()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn see(fen : &str, san : &str) -> i32 {
+        let game = Game::from_fen(fen);
+        let mov = game.fide_to_move(san);
+        assert!(mov.is_valid(), "{} is not legal in {}", san, fen);
+        game.see(mov)
+    }
+
+    #[test]
+    fn see_counts_x_ray_recaptures() {
+        // The rook behind the capturing rook recaptures on d5
+        assert_eq!(see("3r2k1/8/8/3p4/8/8/3R4/3R2K1 w - - 0 1", "Rd2xd5"), 100);
+        // Without it the pawn is defended and taking it loses the exchange
+        assert_eq!(see("3r2k1/8/8/3p4/8/8/3R4/6K1 w - - 0 1", "Rxd5"), -400);
+        // Black's queen behind the rook recaptures last
+        assert_eq!(see("3q2k1/3r4/8/3p4/8/8/3R4/3R2K1 w - - 0 1", "Rd2xd5"), -400);
+    }
+
+    #[test]
+    fn see_of_losing_and_winning_captures() {
+        assert_eq!(see("6k1/8/4p3/3p4/8/8/8/3Q2K1 w - - 0 1", "Qxd5"), -800);
+        assert_eq!(see("6k1/8/4p3/3q4/4P3/8/8/6K1 w - - 0 1", "exd5"), 800);
+        // A quiet move to an attacked square loses the piece
+        assert_eq!(see("6k1/8/4p3/8/8/8/8/3Q2K1 w - - 0 1", "Qd5"), -900);
+    }
+
+    #[test]
+    fn see_of_promotions() {
+        assert_eq!(see("8/1P6/8/8/8/k7/8/6K1 w - - 0 1", "b8=Q"), 800);
+        // The new queen gets taken by the rook
+        assert_eq!(see("7r/1P6/8/8/8/k7/8/6K1 w - - 0 1", "b8=Q"), -100);
+        assert_eq!(see("r7/1P6/8/8/8/k7/8/6K1 w - - 0 1", "bxa8=Q"), 1300);
+    }
+
+    #[test]
+    fn see_of_en_passant() {
+        assert_eq!(see("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "exd6"), 100);
+        assert_eq!(see("4k3/2p5/8/3pP3/8/8/8/4K3 w - d6 0 1", "exd6"), 0);
+        // The captured pawn no longer blocks the rook on d1, which defends d6
+        assert_eq!(see("3rk3/8/8/3pP3/8/8/8/3RK3 w - d6 0 1", "exd6"), 100);
+    }
+
+    /**
+     * SAN of the move in UCI notation, checking that it reads back as the same move.
+     */
+    fn san(fen : &str, uci : &str) -> String {
+        let mut game = Game::from_fen(fen);
+        let mov = game.get_possible_team_moves(game.turn).into_iter().find(|m| m.to_string() == uci).unwrap();
+        let san = game.move_to_san(mov);
+        let read = game.fide_to_move(&san);
+        assert_eq!(read.to_string(), uci, "{} reads back as {}", san, read.to_string());
+        assert_eq!(read.move_type, mov.move_type, "{}", san);
+        san
+    }
+
+    #[test]
+    fn san_disambiguates_pieces() {
+        let knights = "rn1qkb1r/ppp1pppp/5n2/3p4/3P4/5N2/PPP1PPPP/RNBQKB1R b KQkq - 0 1";
+        assert_eq!(san(knights, "b8d7"), "Nbd7");
+        assert_eq!(san(knights, "f6d7"), "Nfd7");
+        assert_eq!(san(knights, "b8c6"), "Nc6");
+        let rooks = "k7/8/8/8/8/4R3/8/4R1K1 w - - 0 1";
+        assert_eq!(san(rooks, "e1e2"), "R1e2");
+        assert_eq!(san(rooks, "e3e2"), "R3e2");
+        let queens = "8/8/8/7k/8/Q7/8/Q1Q3K1 w - - 0 1";
+        assert_eq!(san(queens, "a1b2"), "Qa1b2");
+        assert_eq!(san(queens, "c1b2"), "Qcb2");
+        assert_eq!(san(queens, "a3b2"), "Q3b2");
+    }
+
+    #[test]
+    fn san_of_castling_promotions_and_en_passant() {
+        let castling = "3k4/8/8/8/8/8/8/R3K2R w KQ - 0 1";
+        assert_eq!(san(castling, "e1g1"), "O-O");
+        assert_eq!(san(castling, "e1c1"), "O-O-O+");
+        let promotion = "3r4/4P3/3k4/8/8/8/8/4K3 w - - 0 1";
+        assert_eq!(san(promotion, "e7d8q"), "exd8=Q+");
+        assert_eq!(san(promotion, "e7d8n"), "exd8=N");
+        assert_eq!(san(promotion, "e7e8n"), "e8=N+");
+        assert_eq!(san(promotion, "e7e8q"), "e8=Q");
+        assert_eq!(san("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "e5d6"), "exd6");
+        assert_eq!(san("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", "a1a8"), "Ra8#");
+    }
+
+    #[test]
+    fn reads_other_san_spellings() {
+        let game = Game::from_fen("3r4/4P3/3k4/8/8/8/8/R3K2R w KQ - 0 1");
+        assert_eq!(game.fide_to_move("0-0").to_string(), "e1g1");
+        assert_eq!(game.fide_to_move("e8Q").to_string(), "e7e8q");
+        assert_eq!(game.fide_to_move("ed8=R").to_string(), "e7d8r");
+        assert_eq!(game.fide_to_move("Ra1-d1").to_string(), "a1d1");
+        // Without a piece a promotion is a queen promotion
+        assert_eq!(game.fide_to_move("exd8").move_type, MoveType::QueenPromotionCapture);
+        assert!(!game.fide_to_move("Nf3").is_valid());
+        assert!(!game.fide_to_move("R").is_valid());
+    }
+
+    #[test]
+    fn every_legal_move_reads_back_from_its_san() {
+        let fens = [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/1P4P1/8/8/1pP5/8/6p1/R3K2R b KQkq c3 0 1",
+            "8/8/8/7k/8/Q7/8/Q1Q3K1 w - - 0 1",
+        ];
+        for fen in fens {
+            let game = Game::from_fen(fen);
+            for mov in game.get_possible_team_moves(game.turn) {
+                san(fen, &mov.to_string());
+            }
+        }
+    }
+
+    #[test]
+    fn may_give_check_agrees_with_making_the_move() {
+        let fens = [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R b KQkq - 0 1",
+            "r3k2r/1P4P1/8/8/1pP5/8/6p1/R3K2R b KQkq c3 0 1",
+            // Discovered checks by the rook, pawn checks and checking promotions
+            "3k4/1P6/8/8/4N3/8/8/4R1K1 w - - 0 1",
+            "8/8/3k4/8/2P1P3/8/8/6K1 w - - 0 1",
+            "5k2/8/8/8/8/8/8/R3K2R w KQ - 0 1",
+        ];
+        let mut checks = 0;
+        for fen in fens {
+            let mut game = Game::from_fen(fen);
+            for mov in game.get_possible_team_moves(game.turn) {
+                let may_give_check = game.may_give_check(mov);
+                game.make_move(mov);
+                let gives_check = game.king_check != 0;
+                game.unmake_move();
+                checks += gives_check as u32;
+                if mov.move_type.is_castle() || mov.move_type == MoveType::EnPassantCapture {
+                    assert!(may_give_check || !gives_check, "{} {}", fen, mov.to_string());
+                } else {
+                    assert_eq!(may_give_check, gives_check, "{} {}", fen, mov.to_string());
                 }
             }
-            println!("Move string \"{}\"", mov);
-            //let mov = game.fide_to_move(&mov);
-            //game.make_move(mov);
         }
-        game
+        assert!(checks > 10);
+    }
+
+    fn check_hash(game : &mut Game, depth : u8) {
+        assert_eq!(game.hash, game.compute_hash(), "{}", game.to_fen());
+        if depth == 0 {
+            return;
+        }
+        for mov in game.get_possible_team_moves(game.turn) {
+            game.make_move(mov);
+            check_hash(game, depth - 1);
+            game.unmake_move();
+            assert_eq!(game.hash, game.compute_hash(), "{} after unmaking {}", game.to_fen(), mov.to_string());
+        }
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn incremental_hash_matches_the_full_hash() {
+        // Castling both ways, en passant, promotions with and without captures
+        let fens = [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/1P4P1/8/8/1pP5/8/6p1/R3K2R b KQkq c3 0 1",
+        ];
+        for fen in fens {
+            check_hash(&mut Game::from_fen(fen), 3);
+        }
+    }
+
+    #[test]
+    fn hash_is_the_same_for_transpositions() {
+        let mut a = Game::from_fen(STARTING_POS_FEN);
+        let mut b = Game::from_fen(STARTING_POS_FEN);
+        for fide in ["Nf3", "Nf6", "Nc3", "Nc6"] {
+            a.make_move(a.fide_to_move(fide));
+        }
+        for fide in ["Nc3", "Nc6", "Nf3", "Nf6"] {
+            b.make_move(b.fide_to_move(fide));
+        }
+        assert_eq!(a.hash, b.hash);
+        assert_ne!(a.hash, Game::from_fen(STARTING_POS_FEN).hash);
+    }
+
+    #[test]
+    fn attackers_to_sees_through_removed_pieces() {
+        let game = Game::from_fen("3r2k1/8/8/3p4/8/2N5/3R4/3R2K1 w - - 0 1");
+        let square = |name : &str| Position::from(name.to_string());
+        let d5 = square("d5");
+        let occupied = game.board.occupied();
+        // Attackers of both colors
+        let other_attackers = square("d8").bitboard() | square("c3").bitboard();
+        assert_eq!(game.board.attackers_to(d5, occupied), square("d2").bitboard() | other_attackers);
+        let without_d2 = occupied & !square("d2").bitboard();
+        assert_eq!(game.board.attackers_to(d5, without_d2), square("d1").bitboard() | other_attackers);
+        // The pawn on d5 blocks the black rook until it is removed
+        let d4 = square("d4");
+        assert_eq!(game.board.attackers_to(d4, occupied) & square("d8").bitboard(), 0);
+        assert_eq!(game.board.attackers_to(d4, occupied & !d5.bitboard()) & square("d8").bitboard(), square("d8").bitboard());
+    }
+}
@@ -0,0 +1,298 @@
+use std::sync::Arc;
+
+use crate::{
+    base_types::{Color, PieceType},
+    book::OpeningBook,
+    chess_clock::{ChessClock, TimeControl},
+    endgame::{self, Material},
+    game::Game,
+    pgn::{append_pgn, PgnGame},
+    player::{BotPlayer, ExternalEnginePlayer, GameContext, GameOutcome, HumanPlayer, Player, PlayerAction, RandomPlayer},
+    search::SearchSettings,
+    tablebase::Tablebase,
+    uci_engine::GoLimit,
+    STARTING_POS_FEN,
+};
+
+/**
+ * Who plays a side of a local game.
+ */
+#[derive(Clone)]
+pub enum PlayerKind {
+    Human,
+    Bot(SearchSettings),
+    Random,
+    Engine(String), // Path of a UCI engine
+}
+
+#[derive(Clone)]
+pub struct LaunchSettings {
+    pub white: PlayerKind, // Sides of the first game
+    pub black: PlayerKind,
+    pub engine_limit: GoLimit, // Search limit of external engines in untimed games
+    pub time_control: Option<TimeControl>,
+    pub fen: Option<String>, // Starting position of every game, the current position if not set
+    pub games: usize,
+    pub alternate: bool, // The players switch colours after every game
+    pub pgn_path: String, // All games are appended to this file
+}
+
+impl Default for LaunchSettings {
+    fn default() -> Self {
+        LaunchSettings {
+            white: PlayerKind::Human,
+            black: PlayerKind::Bot(SearchSettings::default()),
+            engine_limit: GoLimit::MoveTime(1000),
+            time_control: None,
+            fen: None,
+            games: 1,
+            alternate: true,
+            pgn_path: "games.pgn".to_string(),
+        }
+    }
+}
+
+/**
+ * Plays one or more local games between any two players and saves them as PGN.
+ */
+pub struct GameLauncher {
+    settings: LaunchSettings,
+    pub book: Option<Arc<OpeningBook>>, // Used by our bot
+    pub tablebase: Option<Arc<Tablebase>>,
+}
+
+impl GameLauncher {
+    pub fn new(settings: LaunchSettings) -> GameLauncher {
+        GameLauncher {
+            settings,
+            book: None,
+            tablebase: None,
+        }
+    }
+
+    fn create_player(&self, kind: &PlayerKind) -> Result<Box<dyn Player>, String> {
+        Ok(match kind {
+            PlayerKind::Human => Box::new(HumanPlayer),
+            PlayerKind::Bot(settings) => {
+                let settings = SearchSettings {
+                    tablebase: settings.tablebase.clone().or(self.tablebase.clone()),
+                    ..settings.clone()
+                };
+                Box::new(BotPlayer::new(settings, self.book.clone()))
+            }
+            PlayerKind::Random => Box::new(RandomPlayer),
+            PlayerKind::Engine(path) => Box::new(ExternalEnginePlayer::new(path, self.settings.engine_limit)?),
+        })
+    }
+
+    /**
+     * Plays all games. Every game starts from the same position, the last one stays on the board.
+     */
+    pub async fn run(&self, game: &mut Game) {
+        let fen = self.settings.fen.clone().unwrap_or_else(|| game.to_fen());
+        let mut players = match (self.create_player(&self.settings.white), self.create_player(&self.settings.black)) {
+            (Ok(white), Ok(black)) => [white, black],
+            (Err(err), _) | (_, Err(err)) => {
+                println!("{}", err);
+                return;
+            }
+        };
+        let names = [players[0].name(), players[1].name()];
+        let mut points = [0.0; 2]; // Of the players in the order they were given
+        let mut swapped = false;
+
+        for round in 1..=self.settings.games {
+            *game = Game::from_fen(&fen);
+            let (white, black) = if swapped { (1, 0) } else { (0, 1) };
+            match &self.settings.time_control {
+                Some(control) => println!("Game {}: {} vs {} with {}", round, names[white], names[black], control),
+                None => println!("Game {}: {} vs {}", round, names[white], names[black]),
+            }
+            let clock = self.settings.time_control.clone().map(ChessClock::new);
+            let (outcome, san_moves) = play_game(game, &mut players, clock).await;
+
+            match outcome.winner {
+                Some(Color::White) => points[white] += 1.0,
+                Some(Color::Black) => points[black] += 1.0,
+                None => {
+                    points[white] += 0.5;
+                    points[black] += 0.5;
+                }
+            }
+            if let Err(err) = self.write_pgn(round, &names[white], &names[black], &fen, san_moves, &outcome) {
+                println!("{}", err);
+            }
+            if self.settings.games > 1 {
+                println!("Score after game {}: {} {} - {} {}", round, names[0], points[0], points[1], names[1]);
+            }
+
+            if self.settings.alternate {
+                players.swap(0, 1);
+                swapped = !swapped;
+            }
+        }
+        println!("Games saved to {}", self.settings.pgn_path);
+    }
+
+    fn write_pgn(&self, round: usize, white: &str, black: &str, fen: &str, san_moves: Vec<String>, outcome: &GameOutcome) -> Result<(), String> {
+        let mut headers = vec![
+            ("Event", "Local game".to_string()),
+            ("Site", "local".to_string()),
+            ("Round", round.to_string()),
+            ("White", white.to_string()),
+            ("Black", black.to_string()),
+            ("Result", outcome.result().to_string()),
+        ];
+        if fen != STARTING_POS_FEN {
+            headers.push(("FEN", fen.to_string()));
+            headers.push(("SetUp", "1".to_string()));
+        }
+        let game = PgnGame {
+            headers: headers.into_iter().map(|(name, value)| (name.to_string(), value)).collect(),
+            moves: san_moves,
+            result: outcome.result().to_string(),
+        };
+        append_pgn(&self.settings.pgn_path, &game.to_pgn(Some(&outcome.reason)))
+    }
+}
+
+/**
+ * Plays a game between the two players, white first, from the current position until it is over.
+ * With a clock a player that runs out of time loses, even while it is still thinking.
+ * Returns the outcome and the moves in SAN.
+ */
+pub async fn play_game(game: &mut Game, players: &mut [Box<dyn Player>], mut clock: Option<ChessClock>) -> (GameOutcome, Vec<String>) {
+    let initial_fen = game.to_fen();
+    let mut san_moves = Vec::new();
+    let mut hashes = vec![game.hash];
+    let mut halfmove_clock = initial_fen.split_whitespace().nth(4).and_then(|clock| clock.parse::<usize>().ok()).unwrap_or(0);
+    let mut contexts = [Color::White, Color::Black].map(|color| GameContext::new(color, &initial_fen));
+    contexts[0].opponent = Some(players[1].name());
+    contexts[1].opponent = Some(players[0].name());
+    for (player, context) in players.iter_mut().zip(&contexts) {
+        player.new_game(context);
+    }
+
+    let outcome = loop {
+        let color = game.turn;
+        let side = if color == Color::White { 0 } else { 1 };
+        if game.get_possible_team_moves(color).is_empty() {
+            if game.king_check != 0 {
+                println!("Checkmate!");
+                break GameOutcome::new(Some(color.opposite()), "checkmate");
+            }
+            println!("Stalemate!");
+            break GameOutcome::new(None, "stalemate");
+        }
+        if let Some(reason) = draw_reason(game, &hashes, halfmove_clock) {
+            break GameOutcome::new(None, reason);
+        }
+
+        if let Some(clock) = clock.as_mut() {
+            for context in contexts.iter_mut() {
+                context.white_clock = Some(clock.clock(Color::White));
+                context.black_clock = Some(clock.clock(Color::Black));
+            }
+            clock.start(color);
+        }
+        let play = players[side].play(game, &contexts[side]);
+        let played = match clock.as_ref().and_then(|clock| clock.time_to_flag()) {
+            Some(time_to_flag) => tokio::select! {
+                played = play => Some(played),
+                _ = tokio::time::sleep(time_to_flag) => None,
+            },
+            None => Some(play.await),
+        };
+        let decision = match played {
+            Some(Ok(decision)) => decision,
+            Some(Err(err)) => {
+                println!("{} failed: {}", players[side].name(), err);
+                break GameOutcome::new(Some(color.opposite()), "failure");
+            }
+            None => break flag_outcome(game, color),
+        };
+        let mov = match decision.action {
+            PlayerAction::Move(mov) => mov,
+            PlayerAction::OfferDraw(mov) => {
+                println!("{:?} offers a draw", color);
+                mov
+            }
+            PlayerAction::AcceptDraw if contexts[side].draw_offered => {
+                println!("{:?} accepts the draw", color);
+                break GameOutcome::new(None, "agreement");
+            }
+            PlayerAction::AcceptDraw => {
+                println!("{:?} accepted a draw that was not offered", color);
+                break GameOutcome::new(Some(color.opposite()), "illegal move");
+            }
+            PlayerAction::Resign => {
+                println!("{:?} resigns", color);
+                break GameOutcome::new(Some(color.opposite()), "resign");
+            }
+        };
+        if clock.as_mut().is_some_and(|clock| !clock.press()) {
+            break flag_outcome(game, color);
+        }
+
+        let san = game.move_to_san(mov);
+        // The move type of a move from a player is not known yet, the board tells if it captures
+        let is_pawn_move = game.board.get_piece(mov.from).is_some_and(|piece| piece.piece_type == PieceType::Pawn);
+        let is_capture = game.board.get_piece(mov.to).is_some();
+        if !game.make_move(mov) {
+            println!("Invalid move {} by {}", mov.to_string(), players[side].name());
+            break GameOutcome::new(Some(color.opposite()), "illegal move");
+        }
+        let score = decision.score.map_or(String::new(), |score| format!(" ({})", score));
+        println!("{:?} plays {}{}", color, san, score);
+        san_moves.push(san);
+        halfmove_clock = if is_pawn_move || is_capture { 0 } else { halfmove_clock + 1 };
+        hashes.push(game.hash);
+        game.board.print();
+        if let Some(clock) = &clock {
+            println!("{}", clock);
+        }
+
+        for context in contexts.iter_mut() {
+            context.moves.push(mov.to_string());
+            context.draw_offered = false;
+        }
+        contexts[1 - side].draw_offered = matches!(decision.action, PlayerAction::OfferDraw(_));
+        players[1 - side].opponent_moved(mov, &contexts[1 - side]);
+    };
+
+    match outcome.winner {
+        Some(winner) => println!("Winner: {:?} by {}", winner, outcome.reason),
+        None => println!("Draw by {}", outcome.reason),
+    }
+    for player in players.iter_mut() {
+        player.game_over(&outcome);
+    }
+    (outcome, san_moves)
+}
+
+/**
+ * Draws by the rules: fifty moves without a capture or pawn move, threefold repetition and insufficient material.
+ */
+fn draw_reason(game: &Game, hashes: &[u64], halfmove_clock: usize) -> Option<&'static str> {
+    if halfmove_clock >= 100 {
+        Some("fifty move rule")
+    } else if hashes.iter().filter(|hash| **hash == game.hash).count() >= 3 {
+        Some("threefold repetition")
+    } else if Material::new(game).is_insufficient() {
+        Some("insufficient material")
+    } else {
+        None
+    }
+}
+
+/**
+ * The side ran out of time. It loses, unless the opponent can't mate by any sequence of legal moves.
+ */
+fn flag_outcome(game: &Game, color: Color) -> GameOutcome {
+    println!("{:?} ran out of time", color);
+    if endgame::can_helpmate(game, &Material::new(game), color.opposite()) {
+        GameOutcome::new(Some(color.opposite()), "timeout")
+    } else {
+        GameOutcome::new(None, "timeout vs insufficient material")
+    }
+}
@@ -0,0 +1,89 @@
+use std::collections::VecDeque;
+use std::io;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+struct Input {
+    receiver: Receiver<String>,
+    pending: VecDeque<String>, // Lines that were polled but handed back
+}
+
+static INPUT: OnceLock<Mutex<Input>> = OnceLock::new();
+// How often the async read checks for a new line
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/**
+ * All terminal input goes through a single thread reading stdin line by line.
+ * This way the input can be polled without blocking, e.g. to stop a running search.
+ */
+fn input() -> &'static Mutex<Input> {
+    INPUT.get_or_init(|| {
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || loop {
+            let mut line = String::new();
+            match io::stdin().read_line(&mut line) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {
+                    if sender.send(line).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+        Mutex::new(Input {
+            receiver,
+            pending: VecDeque::new(),
+        })
+    })
+}
+
+/**
+ * Blocks until a line is entered. Returns an empty string when stdin is closed.
+ */
+pub fn read_line() -> String {
+    let mut input = input().lock().unwrap();
+    if let Some(line) = input.pending.pop_front() {
+        return line;
+    }
+    input.receiver.recv().unwrap_or_default()
+}
+
+/**
+ * Waits until a line is entered without blocking the async runtime. Unlike a blocking read on another
+ * thread, dropping the future does not swallow the next line. Returns an empty string when stdin is closed.
+ */
+pub async fn read_line_async() -> String {
+    loop {
+        {
+            let mut input = input().lock().unwrap();
+            if let Some(line) = input.pending.pop_front() {
+                return line;
+            }
+            match input.receiver.try_recv() {
+                Ok(line) => return line,
+                Err(TryRecvError::Disconnected) => return String::new(),
+                Err(TryRecvError::Empty) => {}
+            }
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+/**
+ * Returns the next entered line if there is one, without blocking.
+ */
+pub fn try_read_line() -> Option<String> {
+    let mut input = input().lock().unwrap();
+    if let Some(line) = input.pending.pop_front() {
+        return Some(line);
+    }
+    input.receiver.try_recv().ok()
+}
+
+/**
+ * Hands a polled line back, so the next read returns it again.
+ */
+pub fn unread_line(line: String) {
+    input().lock().unwrap().pending.push_front(line);
+}
@@ -1,207 +1,933 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
 use dotenv::dotenv;
 
 use serde::Deserialize;
 use serde::Serialize;
+use tokio::task::JoinHandle;
 
 use crate::base_types::Color;
-use crate::base_types::Position;
+use crate::book::OpeningBook;
+use crate::bot_behaviour::BotBehaviour;
+use crate::bot_behaviour::GameTracker;
+use crate::challenge_policy::ChallengePolicy;
 use crate::game::Game;
 use crate::moves::Move;
-use crate::player::BotPlayer;
-use crate::player::Player;
+use crate::player::{BotPlayer, Clock, GameContext, GameOutcome, Player, PlayerAction};
+use crate::search::SearchSettings;
+use crate::tablebase::Tablebase;
+use crate::STARTING_POS_FEN;
 
-pub struct Lichess<'a> {
+/**
+ * Authenticated connection to the Lichess API, shared by the bot and the board mode.
+ */
+#[derive(Clone)]
+pub struct LichessClient {
+    base_url: String,
     auth: String,
     client: reqwest::Client,
-    game: &'a mut Game,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
-struct PlayerData {
-    id: String,
-    username: String,
-    title: String,
+/**
+ * Lichess bot client. It is cheap to clone, every running game gets its own copy.
+ */
+#[derive(Clone)]
+pub struct Lichess {
+    client: LichessClient,
+    bot_id: String,
+    pub settings: SearchSettings,
+    pub ponder: bool, // Keep searching the expected reply while the opponent thinks
+    pub policy: ChallengePolicy,
+    pub behaviour: BotBehaviour,
+    pub book: Option<Arc<OpeningBook>>,
+}
+
+/**
+ * A game the bot (or the user in board mode) is playing.
+ */
+pub struct GameSession {
+    pub id: String,
+    pub initial_fen: String,
+    pub game: Game,
+    pub moves: Vec<String>, // Moves in UCI notation applied to the game since the initial position
+    pub color: Option<Color>, // Known after the gameFull event
+    pub moved_at_ply: Option<usize>, // Number of moves played when we sent our last move
+    pub opponent: Option<String>,
+    pub opponent_rating: Option<u32>,
+    pub last_state: Option<State>, // Clocks and offers of the latest game state
+    latency: Duration, // Average round trip time of sending a move
+    rated: bool,
+    hashes: Vec<u64>, // Hashes of all positions of the game, to find repetitions
+    tracker: GameTracker,
+    greeted: bool,
+    answered_at_ply: Option<usize>, // Number of moves played when we last answered a draw or takeback offer
+}
+
+pub enum SyncChange {
+    Unchanged,
+    Appended,
+    Rebuilt, // The moves were not a continuation of the known moves, e.g. after a takeback
+}
+
+impl GameSession {
+    pub fn new(id: String) -> GameSession {
+        let mut session = GameSession {
+            id,
+            initial_fen: STARTING_POS_FEN.to_string(),
+            game: Game::from_fen(STARTING_POS_FEN),
+            moves: Vec::new(),
+            color: None,
+            moved_at_ply: None,
+            opponent: None,
+            opponent_rating: None,
+            last_state: None,
+            latency: INITIAL_LATENCY,
+            rated: false,
+            hashes: Vec::new(),
+            tracker: GameTracker::default(),
+            greeted: false,
+            answered_at_ply: None,
+        };
+        session.reset(None);
+        session
+    }
+
+    /**
+     * Brings the game to the position after the given moves from the initial position.
+     * If the known moves are the start of the given ones only the new moves are played,
+     * otherwise the game is rebuilt from the initial position. Every move is checked to be legal.
+     */
+    pub fn sync(&mut self, moves: &[&str]) -> Result<SyncChange, String> {
+        let is_continuation = self.moves.len() <= moves.len()
+            && self.moves.iter().zip(moves).all(|(known, new)| known == new);
+        let mut change = SyncChange::Unchanged;
+        if !is_continuation {
+            self.reset(None);
+            change = SyncChange::Rebuilt;
+        }
+
+        for (ply, move_str) in moves.iter().enumerate().skip(self.moves.len()) {
+            let mov = Move::parse(move_str).ok_or(format!("Invalid move {} at ply {}", move_str, ply + 1))?;
+            if !self.game.make_move(mov) {
+                return Err(format!("Illegal move {} at ply {}", move_str, ply + 1));
+            }
+            self.moves.push(move_str.to_string());
+            self.hashes.push(self.game.hash);
+            if let SyncChange::Unchanged = change {
+                change = SyncChange::Appended;
+            }
+        }
+        Ok(change)
+    }
+
+    /**
+     * Goes back to the initial position, optionally replacing it.
+     */
+    pub fn reset(&mut self, initial_fen: Option<String>) {
+        if let Some(initial_fen) = initial_fen {
+            self.initial_fen = initial_fen;
+        }
+        self.game = Game::from_fen(&self.initial_fen);
+        self.moves.clear();
+        self.hashes = vec![self.game.hash];
+    }
+
+    /**
+     * Sets up the game from the gameFull event. `user_id` is the account we play with.
+     */
+    pub fn start(&mut self, white: &GamePlayer, black: &GamePlayer, initial_fen: String, user_id: &str) {
+        let initial_fen = if initial_fen == "startpos" { STARTING_POS_FEN.to_string() } else { initial_fen };
+        if initial_fen != self.initial_fen {
+            self.reset(Some(initial_fen));
+        }
+        self.color = if white.id.as_deref() == Some(user_id) {
+            Some(Color::White)
+        } else if black.id.as_deref() == Some(user_id) {
+            Some(Color::Black)
+        } else {
+            None
+        };
+        let opponent = if self.color == Some(Color::White) { black } else { white };
+        self.opponent = opponent.name.clone();
+        self.opponent_rating = opponent.rating;
+        println!("Game {}: {:?} vs {:?}, playing {:?}", self.id, white.name, black.name, self.color);
+    }
+
+    /**
+     * Returns the outcome and prints the result if the game ended.
+     */
+    pub fn outcome(&self, state: &State) -> Option<GameOutcome> {
+        if state.status == "started" || state.status == "created" {
+            return None;
+        }
+        let winner = match state.winner.as_deref() {
+            Some("white") => Some(Color::White),
+            Some("black") => Some(Color::Black),
+            _ => None,
+        };
+        let result = match (winner, self.color) {
+            (None, _) => "no winner".to_string(),
+            (Some(winner), Some(color)) if winner == color => "we won".to_string(),
+            (Some(_), Some(_)) => "we lost".to_string(),
+            (Some(winner), None) => format!("{:?} won", winner),
+        };
+        println!("Game {} ended by {}: {}", self.id, state.status, result);
+        Some(GameOutcome::new(winner, &state.status))
+    }
+
+    /**
+     * What our player needs to know about the game besides the position.
+     */
+    pub fn context(&self) -> GameContext {
+        let mut context = GameContext::new(self.color.unwrap_or(self.game.turn), &self.initial_fen);
+        context.moves = self.moves.clone();
+        context.overhead = self.latency.as_millis() as u64 + SAFETY_MARGIN;
+        context.opponent = self.opponent.clone();
+        context.opponent_rating = self.opponent_rating;
+        if let Some(state) = &self.last_state {
+            let clock = |time: Option<u64>, increment: Option<u64>| {
+                time.map(|time_left| Clock {
+                    time_left,
+                    increment: increment.unwrap_or(0),
+                    moves_to_go: None,
+                })
+            };
+            context.white_clock = clock(state.wtime, state.winc);
+            context.black_clock = clock(state.btime, state.binc);
+            context.draw_offered = self.opponent_offers(state).0;
+        }
+        context
+    }
+
+    /**
+     * Whether the current position already occurred earlier in the game.
+     */
+    fn is_repetition(&self) -> bool {
+        match self.hashes.split_last() {
+            Some((current, earlier)) => earlier.contains(current),
+            None => false,
+        }
+    }
+
+    /**
+     * Whether the opponent currently offers a draw and asks for a takeback.
+     */
+    fn opponent_offers(&self, state: &State) -> (bool, bool) {
+        match self.color {
+            Some(Color::White) => (state.bdraw, state.btakeback),
+            Some(Color::Black) => (state.wdraw, state.wtakeback),
+            None => (false, false),
+        }
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug)]
+pub struct PlayerData {
+    pub id: String,
+    pub username: String,
+    pub title: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct State {
-    #[serde(rename = "type")]
-    state_type: String,
-    moves: String,
-    status: String,
+    pub moves: String,
+    pub status: String,
+    pub winner: Option<String>,
+    // Clock times and increments in milliseconds. Not set for unlimited games
+    wtime: Option<u64>,
+    btime: Option<u64>,
+    winc: Option<u64>,
+    binc: Option<u64>,
+    // Pending draw offers and takeback requests
+    #[serde(default)]
+    wdraw: bool,
+    #[serde(default)]
+    bdraw: bool,
+    #[serde(default)]
+    wtakeback: bool,
+    #[serde(default)]
+    btakeback: bool,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct Challenge {
-    id: String,
+    pub id: String,
     #[serde(rename = "finalColor")]
-    final_color: String,
+    pub final_color: Option<String>,
+    pub challenger: Option<ChallengeUser>,
+    #[serde(default)]
+    pub rated: bool,
+    #[serde(default)]
+    pub variant: Variant,
+    #[serde(rename = "timeControl", default)]
+    pub time_control: TimeControl,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ChallengeUser {
+    pub name: String,
+    pub rating: Option<u32>,
+    pub title: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct Variant {
+    pub key: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct TimeControl {
+    #[serde(rename = "type")]
+    pub control_type: String, // clock, correspondence or unlimited
+    pub limit: Option<u64>, // Initial time in seconds
+    pub increment: Option<u64>,
 }
 
-pub struct LichessPlayer;
+#[derive(Deserialize, Serialize, Debug)]
+pub struct GameEventInfo {
+    #[serde(rename = "gameId")]
+    pub game_id: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct GamePlayer {
+    pub id: Option<String>, // Not set for the Lichess AI
+    pub name: Option<String>,
+    pub rating: Option<u32>,
+}
+
+/**
+ * Events of the incoming event stream.
+ */
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Event {
+    Challenge { challenge: Challenge },
+    ChallengeCanceled { challenge: Challenge },
+    ChallengeDeclined { challenge: Challenge },
+    GameStart { game: GameEventInfo },
+    GameFinish { game: GameEventInfo },
+    #[serde(other)]
+    Unknown,
+}
+
+/**
+ * Events of a game stream.
+ */
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum GameEvent {
+    GameFull {
+        white: GamePlayer,
+        black: GamePlayer,
+        #[serde(rename = "initialFen")]
+        initial_fen: String,
+        #[serde(default)]
+        rated: bool,
+        state: State,
+    },
+    GameState(State),
+    ChatLine {
+        username: String,
+        text: String,
+        room: String,
+    },
+    OpponentGone {
+        gone: bool,
+        #[serde(rename = "claimWinInSeconds")]
+        claim_win_in_seconds: Option<u64>,
+    },
+    #[serde(other)]
+    Unknown,
+}
+
+/**
+ * Splits a streamed response into newline delimited JSON values. A chunk can end in the middle of a line,
+ * so the rest is kept until the next chunk arrives. Empty keep alive lines are skipped.
+ */
+pub struct NdjsonReader {
+    buffer: Vec<u8>,
+}
+
+impl NdjsonReader {
+    pub fn new() -> NdjsonReader {
+        NdjsonReader { buffer: Vec::new() }
+    }
+
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<serde_json::Value> {
+        self.buffer.extend_from_slice(chunk);
+        let mut values = Vec::new();
+        while let Some(end) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line = self.buffer.drain(..=end).collect::<Vec<u8>>();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            match serde_json::from_str(line) {
+                Ok(value) => values.push(value),
+                Err(err) => println!("Skipping malformed line \"{}\": {}", line, err),
+            }
+        }
+        values
+    }
+}
+
+/**
+ * Exponential backoff for reconnecting, from one second up to a minute.
+ */
+pub struct Backoff {
+    delay: Duration,
+}
+
+impl Backoff {
+    pub fn new() -> Backoff {
+        Backoff { delay: RECONNECT_DELAY }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Backoff::new();
+    }
 
-impl Player for LichessPlayer {
-    fn play(&self, _: &mut Game) -> Move {
-        Move::new(Position::new(0), Position::new(0))
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.delay;
+        self.delay = (self.delay * 2).min(Duration::from_secs(60));
+        delay
     }
 }
 
-static BASE_URL: &str = "https://lichess.org/api";
+// API used when LICHESS_URL is not set
+static DEFAULT_BASE_URL: &str = "https://lichess.org/api";
+// Policy file used when LICHESS_POLICY is not set
+static DEFAULT_POLICY_PATH: &str = "lichess_policy.json";
+// Behaviour file used when LICHESS_BEHAVIOUR is not set
+static DEFAULT_BEHAVIOUR_PATH: &str = "lichess_behaviour.json";
+// Attempts to reconnect to a game stream without getting a new game state before the game is given up
+pub const MAX_GAME_RECONNECTS: u32 = 8;
+// First delay of reconnecting, it doubles with every attempt. The mock server in the tests doesn't need to wait
+const RECONNECT_DELAY: Duration = if cfg!(test) { Duration::from_millis(1) } else { Duration::from_secs(1) };
+// Assumed round trip time until the first move was sent
+const INITIAL_LATENCY: Duration = Duration::from_millis(200);
+// Milliseconds kept in reserve on top of the latency
+const SAFETY_MARGIN: u64 = 50;
 
-impl<'a> Lichess<'a> {
-    pub fn new(game: &'a mut Game) -> Lichess<'a> {
+impl LichessClient {
+    /**
+     * Client configured from the environment (or a .env file). The token is read from the given variable,
+     * the API url from LICHESS_URL if it is set.
+     */
+    pub fn from_env(token_var: &str) -> LichessClient {
         dotenv().ok();
-        let auth =
-            "Bearer ".to_owned() + &std::env::var("LICHESS_TOK").expect("LICHESS_TOK not set");
+        let token = std::env::var(token_var).unwrap_or_else(|_| panic!("{} not set", token_var));
+        let base_url = std::env::var("LICHESS_URL").unwrap_or_else(|_| DEFAULT_BASE_URL.to_string());
+        LichessClient::with_server(&base_url, &token)
+    }
 
-        Lichess {
-            auth,
+    /**
+     * Client talking to the API at the given url, e.g. "https://lichess.org/api".
+     */
+    pub fn with_server(base_url: &str, token: &str) -> LichessClient {
+        LichessClient {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            auth: "Bearer ".to_owned() + token,
             client: reqwest::Client::new(),
-            game,
         }
     }
 
-    pub async fn get_account(&self) -> Result<(), reqwest::Error> {
-        let url = BASE_URL.to_string() + "/account";
+    pub async fn get_account(&self) -> Result<PlayerData, reqwest::Error> {
+        let url = self.base_url.clone() + "/account";
         let response = self
             .client
             .get(url)
             .header("Authorization", self.auth.clone())
             .send()
-            .await?;
+            .await?
+            .error_for_status()?;
 
-        let result = response.text().await?;
+        let account: PlayerData = response.json().await?;
+        println!("Logged in as {} ({:?})", account.username, account.title);
+        Ok(account)
+    }
 
-        let json: PlayerData = serde_json::from_str(result.as_str()).unwrap();
-        println!("{:?}", json);
-        Ok(())
+    pub async fn open_stream(&self, path: &str) -> Result<reqwest::Response, reqwest::Error> {
+        self.client
+            .get(self.base_url.clone() + path)
+            .header("Authorization", self.auth.clone())
+            .send()
+            .await?
+            .error_for_status()
     }
 
-    pub async fn get_challenge(&self) -> Result<Challenge, String> {
-        let url = BASE_URL.to_string() + "/stream/event";
-        let mut response = self
+    pub async fn post(&self, path: &str, form: &[(&str, &str)]) -> Result<(), reqwest::Error> {
+        let response = self
             .client
-            .get(url)
+            .post(self.base_url.clone() + path)
             .header("Authorization", self.auth.clone())
+            .form(form)
             .send()
-            .await
-            .expect("Failed to send request");
+            .await?;
+        if let Err(err) = response.error_for_status_ref() {
+            println!("Request to {} failed: {}", path, response.text().await.unwrap_or_default());
+            return Err(err);
+        }
+        Ok(())
+    }
 
-        while let Some(chunk) = response.chunk().await.expect("Failed to read chunk") {
-            let chunk = std::str::from_utf8(&chunk).unwrap();
+    pub async fn accept_challenge(&self, id: &str) -> Result<(), reqwest::Error> {
+        self.post(&format!("/challenge/{}/accept", id), &[]).await
+    }
 
-            println!("Challenge chunk: {}", chunk);
+    pub async fn decline_challenge(&self, id: &str, reason: &str) -> Result<(), reqwest::Error> {
+        self.post(&format!("/challenge/{}/decline", id), &[("reason", reason)]).await
+    }
+}
 
-            if chunk.len() < 5 {
-                // We need some text for json
-                continue;
+impl Lichess {
+    /**
+     * Bot configured from the environment (or a .env file). LICHESS_TOK is required,
+     * LICHESS_URL, LICHESS_POLICY and LICHESS_BEHAVIOUR are optional.
+     */
+    pub fn new() -> Lichess {
+        let mut lichess = Lichess::with_client(LichessClient::from_env("LICHESS_TOK"));
+        lichess.policy = load_config("LICHESS_POLICY", DEFAULT_POLICY_PATH, ChallengePolicy::load);
+        lichess.behaviour = load_config("LICHESS_BEHAVIOUR", DEFAULT_BEHAVIOUR_PATH, BotBehaviour::load);
+        if let Some(path) = &lichess.behaviour.book {
+            match OpeningBook::load(path) {
+                Ok(mut book) => {
+                    book.max_ply = lichess.behaviour.book_max_ply;
+                    book.selection = lichess.behaviour.book_selection;
+                    lichess.book = Some(Arc::new(book));
+                }
+                Err(err) => println!("{}. Playing without a book", err),
+            }
+        }
+        if let Some(path) = &lichess.behaviour.tablebase {
+            match Tablebase::load(path) {
+                Ok(tablebase) => lichess.settings.tablebase = Some(Arc::new(tablebase)),
+                Err(err) => println!("{}. Playing without a tablebase", err),
+            }
+        }
+        lichess
+    }
+
+    /**
+     * Bot with the default configs.
+     */
+    pub fn with_client(client: LichessClient) -> Lichess {
+        Lichess {
+            client,
+            bot_id: String::new(),
+            settings: SearchSettings::default(),
+            ponder: true,
+            policy: ChallengePolicy::default(),
+            behaviour: BotBehaviour::default(),
+            book: None,
+        }
+    }
+
+    /**
+     * Sends a message to all configured chat rooms of a game.
+     */
+    async fn chat(&self, game_id: &str, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        for room in &self.behaviour.chat_rooms {
+            let path = format!("/bot/game/{}/chat", game_id);
+            if let Err(err) = self.client.post(&path, &[("room", room.as_str()), ("text", text)]).await {
+                println!("Failed to chat in game {}: {}", game_id, err);
             }
+        }
+    }
+
+    /**
+     * Runs the bot until Ctrl-C is pressed.
+     */
+    pub async fn run(&mut self) {
+        self.run_until(async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await
+    }
+
+    /**
+     * Runs the bot until the shutdown future completes. Challenges get accepted and every started game is played
+     * in its own task. The event stream is reconnected when the connection drops.
+     * On shutdown no new games get accepted, but the running ones are played to the end.
+     */
+    pub async fn run_until(&mut self, shutdown: impl Future<Output = ()>) {
+        match self.client.get_account().await {
+            Ok(account) => self.bot_id = account.id,
+            Err(err) => {
+                println!("Failed to get account: {}", err);
+                return;
+            }
+        }
+
+        let mut games: HashMap<String, JoinHandle<()>> = HashMap::new();
+        let mut backoff = Backoff::new();
+        tokio::pin!(shutdown);
 
-            let json: serde_json::Value = serde_json::from_str(chunk).unwrap();
-            if json.is_object() {
-                let event = json.get("type").unwrap().as_str().unwrap();
-                if event == "challenge" {
-                    let challenge: Challenge =
-                        serde_json::from_value(json.get("challenge").unwrap().clone()).unwrap();
-                    // Accept challenge
-                    self.client
-                        .post(BASE_URL.to_string() + "/challenge/" + &challenge.id + "/accept")
-                        .header("Authorization", self.auth.clone())
-                        .send()
-                        .await
-                        .expect("Failed to accept challenge");
-
-                    //let accept_text = accept_response.text().await.expect("Failed to read response");
-                    println!("Accept response: {:?}", challenge);
-                    return Ok(challenge);
+        'connection: loop {
+            match self.client.open_stream("/stream/event").await {
+                Ok(mut response) => {
+                    println!("Connected to the event stream");
+                    let mut reader = NdjsonReader::new();
+                    loop {
+                        let chunk = tokio::select! {
+                            _ = &mut shutdown => break 'connection,
+                            chunk = response.chunk() => chunk,
+                        };
+                        match chunk {
+                            Ok(Some(bytes)) => {
+                                // Events or keep alive lines, the connection works
+                                backoff.reset();
+                                for value in reader.push(&bytes) {
+                                    self.handle_event(value, &mut games).await;
+                                }
+                            }
+                            Ok(None) => {
+                                println!("Event stream closed");
+                                break;
+                            }
+                            Err(err) => {
+                                println!("Event stream failed: {}", err);
+                                break;
+                            }
+                        }
+                    }
                 }
-                continue;
+                Err(err) => println!("Failed to connect to the event stream: {}", err),
+            }
+
+            let delay = backoff.next_delay();
+            println!("Reconnecting in {}s", delay.as_secs());
+            tokio::select! {
+                _ = &mut shutdown => break 'connection,
+                _ = tokio::time::sleep(delay) => {}
             }
+        }
 
-            return Err("No challenge".to_string());
+        games.retain(|_, handle| !handle.is_finished());
+        println!("Shutting down. Waiting for {} running games to finish", games.len());
+        for (_, handle) in games {
+            let _ = handle.await;
         }
-        Err("Cant connect to event stream".to_string())
     }
 
-    pub async fn stream_game(&mut self, chal: Challenge) -> Result<(), ()> {
-        let url = BASE_URL.to_string() + "/bot/game/stream/" + &chal.id;
-        let mut response = self
-            .client
-            .get(url)
-            .header("Authorization", self.auth.clone())
-            .send()
-            .await
-            .expect("Failed to send request");
+    async fn handle_event(&self, value: serde_json::Value, games: &mut HashMap<String, JoinHandle<()>>) {
+        games.retain(|_, handle| !handle.is_finished());
 
-        let challenger_team = if chal.final_color == "white" {
-            Color::White
-        } else {
-            Color::Black
-        };
-        let player_white: &dyn Player = if let Color::White = challenger_team {
-            &LichessPlayer
-        } else {
-            &BotPlayer
-        };
-        let player_black: &dyn Player = if let Color::Black = challenger_team {
-            &LichessPlayer
-        } else {
-            &BotPlayer
+        let event: Event = match serde_json::from_value(value.clone()) {
+            Ok(event) => event,
+            Err(err) => {
+                println!("Failed to read event {}: {}", value, err);
+                return;
+            }
         };
 
-        let mut current_player: &dyn Player;
-        loop {
-            if let Some(chunk) = response.chunk().await.expect("Failed to read game chunk") {
-                println!("Game chunk: {}", std::str::from_utf8(&chunk).unwrap());
-                let chunk = std::str::from_utf8(&chunk).unwrap();
-                if chunk.len() < 5 {
-                    // We need some text for json
-                    continue;
-                }
-                let chunk_json: serde_json::Value = serde_json::from_str(chunk).unwrap();
-                if chunk_json.is_object() {
-                    let state: State;
-                    if chunk_json.get("state").is_some() {
-                        state = serde_json::from_value(chunk_json.get("state").unwrap().clone())
-                            .unwrap();
-                    } else {
-                        state = serde_json::from_value(chunk_json.clone()).unwrap();
+        match event {
+            Event::Challenge { challenge } => {
+                println!("Challenge {} (color {:?})", challenge.id, challenge.final_color);
+                let result = match self.policy.check(&challenge, games.len()) {
+                    Some(reason) => {
+                        println!("Declining challenge {}: {}", challenge.id, reason);
+                        self.client.decline_challenge(&challenge.id, reason.as_str()).await
                     }
+                    None => self.client.accept_challenge(&challenge.id).await,
+                };
+                if let Err(err) = result {
+                    println!("Failed to answer challenge {}: {}", challenge.id, err);
+                }
+            }
+            Event::ChallengeCanceled { challenge } => println!("Challenge {} canceled", challenge.id),
+            Event::ChallengeDeclined { challenge } => println!("Challenge {} declined", challenge.id),
+            Event::GameStart { game } => {
+                if games.contains_key(&game.game_id) {
+                    return;
+                }
+                println!("Game {} started", game.game_id);
+                let lichess = self.clone();
+                let id = game.game_id.clone();
+                games.insert(game.game_id, tokio::spawn(async move { lichess.play_game(id).await }));
+            }
+            Event::GameFinish { game } => println!("Game {} finished", game.game_id),
+            Event::Unknown => {}
+        }
+    }
 
-                    let moves: Vec<&str> = state.moves.split_whitespace().collect();
-                    if let Some(last) = moves.last() {
-                        self.game.make_move(Move::from_string(*last));
-                        self.game.board.print();
-                    }
+    /**
+     * Streams a game and plays our moves until it is over.
+     */
+    async fn play_game(self, id: String) {
+        let mut session = GameSession::new(id);
+        let mut player = BotPlayer::new(self.settings.clone(), self.book.clone());
+        player.ponder = self.ponder;
+        let mut backoff = Backoff::new();
+        // A stream that gets accepted and closed straight away must not be retried forever
+        let mut reconnects = 0;
 
-                    match self.game.turn {
-                        Color::White => current_player = player_white,
-                        Color::Black => current_player = player_black,
+        'connection: loop {
+            match self.client.open_stream(&format!("/bot/game/stream/{}", session.id)).await {
+                Ok(mut response) => {
+                    let mut reader = NdjsonReader::new();
+                    loop {
+                        match response.chunk().await {
+                            Ok(Some(bytes)) => {
+                                for value in reader.push(&bytes) {
+                                    let is_state = is_game_state(&value);
+                                    if self.handle_game_event(&mut session, &mut player, value).await {
+                                        break 'connection;
+                                    }
+                                    if is_state {
+                                        reconnects = 0;
+                                        backoff.reset();
+                                    }
+                                }
+                            }
+                            Ok(None) => break,
+                            Err(err) => {
+                                println!("Game {} stream failed: {}", session.id, err);
+                                break;
+                            }
+                        }
                     }
+                }
+                Err(err) => println!("Failed to connect to game {}: {}", session.id, err),
+            }
+            if reconnects == MAX_GAME_RECONNECTS {
+                player.game_over(&GameOutcome::new(None, "lost connection"));
+                break;
+            }
+            reconnects += 1;
+            tokio::time::sleep(backoff.next_delay()).await;
+        }
+        println!("Game {} over", session.id);
+    }
 
-                    if self.game.turn == challenger_team {
-                        continue;
-                    }
+    /**
+     * Returns true when the game is over.
+     */
+    async fn handle_game_event(&self, session: &mut GameSession, player: &mut BotPlayer, value: serde_json::Value) -> bool {
+        let event: GameEvent = match serde_json::from_value(value.clone()) {
+            Ok(event) => event,
+            Err(err) => {
+                println!("Failed to read game event {}: {}", value, err);
+                return false;
+            }
+        };
+
+        match event {
+            GameEvent::GameFull { white, black, initial_fen, rated, state } => {
+                session.start(&white, &black, initial_fen, &self.bot_id);
+                session.rated = rated;
+                if !session.greeted && session.color.is_some() {
+                    session.greeted = true;
+                    player.new_game(&session.context());
+                    self.chat(&session.id, &self.behaviour.greeting).await;
+                }
+                self.handle_state(session, player, state).await
+            }
+            GameEvent::GameState(state) => self.handle_state(session, player, state).await,
+            GameEvent::ChatLine { username, text, room } => {
+                println!("Game {} [{}] {}: {}", session.id, room, username, text);
+                false
+            }
+            GameEvent::OpponentGone { gone, claim_win_in_seconds } => {
+                if gone {
+                    println!("Game {}: opponent gone, can claim win in {:?}s", session.id, claim_win_in_seconds);
+                } else {
+                    println!("Game {}: opponent is back", session.id);
+                }
+                false
+            }
+            GameEvent::Unknown => false,
+        }
+    }
 
-                    let move_ = current_player.play(self.game);
-                    if !move_.is_valid() {
-                        println!("No more moves to make. Game over");
-                        break;
+    async fn handle_state(&self, session: &mut GameSession, player: &mut BotPlayer, state: State) -> bool {
+        session.last_state = Some(state.clone());
+        let moves: Vec<&str> = state.moves.split_whitespace().collect();
+        match session.sync(&moves) {
+            Ok(SyncChange::Unchanged) => {}
+            Ok(change) => {
+                if let SyncChange::Rebuilt = change {
+                    println!("Game {}: rebuilt position from {} moves", session.id, moves.len());
+                    session.moved_at_ply = None;
+                }
+                session.game.board.print();
+                // The last move was the one of the opponent if it is our turn now
+                let last_move = moves.last().and_then(|m| Move::parse(m));
+                if let (Some(last_move), true) = (last_move, session.color == Some(session.game.turn)) {
+                    player.opponent_moved(last_move, &session.context());
+                }
+            }
+            Err(err) => {
+                println!("Game {} out of sync: {}", session.id, err);
+                return false;
+            }
+        }
+
+        if let Some(outcome) = session.outcome(&state) {
+            player.game_over(&outcome);
+            if session.color.is_some() {
+                self.chat(&session.id, &self.behaviour.goodbye).await;
+            }
+            return true;
+        }
+
+        if self.answer_offers(session, &state).await {
+            return false;
+        }
+
+        // Duplicate events of the same position must not make us move twice
+        if session.color != Some(session.game.turn) || session.moved_at_ply == Some(session.moves.len()) {
+            return false;
+        }
+
+        let context = session.context();
+        let decision = match player.play(&session.game, &context).await {
+            Ok(decision) => decision,
+            Err(err) => {
+                println!("Game {}: {}. Game over", session.id, err);
+                player.game_over(&GameOutcome::new(None, &err));
+                return true;
+            }
+        };
+
+        // Book moves have no score, the behaviour only judges searched moves
+        let mut action = decision.action;
+        if let Some(score) = decision.score {
+            self.behaviour.record_score(&mut session.tracker, score);
+            let repetition = session.is_repetition();
+            if self.behaviour.should_resign(&session.tracker) {
+                println!("Game {}: resigning at {}", session.id, score);
+                action = PlayerAction::Resign;
+            } else if let PlayerAction::Move(mov) = action {
+                if self.behaviour.should_offer_draw(&mut session.tracker, session.moves.len(), repetition) {
+                    println!("Game {}: offering a draw at {}", session.id, score);
+                    action = PlayerAction::OfferDraw(mov);
+                }
+            }
+        }
+
+        match action {
+            PlayerAction::Move(mov) | PlayerAction::OfferDraw(mov) => {
+                if let PlayerAction::OfferDraw(_) = action {
+                    if let Err(err) = self.client.post(&format!("/bot/game/{}/draw/yes", session.id), &[]).await {
+                        println!("Failed to offer a draw: {}", err);
                     }
-                    let move_str = move_.to_string();
-                    println!("Move: {}", move_str);
-                    let url = BASE_URL.to_string() + "/bot/game/" + &chal.id + "/move/" + &move_str;
-                    self.client
-                        .post(url)
-                        .header("Authorization", self.auth.clone())
-                        .send()
-                        .await
-                        .expect("Failed to send move");
                 }
-            } else {
-                break;
+                let kind = if decision.score.is_some() { "move" } else { "book move" };
+                println!("Game {} {}: {}", session.id, kind, mov.to_string());
+                self.send_move(session, mov).await;
+            }
+            PlayerAction::AcceptDraw => {
+                if let Err(err) = self.client.post(&format!("/bot/game/{}/draw/yes", session.id), &[]).await {
+                    println!("Failed to accept the draw: {}", err);
+                }
+            }
+            PlayerAction::Resign => {
+                if let Err(err) = self.client.post(&format!("/bot/game/{}/resign", session.id), &[]).await {
+                    println!("Failed to resign: {}", err);
+                }
             }
         }
-        Ok(())
+        false
+    }
+
+    async fn send_move(&self, session: &mut GameSession, mov: Move) {
+        let sent_at = Instant::now();
+        if let Err(err) = self.client.post(&format!("/bot/game/{}/move/{}", session.id, mov.to_string()), &[]).await {
+            println!("Failed to send move: {}", err);
+            return;
+        }
+        session.latency = (session.latency * 3 + sent_at.elapsed()) / 4;
+        session.moved_at_ply = Some(session.moves.len());
+    }
+
+    /**
+     * Answers draw offers and takeback requests of the opponent, once per position.
+     * Returns true if one was accepted, the game then continues with the next state.
+     */
+    async fn answer_offers(&self, session: &mut GameSession, state: &State) -> bool {
+        let (draw_offered, takeback_asked) = session.opponent_offers(state);
+        if !(draw_offered || takeback_asked) || session.answered_at_ply == Some(session.moves.len()) {
+            return false;
+        }
+        session.answered_at_ply = Some(session.moves.len());
+
+        if takeback_asked {
+            let accept = self.behaviour.accept_takeback(&mut session.tracker, session.rated);
+            println!("Game {}: {} takeback", session.id, if accept { "accepting" } else { "declining" });
+            let path = format!("/bot/game/{}/takeback/{}", session.id, if accept { "yes" } else { "no" });
+            if let Err(err) = self.client.post(&path, &[]).await {
+                println!("Failed to answer takeback: {}", err);
+            } else if accept {
+                return true;
+            }
+        }
+        if draw_offered {
+            let accept = self.behaviour.should_accept_draw(&session.tracker, session.moves.len(), session.is_repetition());
+            println!("Game {}: {} draw", session.id, if accept { "accepting" } else { "declining" });
+            let path = format!("/bot/game/{}/draw/{}", session.id, if accept { "yes" } else { "no" });
+            if let Err(err) = self.client.post(&path, &[]).await {
+                println!("Failed to answer draw offer: {}", err);
+            } else if accept {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+/**
+ * Whether the game stream line is a gameFull or gameState event, which shows that the game goes on.
+ */
+pub fn is_game_state(value: &serde_json::Value) -> bool {
+    matches!(value["type"].as_str(), Some("gameFull" | "gameState"))
+}
+
+/**
+ * Loads a config file from the path in the environment variable, or from the default path if that exists.
+ * Falls back to the default config if there is no file or it can not be read.
+ */
+fn load_config<T: Default>(env_var: &str, default_path: &str, load: fn(&str) -> Result<T, String>) -> T {
+    let path = std::env::var(env_var).ok();
+    let config = match path.as_deref() {
+        Some(path) => load(path),
+        None if std::path::Path::new(default_path).exists() => load(default_path),
+        None => Ok(T::default()),
+    };
+    config.unwrap_or_else(|err| {
+        println!("{}. Using the default config", err);
+        T::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ndjson_reader_joins_lines_split_across_chunks() {
+        let mut reader = NdjsonReader::new();
+        assert!(reader.push(b"{\"type\":\"gameSt").is_empty());
+        assert!(reader.push(b"ate\",\"moves\":").is_empty());
+        let values = reader.push(b"\"e2e4\"}\n{\"type\"");
+        assert_eq!(values, vec![serde_json::json!({ "type": "gameState", "moves": "e2e4" })]);
+        let values = reader.push(b":\"chatLine\"}\n");
+        assert_eq!(values, vec![serde_json::json!({ "type": "chatLine" })]);
+    }
+
+    #[test]
+    fn ndjson_reader_splits_several_lines_of_one_chunk() {
+        let mut reader = NdjsonReader::new();
+        let values = reader.push(b"{\"a\":1}\n\n{ not json\n{\"b\":2}\r\n{\"c\":");
+        assert_eq!(values, vec![serde_json::json!({ "a": 1 }), serde_json::json!({ "b": 2 })]);
+        assert_eq!(reader.push(b"3}\n"), vec![serde_json::json!({ "c": 3 })]);
     }
 }
@@ -0,0 +1,327 @@
+use tokio::task::JoinHandle;
+
+use crate::challenge_policy::ChallengePolicy;
+use crate::lichess::{
+    is_game_state, Backoff, Event, GameEvent, GameSession, LichessClient, NdjsonReader, State, SyncChange, MAX_GAME_RECONNECTS,
+};
+use crate::player::{join_search, Decision, HumanPlayer, Player, PlayerAction};
+use crate::search::{Search, SearchSettings};
+
+/**
+ * A seek for a new game. The time is in minutes and the increment in seconds, like on the Lichess site.
+ */
+pub struct Seek {
+    pub time: u32,
+    pub increment: u32,
+    pub rated: bool,
+}
+
+/**
+ * Plays a game of a normal (not bot) Lichess account from the terminal through the Board API.
+ * Moves are entered like in a local game. The engine can show a hint before each of our moves.
+ */
+pub struct LichessBoard {
+    client: LichessClient,
+    pub settings: SearchSettings, // Used for the hints
+    pub hint: bool,
+    pub policy: ChallengePolicy, // Incoming challenges passing the policy are accepted while waiting for a game
+}
+
+// Milliseconds the engine thinks on a hint
+const HINT_TIME: u64 = 2000;
+
+/**
+ * Whatever arrives first while playing: data from the game stream or the move entered by the user.
+ */
+enum Update {
+    Stream(Result<Option<Vec<u8>>, reqwest::Error>),
+    Input(Result<Decision, String>),
+}
+
+impl LichessBoard {
+    /**
+     * Board client for the token in LICHESS_BOARD_TOK. Needs the board:play scope.
+     */
+    pub fn new() -> LichessBoard {
+        LichessBoard::with_client(LichessClient::from_env("LICHESS_BOARD_TOK"))
+    }
+
+    pub fn with_client(client: LichessClient) -> LichessBoard {
+        LichessBoard {
+            client,
+            settings: SearchSettings {
+                time_limit: Some(HINT_TIME),
+                ..SearchSettings::default()
+            },
+            hint: false,
+            policy: ChallengePolicy {
+                max_games: 1,
+                ..ChallengePolicy::default()
+            },
+        }
+    }
+
+    /**
+     * Seeks a game or waits for a challenge (or an already running game), then plays it.
+     * Ctrl-C while waiting cancels the seek.
+     */
+    pub async fn run(&self, seek: Option<Seek>) {
+        let account = match self.client.get_account().await {
+            Ok(account) => account,
+            Err(err) => {
+                println!("Failed to get account: {}", err);
+                return;
+            }
+        };
+
+        let seek_task = seek.map(|seek| self.start_seek(seek));
+        let game_id = self.wait_for_game().await;
+        if let Some(task) = seek_task {
+            // Closing the seek request cancels the seek
+            task.abort();
+        }
+        if let Some(game_id) = game_id {
+            self.play_game(game_id, &account.id).await;
+        }
+    }
+
+    /**
+     * The seek stays open as long as the request is running.
+     */
+    fn start_seek(&self, seek: Seek) -> JoinHandle<()> {
+        let client = self.client.clone();
+        println!("Seeking a {}+{} game", seek.time, seek.increment);
+        tokio::spawn(async move {
+            let form = [
+                ("time", seek.time.to_string()),
+                ("increment", seek.increment.to_string()),
+                ("rated", seek.rated.to_string()),
+            ];
+            let form: Vec<(&str, &str)> = form.iter().map(|(key, value)| (*key, value.as_str())).collect();
+            if let Err(err) = client.post("/board/seek", &form).await {
+                println!("Seek failed: {}", err);
+            }
+        })
+    }
+
+    /**
+     * Waits on the event stream until a game starts. Returns None on Ctrl-C.
+     */
+    async fn wait_for_game(&self) -> Option<String> {
+        println!("Waiting for a game. Press Ctrl-C to cancel");
+        let mut backoff = Backoff::new();
+        let shutdown = tokio::signal::ctrl_c();
+        tokio::pin!(shutdown);
+
+        loop {
+            match self.client.open_stream("/stream/event").await {
+                Ok(mut response) => {
+                    let mut reader = NdjsonReader::new();
+                    loop {
+                        let chunk = tokio::select! {
+                            _ = &mut shutdown => return None,
+                            chunk = response.chunk() => chunk,
+                        };
+                        match chunk {
+                            Ok(Some(bytes)) => {
+                                backoff.reset();
+                                for value in reader.push(&bytes) {
+                                    if let Some(game_id) = self.handle_event(value).await {
+                                        return Some(game_id);
+                                    }
+                                }
+                            }
+                            Ok(None) => break,
+                            Err(err) => {
+                                println!("Event stream failed: {}", err);
+                                break;
+                            }
+                        }
+                    }
+                }
+                Err(err) => println!("Failed to connect to the event stream: {}", err),
+            }
+            tokio::select! {
+                _ = &mut shutdown => return None,
+                _ = tokio::time::sleep(backoff.next_delay()) => {}
+            }
+        }
+    }
+
+    /**
+     * Answers challenges. Returns the id of a started game.
+     */
+    async fn handle_event(&self, value: serde_json::Value) -> Option<String> {
+        match serde_json::from_value(value).ok()? {
+            Event::Challenge { challenge } => {
+                let challenger = challenge.challenger.as_ref().map(|c| c.name.clone()).unwrap_or_default();
+                let result = match self.policy.check(&challenge, 0) {
+                    Some(reason) => {
+                        println!("Declining challenge from {}: {}", challenger, reason);
+                        self.client.decline_challenge(&challenge.id, reason.as_str()).await
+                    }
+                    None => {
+                        println!("Accepting challenge from {}", challenger);
+                        self.client.accept_challenge(&challenge.id).await
+                    }
+                };
+                if let Err(err) = result {
+                    println!("Failed to answer challenge {}: {}", challenge.id, err);
+                }
+                None
+            }
+            Event::GameStart { game } => Some(game.game_id),
+            _ => None,
+        }
+    }
+
+    /**
+     * Streams the game and sends the moves entered by the user until the game is over.
+     */
+    async fn play_game(&self, id: String, user_id: &str) {
+        let mut session = GameSession::new(id);
+        let mut input: Option<JoinHandle<Result<Decision, String>>> = None;
+        let mut backoff = Backoff::new();
+        let mut reconnects = 0; // Reconnects since the last game state
+
+        'connection: loop {
+            match self.client.open_stream(&format!("/board/game/stream/{}", session.id)).await {
+                Ok(mut response) => {
+                    let mut reader = NdjsonReader::new();
+                    loop {
+                        let our_turn = session.color == Some(session.game.turn);
+                        if input.is_none() && our_turn && session.moved_at_ply != Some(session.moves.len()) {
+                            input = Some(self.ask_move(&session).await);
+                        }
+
+                        let update = match input.as_mut() {
+                            Some(task) => tokio::select! {
+                                chunk = response.chunk() => Update::Stream(chunk.map(|c| c.map(|b| b.to_vec()))),
+                                decision = task => Update::Input(decision.expect("Input task panicked")),
+                            },
+                            None => Update::Stream(response.chunk().await.map(|c| c.map(|b| b.to_vec()))),
+                        };
+                        match update {
+                            Update::Input(Ok(decision)) => {
+                                input = None;
+                                self.send_action(&mut session, decision.action).await;
+                            }
+                            Update::Input(Err(err)) => {
+                                println!("{}", err);
+                                return;
+                            }
+                            Update::Stream(Ok(Some(bytes))) => {
+                                for value in reader.push(&bytes) {
+                                    let is_state = is_game_state(&value);
+                                    if self.handle_game_event(&mut session, user_id, value) {
+                                        break 'connection;
+                                    }
+                                    if is_state {
+                                        reconnects = 0;
+                                        backoff.reset();
+                                    }
+                                }
+                            }
+                            Update::Stream(Ok(None)) => break,
+                            Update::Stream(Err(err)) => {
+                                println!("Game {} stream failed: {}", session.id, err);
+                                break;
+                            }
+                        }
+                    }
+                }
+                Err(err) => println!("Failed to connect to game {}: {}", session.id, err),
+            }
+            if reconnects == MAX_GAME_RECONNECTS {
+                println!("Lost the connection to game {}", session.id);
+                break;
+            }
+            reconnects += 1;
+            tokio::time::sleep(backoff.next_delay()).await;
+        }
+
+        if let Some(task) = input {
+            task.abort();
+        }
+    }
+
+    /**
+     * Shows the hint and starts reading the move of the user.
+     */
+    async fn ask_move(&self, session: &GameSession) -> JoinHandle<Result<Decision, String>> {
+        if self.hint {
+            let result = join_search(Search::spawn(session.game.clone(), self.settings.clone())).await;
+            println!("Hint: {} ({})", result.best_move.to_string(), result.score);
+        }
+        let context = session.context();
+        if context.draw_offered {
+            println!("Your opponent offers a draw, enter draw to accept");
+        }
+        println!("Your move:");
+        let game = session.game.clone();
+        tokio::spawn(async move { HumanPlayer.play(&game, &context).await })
+    }
+
+    async fn send_action(&self, session: &mut GameSession, action: PlayerAction) {
+        let id = &session.id;
+        let (path, mov) = match action {
+            PlayerAction::Move(mov) => (format!("/board/game/{}/move/{}", id, mov.to_string()), Some(mov)),
+            PlayerAction::OfferDraw(mov) => {
+                (format!("/board/game/{}/move/{}?offeringDraw=true", id, mov.to_string()), Some(mov))
+            }
+            PlayerAction::AcceptDraw => (format!("/board/game/{}/draw/yes", id), None),
+            PlayerAction::Resign => (format!("/board/game/{}/resign", id), None),
+        };
+        match self.client.post(&path, &[]).await {
+            Ok(()) if mov.is_some() => session.moved_at_ply = Some(session.moves.len()),
+            Ok(()) => {}
+            Err(err) => println!("Failed to send {:?}: {}", action, err),
+        }
+    }
+
+    /**
+     * Returns true when the game is over.
+     */
+    fn handle_game_event(&self, session: &mut GameSession, user_id: &str, value: serde_json::Value) -> bool {
+        match serde_json::from_value(value) {
+            Ok(GameEvent::GameFull { white, black, initial_fen, state, .. }) => {
+                session.start(&white, &black, initial_fen, user_id);
+                session.game.board.print();
+                self.handle_state(session, state)
+            }
+            Ok(GameEvent::GameState(state)) => self.handle_state(session, state),
+            Ok(GameEvent::ChatLine { username, text, room }) => {
+                println!("[{}] {}: {}", room, username, text);
+                false
+            }
+            Ok(GameEvent::OpponentGone { gone: true, claim_win_in_seconds }) => {
+                println!("Opponent gone, you can claim the win in {:?}s", claim_win_in_seconds);
+                false
+            }
+            Ok(_) => false,
+            Err(err) => {
+                println!("Failed to read game event: {}", err);
+                false
+            }
+        }
+    }
+
+    fn handle_state(&self, session: &mut GameSession, state: State) -> bool {
+        session.last_state = Some(state.clone());
+        let moves: Vec<&str> = state.moves.split_whitespace().collect();
+        match session.sync(&moves) {
+            Ok(SyncChange::Unchanged) => {}
+            Ok(change) => {
+                if let SyncChange::Rebuilt = change {
+                    session.moved_at_ply = None;
+                }
+                if let Some(last_move) = moves.last() {
+                    println!("Played: {}", last_move);
+                }
+                session.game.board.print();
+            }
+            Err(err) => println!("Game {} out of sync: {}", session.id, err),
+        }
+        session.outcome(&state).is_some()
+    }
+}
@@ -0,0 +1,413 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde_json::Value;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+pub static MOCK_TOKEN: &str = "mock-token";
+pub static MOCK_BOT_ID: &str = "mockbot";
+// Script lines starting with this pause the stream until a request to the path following it was made,
+// or as many requests as the number after the path
+static WAIT_PREFIX: &str = "#wait ";
+
+/**
+ * A request the bot sent to the mock server.
+ */
+#[derive(Clone, Debug)]
+pub struct Request {
+    pub method: String,
+    pub path: String, // Without the /api prefix
+    pub body: String,
+}
+
+struct MockState {
+    event_lines: Option<Vec<String>>, // Sent on the first connection to the event stream only
+    game_lines: HashMap<String, Vec<String>>,
+    requests: Vec<Request>,
+}
+
+/**
+ * Local stand in for the Lichess API. It plays scripted NDJSON lines on the event and game streams
+ * and records every request. Posts always succeed.
+ * The event stream stays open after the script, game streams close after their script.
+ */
+pub struct MockLichess {
+    pub url: String,
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockLichess {
+    pub async fn start(event_lines: Vec<String>, game_lines: HashMap<String, Vec<String>>) -> MockLichess {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("Cant bind the mock server");
+        let url = format!("http://{}/api", listener.local_addr().unwrap());
+        let state = Arc::new(Mutex::new(MockState {
+            event_lines: Some(event_lines),
+            game_lines,
+            requests: Vec::new(),
+        }));
+
+        let server_state = state.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(handle_connection(stream, server_state.clone()));
+            }
+        });
+        MockLichess { url, state }
+    }
+
+    pub fn requests(&self) -> Vec<Request> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    /**
+     * Waits until the recorded requests fulfill the condition. Panics after ten seconds.
+     */
+    pub async fn wait_for(&self, condition: impl Fn(&[Request]) -> bool) {
+        let wait = async {
+            while !condition(&self.state.lock().unwrap().requests) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        if tokio::time::timeout(Duration::from_secs(10), wait).await.is_err() {
+            panic!("Timed out waiting for the bot. Requests: {:?}", self.requests());
+        }
+    }
+}
+
+async fn handle_connection(mut stream: TcpStream, state: Arc<Mutex<MockState>>) {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 4096];
+    let header_end = loop {
+        if let Some(position) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+            break position + 4;
+        }
+        match stream.read(&mut chunk).await {
+            Ok(0) | Err(_) => return,
+            Ok(read) => buffer.extend_from_slice(&chunk[..read]),
+        }
+    };
+
+    let head = String::from_utf8_lossy(&buffer[..header_end]).to_string();
+    let mut head_lines = head.lines();
+    let mut request_line = head_lines.next().unwrap_or_default().split_whitespace();
+    let method = request_line.next().unwrap_or_default().to_string();
+    let path = request_line.next().unwrap_or_default().trim_start_matches("/api").to_string();
+    let mut content_length = 0;
+    let mut authorized = false;
+    for line in head_lines {
+        if let Some((name, value)) = line.split_once(':') {
+            match name.trim().to_lowercase().as_str() {
+                "content-length" => content_length = value.trim().parse().unwrap_or(0),
+                "authorization" => authorized = value.trim() == format!("Bearer {}", MOCK_TOKEN),
+                _ => {}
+            }
+        }
+    }
+    while buffer.len() < header_end + content_length {
+        match stream.read(&mut chunk).await {
+            Ok(0) | Err(_) => return,
+            Ok(read) => buffer.extend_from_slice(&chunk[..read]),
+        }
+    }
+    let body = String::from_utf8_lossy(&buffer[header_end..header_end + content_length]).to_string();
+
+    let stream_lines = {
+        let mut state = state.lock().unwrap();
+        state.requests.push(Request {
+            method: method.clone(),
+            path: path.clone(),
+            body,
+        });
+        if path == "/stream/event" {
+            Some((state.event_lines.take().unwrap_or_default(), true, String::new()))
+        } else {
+            path.strip_prefix("/bot/game/stream/")
+                .or_else(|| path.strip_prefix("/board/game/stream/"))
+                .map(|id| (state.game_lines.remove(id).unwrap_or_default(), false, id.to_string()))
+        }
+    };
+
+    if !authorized {
+        respond(&mut stream, "401 Unauthorized", "{\"error\":\"No such token\"}").await;
+        return;
+    }
+    match (method.as_str(), stream_lines) {
+        ("GET", Some((lines, keep_open, game_id))) => {
+            let header = "HTTP/1.1 200 OK\r\nContent-Type: application/x-ndjson\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n";
+            if stream.write_all(header.as_bytes()).await.is_err() {
+                return;
+            }
+            for line in lines {
+                if let Some(wait) = line.strip_prefix(WAIT_PREFIX) {
+                    let (wait_path, count) = match wait.split_once(' ') {
+                        Some((wait_path, count)) => (wait_path, count.parse().unwrap_or(1)),
+                        None => (wait, 1),
+                    };
+                    while state.lock().unwrap().requests.iter().filter(|r| r.path.starts_with(wait_path)).count() < count {
+                        tokio::time::sleep(Duration::from_millis(10)).await;
+                    }
+                    continue;
+                }
+                let line = insert_moves(&line, &state.lock().unwrap().requests, &game_id) + "\n";
+                let chunk = format!("{:x}\r\n{}\r\n", line.len(), line);
+                if stream.write_all(chunk.as_bytes()).await.is_err() {
+                    return;
+                }
+            }
+            if keep_open {
+                std::future::pending::<()>().await;
+            }
+            let _ = stream.write_all(b"0\r\n\r\n").await;
+        }
+        ("GET", None) if path == "/account" => {
+            let account = format!("{{\"id\":\"{}\",\"username\":\"MockBot\",\"title\":\"BOT\"}}", MOCK_BOT_ID);
+            respond(&mut stream, "200 OK", &account).await;
+        }
+        ("POST", _) => respond(&mut stream, "200 OK", "{\"ok\":true}").await,
+        _ => respond(&mut stream, "404 Not Found", "{\"error\":\"Not found\"}").await,
+    }
+}
+
+/**
+ * Replaces the placeholders of `bot_move` with the moves the bot posted in the game.
+ */
+fn insert_moves(line: &str, requests: &[Request], game_id: &str) -> String {
+    let prefixes = [format!("/bot/game/{}/move/", game_id), format!("/board/game/{}/move/", game_id)];
+    let moves = requests
+        .iter()
+        .filter(|r| r.method == "POST")
+        .filter_map(|r| prefixes.iter().find_map(|prefix| r.path.strip_prefix(prefix.as_str())));
+    let mut line = line.to_string();
+    for (i, mov) in moves.enumerate() {
+        line = line.replace(&bot_move(i + 1), mov);
+    }
+    line
+}
+
+async fn respond(stream: &mut TcpStream, status: &str, body: &str) {
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    let _ = stream.write_all(response.as_bytes()).await;
+}
+
+/**
+ * Event and game stream lines.
+ */
+pub fn wait_line(path: &str) -> String {
+    WAIT_PREFIX.to_string() + path
+}
+
+pub fn wait_count_line(path: &str, count: usize) -> String {
+    format!("{}{} {}", WAIT_PREFIX, path, count)
+}
+
+/**
+ * Placeholder for the nth (from 1) move the bot posted in the game, for scripting game states.
+ */
+pub fn bot_move(n: usize) -> String {
+    format!("{{move {}}}", n)
+}
+
+pub fn challenge_line(id: &str, variant: &str, time_control: &str) -> String {
+    serde_json::json!({
+        "type": "challenge",
+        "challenge": {
+            "id": id,
+            "challenger": { "name": "Someone", "rating": 1500, "title": null },
+            "rated": false,
+            "variant": { "key": variant },
+            "timeControl": { "type": time_control, "limit": 180, "increment": 2 },
+        }
+    })
+    .to_string()
+}
+
+pub fn game_start_line(id: &str) -> String {
+    serde_json::json!({ "type": "gameStart", "game": { "gameId": id } }).to_string()
+}
+
+fn state_value(moves: &str, status: &str, winner: Option<&str>) -> Value {
+    serde_json::json!({ "type": "gameState", "moves": moves, "status": status, "winner": winner })
+}
+
+/**
+ * gameFull event with the bot playing white.
+ */
+pub fn game_full_line(initial_fen: &str, moves: &str, status: &str) -> String {
+    serde_json::json!({
+        "type": "gameFull",
+        "white": { "id": MOCK_BOT_ID, "name": "MockBot" },
+        "black": { "id": "someone", "name": "Someone" },
+        "initialFen": initial_fen,
+        "rated": false,
+        "state": state_value(moves, status, None),
+    })
+    .to_string()
+}
+
+pub fn game_state_line(moves: &str, status: &str, winner: Option<&str>) -> String {
+    state_value(moves, status, winner).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::Game;
+    use crate::lichess::{Lichess, LichessClient, MAX_GAME_RECONNECTS};
+    use crate::lichess_board::{LichessBoard, Seek};
+    use crate::moves::Move;
+
+    fn bot(mock: &MockLichess) -> Lichess {
+        let mut lichess = Lichess::with_client(LichessClient::with_server(&mock.url, MOCK_TOKEN));
+        lichess.settings.depth = 2;
+        lichess.settings.hash_size = 1;
+        lichess.ponder = false;
+        lichess.behaviour.chat_rooms = vec!["player".to_string()];
+        lichess
+    }
+
+    fn posts<'a>(requests: &'a [Request], path: &'a str) -> impl Iterator<Item = &'a Request> {
+        requests.iter().filter(move |r| r.method == "POST" && r.path.starts_with(path))
+    }
+
+    #[tokio::test]
+    async fn plays_a_game_until_mate() {
+        // The bot is in check and can only play Kg1, after b4 it mates on the back rank
+        let fen = "6k1/5ppp/8/1p6/8/4b3/5nPP/R6K w - - 0 1";
+        let events = vec![challenge_line("c1", "standard", "clock"), game_start_line("g1")];
+        let game = vec![
+            game_full_line(fen, "", "started"),
+            "{ not json".to_string(),
+            wait_count_line("/bot/game/g1/move/", 1),
+            game_state_line(&bot_move(1), "started", None),
+            game_state_line(&format!("{} b5b4", bot_move(1)), "started", None),
+            wait_count_line("/bot/game/g1/move/", 2),
+            game_state_line(&format!("{} b5b4 {}", bot_move(1), bot_move(2)), "mate", Some("white")),
+        ];
+        let mock = MockLichess::start(events, HashMap::from([("g1".to_string(), game)])).await;
+        let mut lichess = bot(&mock);
+        lichess.run_until(mock.wait_for(|requests| posts(requests, "/bot/game/g1/chat").count() >= 2)).await;
+
+        let requests = mock.requests();
+        assert_eq!(posts(&requests, "/challenge/c1/accept").count(), 1);
+        let moves: Vec<&str> = posts(&requests, "/bot/game/g1/move/")
+            .map(|r| r.path.trim_start_matches("/bot/game/g1/move/"))
+            .collect();
+        // Nothing is posted after the mate
+        assert_eq!(moves.len(), 2);
+        assert_eq!(moves[0], "h1g1");
+
+        let mut game = Game::from_fen(fen);
+        for mov in [moves[0], "b5b4", moves[1]] {
+            assert!(game.make_move(Move::parse(mov).unwrap()), "Illegal move {}", mov);
+        }
+        assert_ne!(game.king_check, 0);
+        assert!(game.get_possible_team_moves(game.turn).is_empty(), "{} is not mate", moves[1]);
+    }
+
+    #[tokio::test]
+    async fn declines_challenges_against_the_policy() {
+        let events = vec![
+            challenge_line("c1", "chess960", "clock"),
+            challenge_line("c2", "standard", "correspondence"),
+        ];
+        let mock = MockLichess::start(events, HashMap::new()).await;
+        let mut lichess = bot(&mock);
+        lichess.run_until(mock.wait_for(|requests| posts(requests, "/challenge/").count() >= 2)).await;
+
+        let requests = mock.requests();
+        let declines: Vec<&Request> = posts(&requests, "/challenge/").collect();
+        assert_eq!(declines[0].path, "/challenge/c1/decline");
+        assert_eq!(declines[0].body, "reason=standard");
+        assert_eq!(declines[1].path, "/challenge/c2/decline");
+        assert_eq!(declines[1].body, "reason=timeControl");
+    }
+
+    #[tokio::test]
+    async fn aborted_game_ends_without_moving() {
+        let events = vec![game_start_line("g1")];
+        let game = vec![
+            game_full_line("startpos", "", "started"),
+            game_state_line("", "aborted", None),
+        ];
+        let mock = MockLichess::start(events, HashMap::from([("g1".to_string(), game)])).await;
+        let mut lichess = bot(&mock);
+        // We move once as white, then the opponent aborts
+        lichess.behaviour.greeting = String::new();
+        lichess.run_until(mock.wait_for(|requests| posts(requests, "/bot/game/g1/chat").count() >= 1)).await;
+
+        let requests = mock.requests();
+        assert_eq!(posts(&requests, "/bot/game/g1/move/").count(), 1);
+        assert_eq!(posts(&requests, "/bot/game/g1/chat").count(), 1);
+    }
+
+    #[tokio::test]
+    async fn resigns_a_lost_position() {
+        let events = vec![game_start_line("g1")];
+        let game = vec![
+            game_full_line("k7/8/8/3q4/8/8/8/K6r w - - 0 1", "", "started"),
+            game_state_line("", "resign", Some("black")),
+        ];
+        let mock = MockLichess::start(events, HashMap::from([("g1".to_string(), game)])).await;
+        let mut lichess = bot(&mock);
+        lichess.behaviour.resign_moves = 1;
+        lichess.run_until(mock.wait_for(|requests| posts(requests, "/bot/game/g1/resign").count() >= 1)).await;
+
+        assert_eq!(posts(&mock.requests(), "/bot/game/g1/move/").count(), 0);
+    }
+
+    #[tokio::test]
+    async fn gives_up_a_game_stream_that_keeps_closing() {
+        // After the first connection the stream closes without sending anything. The bot waits for black
+        let fen = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1";
+        let events = vec![game_start_line("g1")];
+        let game = vec![game_full_line(fen, "", "started")];
+        let mock = MockLichess::start(events, HashMap::from([("g1".to_string(), game)])).await;
+        let mut lichess = bot(&mock);
+        let shutdown = mock.wait_for(|requests| requests.iter().any(|r| r.path == "/bot/game/stream/g1"));
+        // Shutting down waits for the game, so this only returns once the game is given up
+        let run = lichess.run_until(shutdown);
+        tokio::time::timeout(Duration::from_secs(10), run).await.expect("The game stream is reconnected forever");
+
+        let streams = mock.requests().iter().filter(|r| r.path == "/bot/game/stream/g1").count();
+        assert_eq!(streams, 1 + MAX_GAME_RECONNECTS as usize);
+        assert_eq!(posts(&mock.requests(), "/bot/game/g1/move/").count(), 0);
+    }
+
+    #[tokio::test]
+    async fn board_seeks_and_sends_the_entered_move() {
+        let events = vec![wait_line("/board/seek"), game_start_line("g1")];
+        let game = vec![
+            game_full_line("startpos", "", "started"),
+            wait_line("/board/game/g1/move/"),
+            game_state_line("e2e4 e7e5", "resign", Some("white")),
+        ];
+        let mock = MockLichess::start(events, HashMap::from([("g1".to_string(), game)])).await;
+        crate::input::unread_line("e2e4\n".to_string());
+        let board = LichessBoard::with_client(LichessClient::with_server(&mock.url, MOCK_TOKEN));
+        board.run(Some(Seek { time: 5, increment: 3, rated: false })).await;
+
+        let requests = mock.requests();
+        let seeks: Vec<&Request> = posts(&requests, "/board/seek").collect();
+        assert_eq!(seeks.len(), 1);
+        assert_eq!(seeks[0].body, "time=5&increment=3&rated=false");
+        assert_eq!(posts(&requests, "/board/game/g1/move/e2e4").count(), 1);
+    }
+
+    #[tokio::test]
+    async fn stops_on_a_wrong_token() {
+        let mock = MockLichess::start(Vec::new(), HashMap::new()).await;
+        let mut lichess = Lichess::with_client(LichessClient::with_server(&mock.url, "wrong-token"));
+        lichess.run_until(std::future::pending()).await;
+
+        let requests = mock.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].path, "/account");
+    }
+}
@@ -1,21 +1,57 @@
-use std::{io, time::Instant};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use base_types::{Color, Position};
+use book::OpeningBook;
+use book_builder::{BookBuilder, BookBuilderSettings, ResultFilter};
+use chess_clock::TimeControl;
+use epd::{load_epd, run_epd, EpdSettings};
 use game::Game;
+use game_launcher::{GameLauncher, LaunchSettings, PlayerKind};
+use lichess_board::Seek;
+use mate_search::{MateResult, MateSearch};
+use match_runner::{EngineConfig, MatchRunner, MatchSettings};
 use moves::{Move, MoveType};
-use player::{BotPlayer, HumanPlayer, Player};
-use search::{Search, SearchSettings};
+use search::{pv_to_string, Search, SearchSettings};
+use skill::Skill;
+use tablebase::Tablebase;
+use uci::Uci;
+use uci_engine::GoLimit;
 
 mod base_types;
 mod board;
+mod book;
+mod book_builder;
+mod bot_behaviour;
+mod challenge_policy;
+mod chess_clock;
+mod endgame;
+#[cfg(test)]
+mod engine_mock;
+mod epd;
 mod game;
+mod game_launcher;
+mod input;
 mod lichess;
+mod lichess_board;
+#[cfg(test)]
+mod lichess_mock;
+mod mate_search;
+mod match_runner;
 mod moves;
+mod pgn;
 mod piece;
 mod player;
+mod polyglot_keys;
 mod precompute;
 mod search;
+mod skill;
 mod square_table;
+mod tablebase;
+mod time_manager;
+mod tt;
+mod uci;
+mod uci_engine;
 
 static STARTING_POS_FEN: &'static str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
 
@@ -57,20 +93,28 @@ enum InputMessage {
     ShowPgn,
     RunTest(RunTestOptions),
     ShowBitboard(BitboardType),
-    StartGame,
+    StartGame(Box<LaunchSettings>),
     LichessChallenge,
+    LichessBoard(Option<Seek>, bool), // Seek, show hints
     RunSearchTest(SearchSettings),
     ShowScore,
     ShowMoveOrder(Color),
+    ShowSee(Move),
+    LoadBook(String, Option<usize>), // Path, max ply
+    ShowBook,
+    BuildBook(String, Vec<String>, BookBuilderSettings), // Output path, pgn paths
+    LoadTablebase(String),
+    ShowTablebase,
+    FindMate(u8, Option<u64>), // Moves, time limit
+    RunEpd(String, EpdSettings),
+    RunMatch(Box<(MatchSettings, EngineConfig, EngineConfig)>),
+    Uci,
     Quit,
     None,
 }
 
 fn get_input() -> InputMessage {
-    let mut input = String::new();
-    io::stdin()
-        .read_line(&mut input)
-        .expect("Failed to read line");
+    let input = input::read_line();
     if input.len() == 0 {
         return InputMessage::None;
     }
@@ -137,27 +181,7 @@ fn get_input() -> InputMessage {
     } else if args[0] == "st" {
         let mut settings = SearchSettings::default();
         for param in &args[1..] {
-            match *param {
-                "-nomo" => settings.move_order = false,
-                "-log" => settings.show_log = true,
-                _ => {
-                    // check for var
-                    let var = param.split("=").collect::<Vec<&str>>();
-                    if var.len() != 2 {
-                        continue;
-                    }
-                    match var[0] {
-                        "atpen" => {
-                            settings.move_on_attacked_penalty = var[1].parse::<i32>().unwrap()
-                        }
-                        "capt" => settings.capture_multiplier = var[1].parse::<i32>().unwrap(),
-                        "castl" => settings.castle_reword = var[1].parse::<i32>().unwrap(),
-                        "promo" => settings.promotion_bonus = var[1].parse::<i32>().unwrap(),
-                        "depth" => settings.depth = var[1].parse::<u8>().unwrap(),
-                        _ => {}
-                    }
-                }
-            }
+            parse_search_param(&mut settings, param);
         }
         return InputMessage::RunSearchTest(settings);
     } else if args[0] == "bit" {
@@ -176,9 +200,71 @@ fn get_input() -> InputMessage {
             return InputMessage::None;
         }
     } else if args[0] == "start" {
-        return InputMessage::StartGame;
+        // start [white=<player>] [black=<player>] [white.<st param>] [black.<st param>] [time=<ms>] [depth=<int>]
+        //       [tc=<control>] [games=<int>] [-fixed] [pgn=<file>] [fen=<fen>]
+        let mut settings = LaunchSettings::default();
+        let mut kinds = ["human", "bot"];
+        let mut search = [SearchSettings::default(), SearchSettings::default()];
+        for (i, arg) in args.iter().enumerate().skip(1) {
+            if let Some(param) = arg.strip_prefix("white.") {
+                parse_search_param(&mut search[0], param);
+                continue;
+            }
+            if let Some(param) = arg.strip_prefix("black.") {
+                parse_search_param(&mut search[1], param);
+                continue;
+            }
+            match arg.split_once('=') {
+                Some(("white", kind)) => kinds[0] = kind,
+                Some(("black", kind)) => kinds[1] = kind,
+                Some(("time", value)) => settings.engine_limit = value.parse::<u64>().map_or(settings.engine_limit, GoLimit::MoveTime),
+                Some(("depth", value)) => settings.engine_limit = value.parse::<u8>().map_or(settings.engine_limit, GoLimit::Depth),
+                Some(("tc", value)) => match TimeControl::parse(value) {
+                    Ok(control) => settings.time_control = Some(control),
+                    Err(err) => {
+                        println!("{}", err);
+                        return InputMessage::None;
+                    }
+                },
+                Some(("games", value)) => settings.games = value.parse::<usize>().unwrap_or(settings.games),
+                Some(("pgn", path)) => settings.pgn_path = path.to_string(),
+                Some(("fen", _)) => {
+                    // The fen has spaces, it is the rest of the line
+                    settings.fen = Some(args[i..].join(" ")["fen=".len()..].to_string());
+                    break;
+                }
+                _ if *arg == "-fixed" => settings.alternate = false,
+                _ => {}
+            }
+        }
+        let [white, black] = [0, 1].map(|side| match kinds[side] {
+            "human" => PlayerKind::Human,
+            "bot" => PlayerKind::Bot(search[side].clone()),
+            "random" => PlayerKind::Random,
+            path => PlayerKind::Engine(path.to_string()),
+        });
+        settings.white = white;
+        settings.black = black;
+        return InputMessage::StartGame(Box::new(settings));
     } else if args[0] == "lichess" {
         return InputMessage::LichessChallenge;
+    } else if args[0] == "board" {
+        // board [-hint] [seek <minutes> <increment> [rated]]
+        let hint = args.contains(&"-hint");
+        let args = args.iter().filter(|a| **a != "-hint").cloned().collect::<Vec<&str>>();
+        let seek = if args.len() >= 4 && args[1] == "seek" {
+            match (args[2].parse::<u32>(), args[3].parse::<u32>()) {
+                (Ok(time), Ok(increment)) => Some(Seek {
+                    time,
+                    increment,
+                    rated: args.get(4) == Some(&"rated"),
+                }),
+                _ => return InputMessage::None,
+            }
+        } else {
+            None
+        };
+        return InputMessage::LichessBoard(seek, hint);
     } else if args[0] == "score" {
         return InputMessage::ShowScore;
     } else if args[0] == "mo" {
@@ -191,12 +277,153 @@ fn get_input() -> InputMessage {
             Color::Black
         };
         return InputMessage::ShowMoveOrder(color);
+    } else if args[0] == "see" {
+        if args.len() != 2 {
+            return InputMessage::None;
+        }
+        return InputMessage::ShowSee(Move::from_string(args[1]));
+    } else if args[0] == "book" {
+        if args.len() == 1 {
+            return InputMessage::ShowBook;
+        }
+        let max_ply = args.get(2).and_then(|ply| ply.parse::<usize>().ok());
+        return InputMessage::LoadBook(args[1].to_string(), max_ply);
+    } else if args[0] == "mkbook" {
+        // mkbook <out> <pgn>... [ply=<int>] [elo=<int>] [results=all|decisive|winner] [games=<int>]
+        if args.len() < 3 {
+            return InputMessage::None;
+        }
+        let mut settings = BookBuilderSettings::default();
+        let mut pgn_paths = Vec::new();
+        for arg in &args[2..] {
+            match arg.split_once('=') {
+                Some(("ply", value)) => settings.max_ply = value.parse::<usize>().unwrap_or(settings.max_ply),
+                Some(("elo", value)) => settings.min_elo = value.parse::<u32>().unwrap_or(0),
+                Some(("games", value)) => settings.min_games = value.parse::<u32>().unwrap_or(1),
+                Some(("results", "decisive")) => settings.results = ResultFilter::Decisive,
+                Some(("results", "winner")) => settings.results = ResultFilter::Winner,
+                Some(("results", _)) => settings.results = ResultFilter::All,
+                _ => pgn_paths.push(arg.to_string()),
+            }
+        }
+        return InputMessage::BuildBook(args[1].to_string(), pgn_paths, settings);
+    } else if args[0] == "tb" {
+        if args.len() == 1 {
+            return InputMessage::ShowTablebase;
+        }
+        return InputMessage::LoadTablebase(args[1].to_string());
+    } else if args[0] == "mate" {
+        // mate <moves> time=<ms>
+        let moves = match args.get(1).and_then(|moves| moves.parse::<u8>().ok()) {
+            Some(moves) => moves,
+            None => {
+                println!("Usage: mate <moves> [time=<ms>]");
+                return InputMessage::None;
+            }
+        };
+        let time_limit = args.iter().find_map(|arg| arg.strip_prefix("time=")?.parse::<u64>().ok());
+        return InputMessage::FindMate(moves, time_limit);
+    } else if args[0] == "epd" {
+        // epd <file> [time=<ms>] [depth=<int>] [threads=<int>] [count=<int>] [csv=<file>]
+        if args.len() < 2 {
+            println!("Usage: epd <file> [time=<ms>] [depth=<int>] [threads=<int>] [count=<int>] [csv=<file>]");
+            return InputMessage::None;
+        }
+        let mut settings = EpdSettings::default();
+        for arg in &args[2..] {
+            match arg.split_once('=') {
+                Some(("time", value)) => settings.search.time_limit = value.parse::<u64>().ok(),
+                Some(("depth", value)) => settings.search.depth = value.parse::<u8>().unwrap_or(settings.search.depth),
+                Some(("threads", value)) => settings.search.threads = value.parse::<usize>().unwrap_or(1),
+                Some(("count", value)) => settings.max_positions = value.parse::<usize>().unwrap_or(0),
+                Some(("csv", value)) => settings.csv_path = Some(value.to_string()),
+                _ => {}
+            }
+        }
+        return InputMessage::RunEpd(args[1].to_string(), settings);
+    } else if args[0] == "match" {
+        // match [a=<uci engine>] [b=<uci engine>] [a.<st param>] [b.<st param>] [games=<int>] [time=<ms>] [nodes=<int>]
+        //       [openings=<file>] [pgn=<file>] [sprt=<elo0>,<elo1>] [maxply=<int>]
+        let mut settings = MatchSettings::default();
+        let mut search = [SearchSettings::default(), SearchSettings::default()];
+        let mut uci_paths: [Option<String>; 2] = [None, None];
+        for arg in &args[1..] {
+            if let Some(param) = arg.strip_prefix("a.") {
+                parse_search_param(&mut search[0], param);
+                continue;
+            }
+            if let Some(param) = arg.strip_prefix("b.") {
+                parse_search_param(&mut search[1], param);
+                continue;
+            }
+            match arg.split_once('=') {
+                Some(("a", path)) => uci_paths[0] = Some(path.to_string()),
+                Some(("b", path)) => uci_paths[1] = Some(path.to_string()),
+                Some(("games", value)) => settings.games = value.parse::<usize>().unwrap_or(settings.games),
+                Some(("time", value)) => settings.time_per_move = value.parse::<u64>().unwrap_or(settings.time_per_move),
+                Some(("nodes", value)) => settings.nodes_per_move = value.parse::<u64>().ok(),
+                Some(("openings", path)) => settings.openings_path = Some(path.to_string()),
+                Some(("pgn", path)) => settings.pgn_path = path.to_string(),
+                Some(("maxply", value)) => settings.max_ply = value.parse::<usize>().unwrap_or(settings.max_ply),
+                Some(("sprt", bounds)) => {
+                    settings.sprt = bounds.split_once(',').and_then(|(elo0, elo1)| Some((elo0.parse().ok()?, elo1.parse().ok()?)))
+                }
+                _ => {}
+            }
+        }
+        let [search_a, search_b] = search;
+        let [uci_a, uci_b] = uci_paths;
+        let engine_a = uci_a.map_or(EngineConfig::Internal(search_a), EngineConfig::Uci);
+        let engine_b = uci_b.map_or(EngineConfig::Internal(search_b), EngineConfig::Uci);
+        return InputMessage::RunMatch(Box::new((settings, engine_a, engine_b)));
+    } else if args[0] == "uci" {
+        return InputMessage::Uci;
     } else if args[0] == "help" {
         print_help();
     }
     return InputMessage::None;
 }
 
+/**
+ * Applies one flag or var=<int> parameter of the search test to the settings.
+ */
+fn parse_search_param(settings: &mut SearchSettings, param: &str) {
+    match param {
+        "-nomo" => settings.move_order = false,
+        "-nosee" => settings.see = false,
+        "-noqchecks" => settings.qsearch_checks = false,
+        "-log" => settings.show_log = true,
+        _ => {
+            // check for var
+            let var = param.split("=").collect::<Vec<&str>>();
+            if var.len() != 2 {
+                return;
+            }
+            match var[0] {
+                "atpen" => {
+                    settings.move_on_attacked_penalty = var[1].parse::<i32>().unwrap()
+                }
+                "capt" => settings.capture_multiplier = var[1].parse::<i32>().unwrap(),
+                "castl" => settings.castle_reword = var[1].parse::<i32>().unwrap(),
+                "promo" => settings.promotion_bonus = var[1].parse::<i32>().unwrap(),
+                "depth" => settings.depth = var[1].parse::<u8>().unwrap(),
+                "threads" => settings.threads = var[1].parse::<usize>().unwrap(),
+                "hash" => settings.hash_size = var[1].parse::<usize>().unwrap(),
+                "time" => settings.time_limit = Some(var[1].parse::<u64>().unwrap()),
+                "nodes" => settings.node_limit = Some(var[1].parse::<u64>().unwrap()),
+                "multipv" => settings.multi_pv = var[1].parse::<usize>().unwrap(),
+                "tbdepth" => settings.tb_probe_depth = var[1].parse::<u8>().unwrap(),
+                "delta" => settings.delta_margin = var[1].parse::<i32>().unwrap(),
+                "tbpieces" => settings.tb_piece_limit = var[1].parse::<usize>().unwrap(),
+                "knowledge" => settings.positional_weight = var[1].parse::<i32>().unwrap(),
+                "skill" => settings.skill_level = Skill::new(var[1].parse::<u8>().unwrap()).level,
+                "elo" => settings.skill_level = Skill::from_elo(var[1].parse::<u32>().unwrap()).level,
+                _ => {}
+            }
+        }
+    }
+}
+
 fn print_help() {
     println!("Commands:");
     println!("m <move>              - make a move");
@@ -210,11 +437,26 @@ fn print_help() {
     println!("um                    - undo a move");
     println!("st -flags var=<int>   - run a search test");
     println!("    -nomo             - no move ordering");
+    println!("    -nosee            - no static exchange evaluation");
+    println!("    -noqchecks        - no quiet checks in the quiescence search");
     println!("    -log              - log the search");
     println!("    atpen=<int>       - move on attacked penalty");
     println!("    capt=<int>        - capture multiplier");
     println!("    castl=<int>       - castle reword");
     println!("    promo=<int>       - promotion bonus");
+    println!("    depth=<int>       - search depth");
+    println!("    threads=<int>     - number of lazy smp search threads (default 1, which is deterministic)");
+    println!("    hash=<int>        - transposition table size in MB");
+    println!("    time=<int>        - stop the search after this many milliseconds");
+    println!("    nodes=<int>       - stop the search after this many nodes");
+    println!("    multipv=<int>     - number of best lines to show");
+    println!("    delta=<int>       - delta pruning margin in the quiescence search (0 disables)");
+    println!("    skill=<int>       - skill level from 0 to 20 (full strength)");
+    println!("    elo=<int>         - skill level that plays at about this Elo (UCI_Elo)");
+    println!("    knowledge=<int>   - percentage of the positional evaluation that is used");
+    println!("    tbdepth=<int>     - min depth left to probe the tablebase in the search");
+    println!("    tbpieces=<int>    - max pieces to probe the tablebase");
+    println!("    (type stop while the search is running to stop it)");
     println!("rt <depth> -flags     - run a perftest");
     println!("    -d                - debug (show number of moves for each move)");
     println!("    -s                - show board (show the board after each move)");
@@ -222,10 +464,51 @@ fn print_help() {
     println!("    -t                - show time (show the time taken for each move)");
     println!("bit <type>            - show a bitboard");
     println!("    type is either epat (enemy_attack), epin (enemy_pins), echk (enemy_checks)");
-    println!("start                 - start a game (human (white) vs computer (black)");
-    println!("lichess               - accept a challenge of the lichess bot");
+    println!("start -vars           - start a game (human (white) vs computer (black) by default)");
+    println!("    white=<player>    - human, bot, random or the path of a uci engine (also black=<player>)");
+    println!("    white.<param>     - search parameter of the white bot like in st (also black.<param>)");
+    println!("    time=<int>        - milliseconds per move of uci engines (default 1000)");
+    println!("    depth=<int>       - search depth of uci engines instead of a time");
+    println!("    games=<int>       - number of games, the players switch colours after every game");
+    println!("    -fixed            - the players keep their colours");
+    println!("    pgn=<file>        - pgn file the games are appended to (default games.pgn)");
+    println!("    fen=<fen>         - starting position, must be the last parameter (default: current position)");
+    println!("    tc=<control>      - time control in minutes and seconds: 5+3 (increment), 15d5 (delay),");
+    println!("                        15b5 (bronstein), 40/90+30 (moves per period), 40/90,30+30 (periods)");
+    println!("    humans enter a move, resign, draw <move> to offer a draw or draw to accept one");
+    println!("lichess               - run the lichess bot, accepting challenges until Ctrl-C");
+    println!("board [seek <min> <inc> [rated]] - play your own lichess game (board api), seeking or waiting for a challenge");
+    println!("    -hint             - show an engine hint before each move");
     println!("score                 - show the score of the current position");
     println!("mo <color>            - show the move order for a color");
+    println!("see <move>            - show the static exchange evaluation of a move");
+    println!("book <path> [maxply]  - load a polyglot opening book for the bot (default max ply 20)");
+    println!("book                  - show the book moves of the current position");
+    println!("mkbook <out> <pgn>... - build a book from pgn files or directories (.bin for polyglot, else text)");
+    println!("    ply=<int>         - max ply of the book positions (default 20)");
+    println!("    elo=<int>         - min elo of both players");
+    println!("    results=<filter>  - all, decisive (no draws) or winner (only moves of the winner)");
+    println!("    games=<int>       - min number of games a move was played in");
+    println!("tb <dir>              - load syzygy tablebases for the search");
+    println!("tb                    - show the tablebase result (wdl and dtz) of the current position");
+    println!("mate <n> time=<ms>    - find a forced mate in n moves with checks only");
+    println!("epd <file> -vars      - run a test suite (bm, am, id and c0 opcodes) and print the solved positions");
+    println!("    time=<int>        - milliseconds per position (default 1000)");
+    println!("    depth=<int>       - max depth per position");
+    println!("    threads=<int>     - number of search threads");
+    println!("    count=<int>       - only run the first positions");
+    println!("    csv=<file>        - write the results as csv");
+    println!("uci                   - run as a uci engine for a chess gui, until quit");
+    println!("match -vars           - play engine a against engine b and print the elo difference");
+    println!("    a=<path> b=<path> - external uci engine instead of this engine, e.g. another build (started with uci)");
+    println!("    a.<st param>      - search settings of this engine as a (or b), e.g. a.-nosee b.delta=100");
+    println!("    games=<int>       - number of games, each opening is played with both colors (default 100)");
+    println!("    time=<int>        - milliseconds per move (default 100)");
+    println!("    nodes=<int>       - nodes per move instead of a time");
+    println!("    openings=<file>   - fen, epd or pgn file with the openings");
+    println!("    pgn=<file>        - pgn file the games are appended to (default match.pgn)");
+    println!("    sprt=<elo0>,<elo1> - stop when a is weaker than elo0 or stronger than elo1");
+    println!("    maxply=<int>      - games are drawn after this many plies");
     println!("quit/q                - quit");
 }
 
@@ -268,6 +551,39 @@ fn print_bitboard(game: &Game, bitboard_type: BitboardType) {
     });
 }
 
+fn print_tablebase(tablebase: &Tablebase, game: &mut Game) {
+    let wdl = match tablebase.probe_wdl(game) {
+        Some(wdl) => wdl,
+        None => {
+            println!("Position is not in the tablebase");
+            return;
+        }
+    };
+    let dtz = tablebase.probe_dtz(game).map_or("unknown".to_string(), |dtz| dtz.to_string());
+    println!("WDL: {}, DTZ: {}", wdl, dtz);
+    if let Some(mut root_moves) = tablebase.root_moves(game) {
+        root_moves.sort_by_key(|root_move| (std::cmp::Reverse(root_move.wdl), root_move.dtz.unwrap_or(0)));
+        for root_move in root_moves {
+            let dtz = root_move.dtz.map_or("unknown".to_string(), |dtz| dtz.to_string());
+            println!("{}: {} (dtz {})", root_move.mov.to_string(), root_move.wdl, dtz);
+        }
+    }
+}
+
+fn find_mate(game: &mut Game, moves: u8, time_limit: Option<u64>) {
+    let start = Instant::now();
+    let mut mate_search = MateSearch::new(game);
+    mate_search.time_limit = time_limit;
+    let result = mate_search.run(moves);
+    let nodes = mate_search.nodes;
+    match result {
+        MateResult::Mate(line) => println!("Mate in {}: {}", line.len().div_ceil(2), pv_to_string(&line)),
+        MateResult::NoMate => println!("No mate in {} found", moves),
+        MateResult::Stopped => println!("Time limit reached"),
+    }
+    println!("{} nodes in {}ms", nodes, start.elapsed().as_millis());
+}
+
 fn run_test(game: &mut Game, options: RunTestOptions) -> usize {
     if options.depth == 0 {
         return 1;
@@ -309,6 +625,8 @@ fn run_test(game: &mut Game, options: RunTestOptions) -> usize {
 #[tokio::main]
 async fn main() {
     let mut game = Game::from_fen(STARTING_POS_FEN);
+    let mut book: Option<Arc<OpeningBook>> = None;
+    let mut tablebase: Option<Arc<Tablebase>> = None;
     game.board.print();
 
     /*run_test(&mut game, RunTestOptions {
@@ -322,42 +640,79 @@ async fn main() {
     loop {
         let input = get_input();
         match input {
-            InputMessage::StartGame => {
-                println!("Starting game");
-                let players = (HumanPlayer, BotPlayer);
-                loop {
-                    let player: &dyn Player = if game.turn == Color::White {
-                        &players.0
-                    } else {
-                        &players.1
-                    };
-                    let mut mov = player.play(&mut game);
-                    while game.make_move(mov) == false {
-                        println!("Invalid move!");
-                        mov = player.play(&mut game);
+            InputMessage::StartGame(settings) => {
+                let mut launcher = GameLauncher::new(*settings);
+                launcher.book = book.clone();
+                launcher.tablebase = tablebase.clone();
+                launcher.run(&mut game).await;
+            }
+            InputMessage::LoadBook(path, max_ply) => match OpeningBook::load(&path) {
+                Ok(mut loaded) => {
+                    if let Some(max_ply) = max_ply {
+                        loaded.max_ply = max_ply;
                     }
-                    game.board.print();
-                    if game.get_possible_team_moves(game.turn).len() == 0 {
-                        println!("Checkmate!");
-                        println!("Winner: {:?}", game.turn.opposite());
-                        break;
+                    book = Some(Arc::new(loaded));
+                }
+                Err(err) => println!("{}", err),
+            },
+            InputMessage::ShowBook => match &book {
+                Some(book) => {
+                    println!("Polyglot key: {:016x}", book::polyglot_hash(&game));
+                    for (mov, weight) in book.moves(&game) {
+                        println!("{}: {}", mov.to_string(), weight);
+                    }
+                }
+                None => println!("No book loaded"),
+            },
+            InputMessage::BuildBook(out_path, pgn_paths, settings) => {
+                let mut builder = BookBuilder::new(settings);
+                let read = pgn_paths.iter().try_for_each(|path| builder.add_pgn(path));
+                match read.and_then(|_| builder.write(&out_path)) {
+                    Ok(entries) => println!(
+                        "Wrote {} entries to {} from {} of {} games",
+                        entries, out_path, builder.games_used, builder.games_read
+                    ),
+                    Err(err) => println!("{}", err),
+                }
+            }
+            InputMessage::LoadTablebase(path) => match Tablebase::load(&path) {
+                Ok(loaded) => tablebase = Some(Arc::new(loaded)),
+                Err(err) => println!("{}", err),
+            },
+            InputMessage::FindMate(moves, time_limit) => find_mate(&mut game, moves, time_limit),
+            InputMessage::RunMatch(config) => match MatchRunner::new(config.0, &config.1, &config.2) {
+                Ok(mut runner) => {
+                    runner.tablebase = tablebase.clone();
+                    if let Err(err) = runner.run() {
+                        println!("{}", err);
                     }
                 }
+                Err(err) => println!("{}", err),
+            },
+            InputMessage::Uci => {
+                // The GUI sends quit at the end, the process stops with it
+                Uci::new(tablebase.clone()).run();
+                break;
             }
+            InputMessage::RunEpd(path, mut settings) => match load_epd(&path) {
+                Ok(entries) => {
+                    settings.search.tablebase = tablebase.clone();
+                    run_epd(&entries, &settings);
+                }
+                Err(err) => println!("{}", err),
+            },
+            InputMessage::ShowTablebase => match &tablebase {
+                Some(tablebase) => print_tablebase(tablebase, &mut game),
+                None => println!("No tablebase loaded"),
+            },
             InputMessage::LichessChallenge => {
-                let mut online_bot = lichess::Lichess::new(&mut game);
-                online_bot
-                    .get_account()
-                    .await
-                    .expect("Failed to get account");
-                let challenge = online_bot
-                    .get_challenge()
-                    .await
-                    .expect("Failed to get challenger");
-                online_bot
-                    .stream_game(challenge)
-                    .await
-                    .expect("Failed to stream game");
+                let mut online_bot = lichess::Lichess::new();
+                online_bot.run().await;
+            }
+            InputMessage::LichessBoard(seek, hint) => {
+                let mut board = lichess_board::LichessBoard::new();
+                board.hint = hint;
+                board.run(seek).await;
             }
             InputMessage::ShowFen => {
                 println!("{}", game.to_fen());
@@ -399,11 +754,49 @@ async fn main() {
                     println!("------------------");
                 }
             }
-            InputMessage::RunSearchTest(settings) => {
-                let mut search = Search::new(&mut game);
-                search.settings = settings;
-                let mov = search.start();
-                println!("Best move: {}", mov.to_string());
+            InputMessage::RunSearchTest(mut settings) => {
+                settings.tablebase = tablebase.clone();
+                let handle = Search::spawn(game.clone(), settings);
+                // Other commands entered during the search are run after it
+                let mut queued_lines = Vec::new();
+                while !handle.is_finished() {
+                    while let Some(info) = handle.try_info() {
+                        println!("info {}", info);
+                    }
+                    if let Some(line) = input::try_read_line() {
+                        if line.trim() == "stop" {
+                            handle.stop();
+                        } else {
+                            queued_lines.push(line);
+                        }
+                    }
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+                for line in queued_lines.into_iter().rev() {
+                    input::unread_line(line);
+                }
+                while let Some(info) = handle.try_info() {
+                    println!("info {}", info);
+                }
+                let result = handle.join();
+                println!("Best move: {}", result.best_move.to_string());
+                if let Some(ponder_move) = result.ponder_move {
+                    println!("Ponder move: {}", ponder_move.to_string());
+                }
+                println!("Score: {} (depth {}, {} nodes)", result.score, result.depth, result.nodes);
+                println!("PV: {}", pv_to_string(&result.pv));
+                if result.lines.len() > 1 {
+                    for (i, line) in result.lines.iter().enumerate() {
+                        println!(
+                            "{}. {} {} (depth {}) {}",
+                            i + 1,
+                            line.mov.to_string(),
+                            line.score,
+                            line.depth,
+                            pv_to_string(&line.pv)
+                        );
+                    }
+                }
             }
             InputMessage::ShowMoves(pos) => {
                 if let Some(piece) = game.board.get_piece(pos) {
@@ -438,6 +831,14 @@ async fn main() {
                     println!("{}", m.to_string());
                 }
             }
+            InputMessage::ShowSee(mov) => {
+                // Look up the generated move so en passant and promotions are evaluated correctly
+                let moves = game.get_possible_team_moves(game.turn);
+                match moves.iter().find(|m| **m == mov && m.move_type.get_promotion_piece() == mov.move_type.get_promotion_piece()) {
+                    Some(found) => println!("SEE {}: {}", found.to_string(), game.see(*found)),
+                    None => println!("Move {} is not possible!", mov.to_string()),
+                }
+            }
             InputMessage::ShowBoard => game.board.print(),
             InputMessage::None => print_help(),
             InputMessage::Quit => break,
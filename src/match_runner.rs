@@ -0,0 +1,623 @@
+use std::sync::Arc;
+
+use crate::{
+    base_types::{Color, PieceType},
+    endgame::Material,
+    game::Game,
+    moves::Move,
+    pgn::{append_pgn, parse_pgn, PgnGame},
+    search::{is_same_move, Score, Search, SearchResult, SearchSettings},
+    tablebase::{piece_count, Tablebase, Wdl},
+    time_manager::{self, MAX_DEPTH},
+    tt::TranspositionTable,
+    uci_engine::{GoLimit, UciEngine},
+    STARTING_POS_FEN,
+};
+
+// Balanced positions after a few moves of common openings, used without an openings file
+static DEFAULT_OPENINGS: [&str; 8] = [
+    "rnbqkb1r/pp2pppp/3p1n2/8/3NP3/8/PPP2PPP/RNBQKB1R w KQkq - 1 5", // Sicilian
+    "r1bqkbnr/pppp1ppp/2n5/1B2p3/4P3/5N2/PPPP1PPP/RNBQK2R b KQkq - 3 3", // Ruy Lopez
+    "rnbqkb1r/ppp2ppp/4pn2/3p4/2PP4/2N5/PP2PPPP/R1BQKBNR w KQkq - 2 4", // Queen's gambit declined
+    "rnbqkb1r/pppppp1p/5np1/8/2PP4/8/PP2PPPP/RNBQKBNR w KQkq - 0 3", // King's indian
+    "rnbqkbnr/pp2pppp/2p5/3p4/3PP3/8/PPP2PPP/RNBQKBNR w KQkq - 0 3", // Caro-Kann
+    "rnbqkbnr/ppp2ppp/4p3/3p4/3PP3/8/PPP2PPP/RNBQKBNR w KQkq - 0 3", // French
+    "rnbqkb1r/pppp1ppp/5n2/4p3/2P5/2N5/PP1PPPPP/R1BQKBNR w KQkq - 2 3", // English
+    "r1bqk1nr/pppp1ppp/2n5/2b1p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4", // Italian
+];
+
+/**
+ * One side of the match: the engine of this build with its settings, or an external UCI engine.
+ */
+#[derive(Clone)]
+pub enum EngineConfig {
+    Internal(SearchSettings),
+    Uci(String), // Path of the executable
+}
+
+#[derive(Clone)]
+pub struct MatchSettings {
+    pub games: usize, // Every opening is played twice with the colors swapped
+    pub time_per_move: u64, // Milliseconds, used if there is no node limit
+    pub nodes_per_move: Option<u64>,
+    pub openings_path: Option<String>, // FEN or EPD lines, or a PGN file whose games are played to the end
+    pub pgn_path: String, // All games are appended to this file
+    pub max_ply: usize, // Longer games are drawn
+    pub resign_score: i32, // Games are adjudicated as a win if both engines agree on this score
+    pub resign_moves: usize, // for this many moves each
+    pub draw_score: i32, // and as a draw if the score stays within this
+    pub draw_moves: usize, // for this many moves each
+    pub draw_min_ply: usize, // after this ply
+    pub sprt: Option<(f64, f64)>, // Elo of the null and the alternative hypothesis. The match stops once one is accepted
+    pub alpha: f64, // Error probabilities of the SPRT
+    pub beta: f64,
+}
+
+impl Default for MatchSettings {
+    fn default() -> Self {
+        MatchSettings {
+            games: 100,
+            time_per_move: 100,
+            nodes_per_move: None,
+            openings_path: None,
+            pgn_path: "match.pgn".to_string(),
+            max_ply: 400,
+            resign_score: 1000,
+            resign_moves: 3,
+            draw_score: 10,
+            draw_moves: 8,
+            draw_min_ply: 80,
+            sprt: None,
+            alpha: 0.05,
+            beta: 0.05,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum GameResult {
+    WhiteWins,
+    BlackWins,
+    Draw,
+}
+
+impl GameResult {
+    pub fn to_pgn(self) -> &'static str {
+        match self {
+            GameResult::WhiteWins => "1-0",
+            GameResult::BlackWins => "0-1",
+            GameResult::Draw => "1/2-1/2",
+        }
+    }
+
+    fn win_for(color: Color) -> GameResult {
+        if let Color::White = color { GameResult::WhiteWins } else { GameResult::BlackWins }
+    }
+}
+
+/**
+ * Wins, draws and losses from the view of the first engine.
+ */
+#[derive(Copy, Clone, Debug, Default)]
+pub struct MatchScore {
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+}
+
+impl MatchScore {
+    pub fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+
+    /**
+     * Score per game between 0 and 1 and its variance.
+     */
+    fn mean_variance(&self) -> (f64, f64) {
+        let n = self.games() as f64;
+        let (w, d, l) = (self.wins as f64 / n, self.draws as f64 / n, self.losses as f64 / n);
+        let mean = w + d / 2.0;
+        let variance = w * (1.0 - mean).powi(2) + d * (0.5 - mean).powi(2) + l * mean.powi(2);
+        (mean, variance)
+    }
+
+    /**
+     * Elo difference and the half width of its 95% confidence interval.
+     */
+    pub fn elo(&self) -> (f64, f64) {
+        if self.games() == 0 {
+            return (0.0, 0.0);
+        }
+        let (mean, variance) = self.mean_variance();
+        let deviation = (variance / self.games() as f64).sqrt();
+        let elo = score_to_elo(mean);
+        let upper = score_to_elo(mean + 1.96 * deviation);
+        let lower = score_to_elo(mean - 1.96 * deviation);
+        (elo, (upper - lower) / 2.0)
+    }
+
+    /**
+     * Log likelihood ratio of the SPRT between elo0 and elo1, with the normal approximation of the trinomial model.
+     */
+    pub fn llr(&self, elo0: f64, elo1: f64) -> f64 {
+        if self.games() == 0 {
+            return 0.0;
+        }
+        let (mean, variance) = self.mean_variance();
+        // Only the same result so far, e.g. all draws. There is nothing to measure yet
+        if variance == 0.0 {
+            return 0.0;
+        }
+        let (score0, score1) = (elo_to_score(elo0), elo_to_score(elo1));
+        self.games() as f64 * (score1 - score0) * (2.0 * mean - score0 - score1) / (2.0 * variance)
+    }
+}
+
+fn score_to_elo(score: f64) -> f64 {
+    let score = score.clamp(0.001, 0.999);
+    -400.0 * (1.0 / score - 1.0).log10()
+}
+
+fn elo_to_score(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+enum MatchEngine {
+    Internal(String, SearchSettings), // The settings keep the transposition table of the game
+    Uci {
+        path: String,
+        name: String,
+        engine: Option<UciEngine>, // None after a crash or a timeout, until it is restarted
+    },
+}
+
+impl MatchEngine {
+    fn start(config: &EngineConfig, name: &str) -> Result<MatchEngine, String> {
+        match config {
+            EngineConfig::Internal(settings) => Ok(MatchEngine::Internal(name.to_string(), settings.clone())),
+            EngineConfig::Uci(path) => {
+                let engine = UciEngine::start(path)?;
+                Ok(MatchEngine::Uci {
+                    path: path.clone(),
+                    name: engine.name.clone(),
+                    engine: Some(engine),
+                })
+            }
+        }
+    }
+
+    fn name(&self) -> &str {
+        match self {
+            MatchEngine::Internal(name, _) => name,
+            MatchEngine::Uci { name, .. } => name,
+        }
+    }
+
+    /**
+     * Prepares the engine for a new game, restarting it once if it is not running or fails.
+     * The error is the reason the game is lost.
+     */
+    fn new_game(&mut self) -> Result<(), String> {
+        let (path, engine) = match self {
+            MatchEngine::Internal(_, settings) => {
+                settings.tt = Some(Arc::new(TranspositionTable::new(settings.hash_size)));
+                return Ok(());
+            }
+            MatchEngine::Uci { path, engine, .. } => (path, engine),
+        };
+        for _ in 0..2 {
+            let mut running = match engine.take() {
+                Some(running) => running,
+                None => match UciEngine::start(path) {
+                    Ok(started) => started,
+                    Err(err) => {
+                        println!("{}", err);
+                        return Err("crash".to_string());
+                    }
+                },
+            };
+            match running.new_game() {
+                Ok(()) => {
+                    *engine = Some(running);
+                    return Ok(());
+                }
+                Err(err) => println!("{}, restarting it", err),
+            }
+        }
+        Err("crash".to_string())
+    }
+
+    /**
+     * Best move and score from the view of the side to move. An engine that crashes or does not answer in time
+     * gets restarted for the next game, the error is the reason the game is lost.
+     */
+    fn go(&mut self, game: &Game, fen: &str, moves: &[String], limit: GoLimit) -> Result<(Move, Option<Score>), String> {
+        match self {
+            MatchEngine::Internal(_, settings) => {
+                let result = search_internal(settings, game, moves.len(), limit);
+                Ok((result.best_move, Some(result.score)))
+            }
+            MatchEngine::Uci { engine: slot, .. } => {
+                let mut engine = slot.take().ok_or("crash".to_string())?;
+                match engine.go(fen, moves, limit) {
+                    Ok(answer) => {
+                        *slot = Some(engine);
+                        let mov = Move::parse(&answer.best_move).ok_or(format!("invalid move {}", answer.best_move))?;
+                        Ok((mov, answer.score))
+                    }
+                    Err(err) => {
+                        println!("{}", err);
+                        Err(if engine.is_running() { "timeout" } else { "crash" }.to_string())
+                    }
+                }
+            }
+        }
+    }
+}
+
+/**
+ * Searches with the engine of this build. With a time or node limit the depth is unlimited, like on a clock.
+ */
+fn search_internal(settings: &SearchSettings, game: &Game, plies_played: usize, limit: GoLimit) -> SearchResult {
+    let mut game = game.clone();
+    let turn = game.turn;
+    let mut search = Search::new(&mut game);
+    search.settings = settings.clone();
+    match limit {
+        GoLimit::MoveTime(time) => {
+            search.settings.time_limit = Some(time);
+            search.settings.depth = MAX_DEPTH;
+        }
+        GoLimit::Nodes(nodes) => {
+            search.settings.node_limit = Some(nodes);
+            search.settings.depth = MAX_DEPTH;
+        }
+        GoLimit::Depth(depth) => search.settings.depth = depth,
+        GoLimit::Clock { wtime, btime, winc, binc, moves_to_go } => {
+            let (time_left, increment) = if turn == Color::White { (wtime, winc) } else { (btime, binc) };
+            let budget = time_manager::allocate(time_left, increment, moves_to_go, plies_played, 0);
+            search.settings = time_manager::timed_settings(&search.settings, budget);
+        }
+    }
+    search.run()
+}
+
+/**
+ * Plays two engines against each other and reports the Elo difference after every game.
+ */
+pub struct MatchRunner {
+    pub settings: MatchSettings,
+    pub tablebase: Option<Arc<Tablebase>>, // Positions in the tablebase are adjudicated
+    engines: [MatchEngine; 2],
+    openings: Vec<String>,
+    pub score: MatchScore,
+}
+
+impl MatchRunner {
+    pub fn new(settings: MatchSettings, engine_a: &EngineConfig, engine_b: &EngineConfig) -> Result<MatchRunner, String> {
+        let openings = match &settings.openings_path {
+            Some(path) => load_openings(path)?,
+            None => DEFAULT_OPENINGS.iter().map(|fen| fen.to_string()).collect(),
+        };
+        if openings.is_empty() {
+            return Err("No openings found".to_string());
+        }
+        Ok(MatchRunner {
+            settings,
+            tablebase: None,
+            engines: [MatchEngine::start(engine_a, "A")?, MatchEngine::start(engine_b, "B")?],
+            openings,
+            score: MatchScore::default(),
+        })
+    }
+
+    pub fn run(&mut self) -> Result<MatchScore, String> {
+        let names = [self.engines[0].name().to_string(), self.engines[1].name().to_string()];
+        println!("Match {} vs {}, {} games, {} openings", names[0], names[1], self.settings.games, self.openings.len());
+        let sprt_bounds = ((self.settings.beta / (1.0 - self.settings.alpha)).ln(), ((1.0 - self.settings.beta) / self.settings.alpha).ln());
+
+        for round in 0..self.settings.games {
+            let fen = self.openings[(round / 2) % self.openings.len()].clone();
+            let a_is_white = round % 2 == 0;
+            let (result, reason, san_moves) = self.play_game(&fen, a_is_white)?;
+
+            let a_wins = match result {
+                GameResult::WhiteWins => Some(a_is_white),
+                GameResult::BlackWins => Some(!a_is_white),
+                GameResult::Draw => None,
+            };
+            match a_wins {
+                Some(true) => self.score.wins += 1,
+                Some(false) => self.score.losses += 1,
+                None => self.score.draws += 1,
+            }
+            let (white, black) = if a_is_white { (&names[0], &names[1]) } else { (&names[1], &names[0]) };
+            self.write_pgn(round + 1, white, black, &fen, &san_moves, result, &reason)?;
+
+            let (elo, error) = self.score.elo();
+            println!(
+                "Game {}/{}: {} vs {} {} ({}). Score {} - {} - {}, Elo {:.1} +/- {:.1}",
+                round + 1,
+                self.settings.games,
+                white,
+                black,
+                result.to_pgn(),
+                reason,
+                self.score.wins,
+                self.score.losses,
+                self.score.draws,
+                elo,
+                error
+            );
+
+            if let Some((elo0, elo1)) = self.settings.sprt {
+                let llr = self.score.llr(elo0, elo1);
+                println!("LLR {:.2} ({:.2}, {:.2}) [{:.1}, {:.1}]", llr, sprt_bounds.0, sprt_bounds.1, elo0, elo1);
+                if llr >= sprt_bounds.1 {
+                    println!("SPRT: H1 accepted, {} is stronger by at least {} Elo", names[0], elo1);
+                    break;
+                }
+                if llr <= sprt_bounds.0 {
+                    println!("SPRT: H0 accepted, {} is not stronger by {} Elo", names[0], elo1);
+                    break;
+                }
+            }
+        }
+        Ok(self.score)
+    }
+
+    /**
+     * Plays one game from the opening. Returns the result, the reason and the moves in SAN.
+     */
+    fn play_game(&mut self, fen: &str, a_is_white: bool) -> Result<(GameResult, String, Vec<String>), String> {
+        let mut game = Game::from_fen(fen);
+        let mut halfmove_clock = fen.split_whitespace().nth(4).and_then(|clock| clock.parse::<usize>().ok()).unwrap_or(0);
+        let mut uci_moves: Vec<String> = Vec::new();
+        let mut san_moves: Vec<String> = Vec::new();
+        let mut hashes = vec![game.hash];
+        let mut resign_count: i32 = 0; // Plies in a row with a winning score, negative for black
+        let mut draw_count = 0;
+        let limit = match self.settings.nodes_per_move {
+            Some(nodes) => GoLimit::Nodes(nodes),
+            None => GoLimit::MoveTime(self.settings.time_per_move),
+        };
+        let color_of = |engine: usize| if (engine == 0) == a_is_white { Color::White } else { Color::Black };
+        for engine in 0..2 {
+            if let Err(failure) = self.engines[engine].new_game() {
+                let reason = format!("{} of {}", failure, self.engines[engine].name());
+                return Ok((GameResult::win_for(color_of(engine).opposite()), reason, san_moves));
+            }
+        }
+
+        loop {
+            if let Some((result, reason)) = self.game_end(&mut game, &hashes, halfmove_clock, uci_moves.len()) {
+                return Ok((result, reason, san_moves));
+            }
+
+            let mover = game.turn;
+            let engine = if (mover == Color::White) == a_is_white { 0 } else { 1 };
+            let (mov, score) = match self.engines[engine].go(&game, fen, &uci_moves, limit) {
+                Ok(answer) => answer,
+                Err(failure) => {
+                    let reason = format!("{} of {}", failure, self.engines[engine].name());
+                    return Ok((GameResult::win_for(mover.opposite()), reason, san_moves));
+                }
+            };
+            let legal = game.get_possible_team_moves(mover).into_iter().find(|m| is_same_move(*m, mov));
+            let mov = match legal {
+                Some(mov) => mov,
+                None => {
+                    let reason = format!("illegal move {} of {}", mov.to_string(), self.engines[engine].name());
+                    return Ok((GameResult::win_for(mover.opposite()), reason, san_moves));
+                }
+            };
+
+            // Adjudication by the score of both engines, from the view of white
+            let white_score = score.map(|score| {
+                let score = match score {
+                    Score::Cp(cp) => cp,
+                    Score::Mate(moves) => if moves > 0 { i32::MAX } else { -i32::MAX },
+                };
+                if let Color::White = mover { score } else { -score }
+            });
+            match white_score {
+                Some(score) if score.abs() >= self.settings.resign_score => {
+                    resign_count = if resign_count.signum() == score.signum() { resign_count + score.signum() } else { score.signum() };
+                }
+                _ => resign_count = 0,
+            }
+            match white_score {
+                Some(score) if score.abs() <= self.settings.draw_score && uci_moves.len() >= self.settings.draw_min_ply => draw_count += 1,
+                _ => draw_count = 0,
+            }
+
+            let moving_piece = game.board.get_piece(mov.from).map(|piece| piece.piece_type);
+            if mov.move_type.is_capture() || moving_piece == Some(PieceType::Pawn) {
+                halfmove_clock = 0;
+            } else {
+                halfmove_clock += 1;
+            }
+            san_moves.push(game.move_to_san(mov));
+            game.make_move(mov);
+            uci_moves.push(mov.to_string());
+            hashes.push(game.hash);
+
+            if resign_count.unsigned_abs() as usize >= 2 * self.settings.resign_moves {
+                let winner = if resign_count > 0 { GameResult::WhiteWins } else { GameResult::BlackWins };
+                return Ok((winner, "adjudicated by score".to_string(), san_moves));
+            }
+            if draw_count >= 2 * self.settings.draw_moves {
+                return Ok((GameResult::Draw, "adjudicated as draw by score".to_string(), san_moves));
+            }
+        }
+    }
+
+    fn game_end(&self, game: &mut Game, hashes: &[u64], halfmove_clock: usize, ply: usize) -> Option<(GameResult, String)> {
+        if game.get_possible_team_moves(game.turn).is_empty() {
+            return Some(if game.king_check != 0 {
+                (GameResult::win_for(game.turn.opposite()), "checkmate".to_string())
+            } else {
+                (GameResult::Draw, "stalemate".to_string())
+            });
+        }
+        if halfmove_clock >= 100 {
+            return Some((GameResult::Draw, "fifty move rule".to_string()));
+        }
+        if hashes.iter().filter(|hash| **hash == game.hash).count() >= 3 {
+            return Some((GameResult::Draw, "threefold repetition".to_string()));
+        }
+        if Material::new(game).is_insufficient() {
+            return Some((GameResult::Draw, "insufficient material".to_string()));
+        }
+        if let Some(tablebase) = &self.tablebase {
+            if piece_count(game) <= tablebase.max_pieces {
+                if let Some(wdl) = tablebase.probe_wdl(game) {
+                    let result = match wdl {
+                        Wdl::Win => GameResult::win_for(game.turn),
+                        Wdl::Loss => GameResult::win_for(game.turn.opposite()),
+                        _ => GameResult::Draw,
+                    };
+                    return Some((result, "adjudicated by tablebase".to_string()));
+                }
+            }
+        }
+        if ply >= self.settings.max_ply {
+            return Some((GameResult::Draw, "max length".to_string()));
+        }
+        None
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn write_pgn(&self, round: usize, white: &str, black: &str, fen: &str, san_moves: &[String], result: GameResult, reason: &str) -> Result<(), String> {
+        let mut headers = vec![
+            ("Event", "Engine match".to_string()),
+            ("Site", "local".to_string()),
+            ("Round", round.to_string()),
+            ("White", white.to_string()),
+            ("Black", black.to_string()),
+            ("Result", result.to_pgn().to_string()),
+        ];
+        if fen != STARTING_POS_FEN {
+            headers.push(("FEN", fen.to_string()));
+            headers.push(("SetUp", "1".to_string()));
+        }
+        let game = PgnGame {
+            headers: headers.into_iter().map(|(name, value)| (name.to_string(), value)).collect(),
+            moves: san_moves.to_vec(),
+            result: result.to_pgn().to_string(),
+        };
+        append_pgn(&self.settings.pgn_path, &game.to_pgn(Some(reason)))
+    }
+}
+
+/**
+ * Opening positions from a file: a PGN file gives the position at the end of every game,
+ * other files have a FEN or an EPD position on every line.
+ */
+pub fn load_openings(path: &str) -> Result<Vec<String>, String> {
+    let content = std::fs::read(path).map_err(|err| format!("Cant read {}: {}", path, err))?;
+    let content = String::from_utf8_lossy(&content);
+    if path.ends_with(".pgn") {
+        let mut openings = Vec::new();
+        for pgn_game in parse_pgn(&content) {
+            let mut game = Game::from_fen(pgn_game.header("FEN").unwrap_or(STARTING_POS_FEN));
+            for san in pgn_game.moves.iter() {
+                let mov = game.fide_to_move(san);
+                if !mov.is_valid() || !game.make_move(mov) {
+                    break;
+                }
+            }
+            openings.push(game.to_fen());
+        }
+        return Ok(openings);
+    }
+
+    Ok(content
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 4 {
+                return None;
+            }
+            // EPD positions have no move counters
+            match (fields.get(4).and_then(|f| f.parse::<u32>().ok()), fields.get(5).and_then(|f| f.parse::<u32>().ok())) {
+                (Some(_), Some(_)) => Some(fields[..6].join(" ")),
+                _ => Some(format!("{} 0 1", fields[..4].join(" "))),
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    fn score(wins: u32, draws: u32, losses: u32) -> MatchScore {
+        MatchScore { wins, draws, losses }
+    }
+
+    fn assert_close(value: f64, expected: f64) {
+        assert!((value - expected).abs() < 1e-3, "{} != {}", value, expected);
+    }
+
+    #[test]
+    fn elo_and_error_bars_of_known_results() {
+        let (elo, error) = score(10, 10, 10).elo();
+        assert_close(elo, 0.0);
+        assert_close(error, 104.560);
+        let (elo, error) = score(60, 20, 20).elo();
+        assert_close(elo, 147.191);
+        assert_close(error, 66.015);
+        assert_eq!(score(0, 0, 0).elo(), (0.0, 0.0));
+    }
+
+    #[test]
+    fn llr_of_known_results() {
+        assert_close(score(60, 20, 20).llr(0.0, 10.0), 1.7337);
+        assert_close(score(20, 60, 20).llr(0.0, 5.0), -0.0259);
+        // No losses at all still has a variance
+        assert_close(score(10, 10, 0).llr(0.0, 10.0), 1.1179);
+        // Only draws, or no games, measure nothing
+        assert_eq!(score(0, 12, 0).llr(0.0, 10.0), 0.0);
+        assert_eq!(score(0, 0, 0).llr(0.0, 10.0), 0.0);
+    }
+
+    #[test]
+    fn internal_engine_searches_until_the_limit() {
+        let game = Game::from_fen(STARTING_POS_FEN);
+        let settings = SearchSettings::default();
+
+        let start = Instant::now();
+        let result = search_internal(&settings, &game, 0, GoLimit::MoveTime(600));
+        // No new iteration is started after half of the time
+        assert!(start.elapsed().as_millis() >= 300);
+        assert!(result.depth > settings.depth);
+
+        let result = search_internal(&settings, &game, 0, GoLimit::Nodes(150000));
+        assert!(result.nodes >= 150000);
+        assert!(result.depth > settings.depth);
+
+        assert_eq!(search_internal(&settings, &game, 0, GoLimit::Depth(2)).depth, 2);
+    }
+
+    #[test]
+    fn internal_engine_keeps_its_table_for_the_game() {
+        let mut engine = MatchEngine::start(&EngineConfig::Internal(SearchSettings::default()), "A").unwrap();
+        engine.new_game().unwrap();
+        let table = |engine: &MatchEngine| match engine {
+            MatchEngine::Internal(_, settings) => settings.tt.clone().unwrap(),
+            _ => unreachable!(),
+        };
+        let tt = table(&engine);
+        let game = Game::from_fen(STARTING_POS_FEN);
+        engine.go(&game, STARTING_POS_FEN, &[], GoLimit::Depth(3)).unwrap();
+        assert!(Arc::ptr_eq(&tt, &table(&engine)));
+        assert!(tt.hashfull() > 0);
+
+        engine.new_game().unwrap();
+        assert!(!Arc::ptr_eq(&tt, &table(&engine)));
+    }
+}
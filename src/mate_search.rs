@@ -0,0 +1,188 @@
+use std::collections::HashSet;
+use std::time::Instant;
+
+use crate::{game::Game, moves::Move};
+
+/**
+ * Result of a mate search.
+ */
+pub enum MateResult {
+    Mate(Vec<Move>), // The mating line, attacker moves and the longest defence
+    NoMate,          // There is no forced mate with checks only in the given number of moves
+    Stopped,         // The time limit was reached
+}
+
+/**
+ * Solver for "mate in N" problems. The attacker only plays checks, the defender all legal moves.
+ * Mates are searched with increasing length, so the shortest one is found first.
+ */
+pub struct MateSearch<'a> {
+    pub nodes: u64,
+    pub time_limit: Option<u64>, // Milliseconds
+    game: &'a mut Game,
+    refuted: HashSet<(u64, u8)>, // Attacker positions without a mate in that many moves
+    start_time: Instant,
+    polls: u64, // Calls of is_time_up, the clock is checked every 1024 of them
+    stopped: bool,
+}
+
+impl<'a> MateSearch<'a> {
+    pub fn new(game: &'a mut Game) -> MateSearch<'a> {
+        MateSearch {
+            nodes: 0,
+            time_limit: None,
+            game,
+            refuted: HashSet::new(),
+            start_time: Instant::now(),
+            polls: 0,
+            stopped: false,
+        }
+    }
+
+    /**
+     * Searches a mate for the side to move in at most `moves` moves.
+     */
+    pub fn run(&mut self, moves: u8) -> MateResult {
+        self.start_time = Instant::now();
+        self.polls = 0;
+        self.stopped = false;
+        for n in 1..=moves {
+            if let Some(line) = self.attack(n) {
+                return MateResult::Mate(line);
+            }
+            if self.stopped {
+                return MateResult::Stopped;
+            }
+        }
+        MateResult::NoMate
+    }
+
+    fn is_time_up(&mut self) -> bool {
+        if let Some(time_limit) = self.time_limit {
+            // Checking the clock is slow, only do it every few calls
+            if self.polls & 1023 == 0 && self.start_time.elapsed().as_millis() as u64 >= time_limit {
+                self.stopped = true;
+            }
+            self.polls += 1;
+        }
+        self.stopped
+    }
+
+    /**
+     * The attacker is to move and has `n` moves left. Returns the mating line if a check forces mate.
+     */
+    fn attack(&mut self, n: u8) -> Option<Vec<Move>> {
+        if n == 0 || self.is_time_up() {
+            return None;
+        }
+        let hash = self.game.hash;
+        if self.refuted.contains(&(hash, n)) {
+            return None;
+        }
+
+        for m in self.ordered_checks() {
+            self.game.make_move(m);
+            self.nodes += 1;
+            let defence = self.defend(n);
+            self.game.unmake_move();
+
+            if let Some(mut line) = defence {
+                line.insert(0, m);
+                return Some(line);
+            }
+            if self.stopped {
+                return None;
+            }
+        }
+
+        self.refuted.insert((hash, n));
+        None
+    }
+
+    /**
+     * The defender is in check and the attacker has `n` moves left including the check just played.
+     * Returns the longest defence if every move gets mated.
+     */
+    fn defend(&mut self, n: u8) -> Option<Vec<Move>> {
+        let moves = self.game.get_possible_team_moves(self.game.turn);
+        if moves.is_empty() {
+            // Checkmate, the attacker only plays checks so it can't be stalemate
+            return Some(Vec::new());
+        }
+        if n == 1 {
+            return None;
+        }
+
+        let mut longest: Option<Vec<Move>> = None;
+        for m in moves {
+            self.game.make_move(m);
+            self.nodes += 1;
+            let attack = self.attack(n - 1);
+            self.game.unmake_move();
+
+            let mut line = attack?;
+            line.insert(0, m);
+            if longest.as_ref().is_none_or(|longest| line.len() > longest.len()) {
+                longest = Some(line);
+            }
+        }
+        longest
+    }
+
+    /**
+     * Moves of the side to move that give check. Captures and promotions first,
+     * they are the most forcing.
+     */
+    fn ordered_checks(&mut self) -> Vec<Move> {
+        let moves = self.game.get_possible_team_moves(self.game.turn);
+        let mut checks = Vec::new();
+        for m in moves {
+            self.game.make_move(m);
+            // The checks against the side to move are computed when the move is made
+            if self.game.king_check != 0 {
+                checks.push(m);
+            }
+            self.game.unmake_move();
+        }
+        checks.sort_by_key(|m| !(m.move_type.is_capture() || m.move_type.is_promotion()));
+        checks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find_mate(fen: &str, moves: u8) -> Option<String> {
+        let mut game = Game::from_fen(fen);
+        match MateSearch::new(&mut game).run(moves) {
+            MateResult::Mate(line) => Some(line.iter().map(|m| m.to_string()).collect::<Vec<String>>().join(" ")),
+            MateResult::NoMate => None,
+            MateResult::Stopped => panic!("Stopped without a time limit"),
+        }
+    }
+
+    #[test]
+    fn finds_a_mate_in_two() {
+        let fen = "r2qkb1r/pp2nppp/3p4/2pNN1B1/2BnP3/3P4/PPP2PPP/R2bK2R w KQkq - 1 1";
+        assert_eq!(find_mate(fen, 1), None);
+        assert_eq!(find_mate(fen, 2).as_deref(), Some("d5f6 g7f6 c4f7"));
+    }
+
+    #[test]
+    fn finds_a_mate_in_three_with_the_longest_defence() {
+        // Rook ladder, the king runs to the corner to last a move longer
+        let fen = "8/8/5k2/R7/1R6/8/8/6K1 w - - 0 1";
+        assert_eq!(find_mate(fen, 2), None);
+        assert_eq!(find_mate(fen, 3).as_deref(), Some("b4b6 f6g7 a5a7 g7h8 b6b8"));
+    }
+
+    #[test]
+    fn reports_no_mate_and_stops_at_the_time_limit() {
+        assert_eq!(find_mate("4k3/8/8/8/8/8/8/4K2R w K - 0 1", 3), None);
+        let mut game = Game::from_fen("4k3/8/8/8/8/8/8/4K2R w K - 0 1");
+        let mut mate_search = MateSearch::new(&mut game);
+        mate_search.time_limit = Some(0);
+        assert!(matches!(mate_search.run(3), MateResult::Stopped));
+    }
+}
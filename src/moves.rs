@@ -86,6 +86,7 @@ pub struct Move {
 }
 
 impl Move {
+    #[allow(dead_code)]
     pub const fn new(from : Position, to : Position) -> Move {
         Move {
             from,
@@ -117,6 +118,24 @@ impl Move {
         return self.from.to_string() + &self.to.to_string() + promotion_str;
     }
 
+    /**
+     * Like `from_string`, but returns None for anything that is not a move in UCI notation.
+     */
+    pub fn parse(string : &str) -> Option<Move> {
+        let bytes = string.as_bytes();
+        if bytes.len() != 4 && bytes.len() != 5 {
+            return None;
+        }
+        let is_square = |file: u8, rank: u8| (b'a'..=b'h').contains(&file) && (b'1'..=b'8').contains(&rank);
+        if !is_square(bytes[0], bytes[1]) || !is_square(bytes[2], bytes[3]) {
+            return None;
+        }
+        if bytes.len() == 5 && !matches!(bytes[4], b'n' | b'b' | b'r' | b'q') {
+            return None;
+        }
+        Some(Move::from_string(string))
+    }
+
     pub fn from_string(string : &str) -> Move {
         let mut iter = string.chars();
         let from_x = iter.next().unwrap() as u8 - 'a' as u8;
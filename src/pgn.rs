@@ -0,0 +1,241 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+
+/**
+ * A game read from a PGN file. The moves are in SAN as written in the file,
+ * comments, variations and annotations are dropped.
+ */
+#[derive(Clone, Debug, Default)]
+pub struct PgnGame {
+    pub headers: Vec<(String, String)>,
+    pub moves: Vec<String>,
+    pub result: String, // 1-0, 0-1, 1/2-1/2 or * if unknown
+}
+
+impl PgnGame {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+
+    /**
+     * The game as PGN text with lines of at most 80 characters. Move numbers continue from the FEN header
+     * if there is one, the comment (e.g. why the game ended) is written before the result.
+     */
+    pub fn to_pgn(&self, comment: Option<&str>) -> String {
+        let mut pgn = String::new();
+        for (name, value) in &self.headers {
+            pgn += &format!("[{} \"{}\"]\n", name, value);
+        }
+        pgn += "\n";
+
+        let fields: Vec<&str> = self.header("FEN").unwrap_or("").split_whitespace().collect();
+        let mut move_number = fields.get(5).and_then(|number| number.parse::<usize>().ok()).unwrap_or(1);
+        let mut white_to_move = fields.get(1) != Some(&"b");
+        let mut line = String::new();
+        for (i, san) in self.moves.iter().enumerate() {
+            let token = if white_to_move {
+                format!("{}. {}", move_number, san)
+            } else if i == 0 {
+                format!("{}... {}", move_number, san)
+            } else {
+                san.clone()
+            };
+            if !white_to_move {
+                move_number += 1;
+            }
+            white_to_move = !white_to_move;
+            if line.len() + token.len() >= 80 {
+                pgn += line.trim_end();
+                pgn += "\n";
+                line.clear();
+            }
+            line += &token;
+            line += " ";
+        }
+        let ending = match comment {
+            Some(comment) => format!("{{{}}} {}", comment, self.result),
+            None => self.result.clone(),
+        };
+        if line.len() + ending.len() >= 80 {
+            pgn += line.trim_end();
+            pgn += "\n";
+            line.clear();
+        }
+        pgn + &format!("{}{}\n\n", line, ending)
+    }
+}
+
+/**
+ * Appends PGN text to the file, creating it if it does not exist.
+ */
+pub fn append_pgn(path: &str, pgn: &str) -> Result<(), String> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|err| format!("Cant open {}: {}", path, err))?;
+    file.write_all(pgn.as_bytes()).map_err(|err| format!("Cant write {}: {}", path, err))
+}
+
+const RESULTS: [&str; 4] = ["1-0", "0-1", "1/2-1/2", "*"];
+
+/**
+ * Reads all games of a PGN file. Movetext without headers is read as a single game.
+ */
+pub fn parse_pgn(content: &str) -> Vec<PgnGame> {
+    let mut games = Vec::new();
+    let mut game = PgnGame::default();
+    let mut in_movetext = false;
+    let mut comment_depth = 0; // Inside {} comments
+    let mut variation_depth = 0; // Inside () variations
+
+    for line in content.lines() {
+        let mut line = line.trim();
+        while comment_depth == 0 && line.starts_with('[') {
+            // A header after movetext starts the next game, even if the result token was missing
+            if in_movetext {
+                finish_game(&mut games, &mut game);
+                in_movetext = false;
+            }
+            let end = line.find(']').map_or(line.len(), |end| end + 1);
+            if let Some(header) = parse_header(&line[..end]) {
+                game.headers.push(header);
+            }
+            line = line[end..].trim_start();
+        }
+        if comment_depth == 0 && line.starts_with('%') {
+            continue;
+        }
+
+        // Drop comments and variations, they can span multiple lines
+        let mut text = String::new();
+        for c in line.chars() {
+            if comment_depth > 0 {
+                if c == '}' {
+                    comment_depth -= 1;
+                }
+                continue;
+            }
+            match c {
+                '{' => comment_depth += 1,
+                ';' => break, // Rest of the line is a comment
+                '(' => variation_depth += 1,
+                ')' => variation_depth = (variation_depth - 1).max(0),
+                _ if variation_depth > 0 => {}
+                c => text.push(c),
+            }
+            if "{}()".contains(c) {
+                text.push(' ');
+            }
+        }
+
+        for token in text.split_whitespace() {
+            in_movetext = true;
+            if RESULTS.contains(&token) {
+                game.result = token.to_string();
+                finish_game(&mut games, &mut game);
+                in_movetext = false;
+            } else if let Some(mov) = move_token(token) {
+                game.moves.push(mov);
+            }
+        }
+    }
+    if in_movetext || !game.headers.is_empty() {
+        finish_game(&mut games, &mut game);
+    }
+    games
+}
+
+fn finish_game(games: &mut Vec<PgnGame>, game: &mut PgnGame) {
+    let mut finished = std::mem::take(game);
+    if finished.result.is_empty() {
+        finished.result = finished.header("Result").unwrap_or("*").to_string();
+    }
+    games.push(finished);
+}
+
+fn parse_header(line: &str) -> Option<(String, String)> {
+    let inner = line.strip_prefix('[')?.strip_suffix(']')?;
+    let (name, value) = inner.split_once(' ')?;
+    let value = value.trim().trim_matches('"').replace("\\\"", "\"");
+    Some((name.to_string(), value))
+}
+
+/**
+ * Strips move numbers ("12." or "12...") and annotations ("!?", "$1") from a token.
+ * Returns None if nothing of a move is left.
+ */
+fn move_token(token: &str) -> Option<String> {
+    if token.starts_with('$') {
+        return None;
+    }
+    let mut mov = token;
+    if let Some(dot) = token.find('.') {
+        if token[..dot].chars().all(|c| c.is_ascii_digit()) {
+            mov = token[dot..].trim_start_matches('.');
+        }
+    }
+    let mov = mov.trim_end_matches(['!', '?']);
+    if mov.is_empty() || mov.chars().all(|c| c.is_ascii_digit()) {
+        None
+    } else {
+        Some(mov.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_comments_variations_and_annotations() {
+        let pgn = r#"[Event "Test"]
+[White "A"]
+[Black "B"]
+[Result "1-0"]
+
+1. e4 {best by test} e5 (1... c5 2. Nf3 (2. c3) d6) 2. Nf3!? $1 Nc6 ; the rest of the line is a comment 3. d4
+3. Bb5 {a comment
+over two lines (with a bracket)} 3...a6?! 4. Ba4 $14 1-0
+
+% escaped line 1. d4
+1. d4 d5 *
+"#;
+        let games = parse_pgn(pgn);
+        assert_eq!(games.len(), 2);
+        assert_eq!(games[0].header("White"), Some("A"));
+        assert_eq!(games[0].header("Result"), Some("1-0"));
+        assert_eq!(games[0].moves, vec!["e4", "e5", "Nf3", "Nc6", "Bb5", "a6", "Ba4"]);
+        assert_eq!(games[0].result, "1-0");
+        assert!(games[1].headers.is_empty());
+        assert_eq!(games[1].moves, vec!["d4", "d5"]);
+        assert_eq!(games[1].result, "*");
+    }
+
+    #[test]
+    fn header_after_movetext_starts_the_next_game() {
+        let pgn = "[Result \"0-1\"]\n\n1. f3 e5 2. g4 Qh4#\n[Result \"1/2-1/2\"]\n1. e4 1/2-1/2\n";
+        let games = parse_pgn(pgn);
+        assert_eq!(games.len(), 2);
+        assert_eq!(games[0].moves, vec!["f3", "e5", "g4", "Qh4#"]);
+        // The result token was missing, it comes from the header
+        assert_eq!(games[0].result, "0-1");
+        assert_eq!(games[1].moves, vec!["e4"]);
+        assert_eq!(games[1].result, "1/2-1/2");
+    }
+
+    #[test]
+    fn written_pgn_reads_back() {
+        let game = PgnGame {
+            headers: vec![("White".to_string(), "A".to_string()), ("FEN".to_string(), "8/8/8/8/8/8/8/8 b - - 0 12".to_string())],
+            moves: ["Kd7", "Ke2", "Kc6"].iter().map(|m| m.to_string()).collect(),
+            result: "*".to_string(),
+        };
+        let pgn = game.to_pgn(Some("time forfeit"));
+        assert!(pgn.contains("12... Kd7 13. Ke2 Kc6 {time forfeit} *"), "{}", pgn);
+        let read = parse_pgn(&pgn);
+        assert_eq!(read.len(), 1);
+        assert_eq!(read[0].moves, game.moves);
+        assert_eq!(read[0].result, "*");
+    }
+}
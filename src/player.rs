@@ -1,34 +1,525 @@
-use std::io;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
 
-use crate::{moves::Move, game::Game, search::Search};
+use crate::{
+    base_types::Color,
+    book::{self, OpeningBook},
+    game::Game,
+    input,
+    moves::Move,
+    search::{Score, Search, SearchHandle, SearchResult, SearchSettings},
+    time_manager,
+    uci_engine::{EngineMove, GoLimit, UciEngine},
+};
 
-pub trait Player {
-    fn play(&self, game: &mut Game) -> Move;
+// How often a search is checked for being finished while waiting for it
+const SEARCH_POLL_INTERVAL: Duration = Duration::from_millis(2);
+
+/**
+ * Clock of one side. All times are in milliseconds.
+ */
+#[derive(Copy, Clone, Debug)]
+pub struct Clock {
+    pub time_left: u64,
+    pub increment: u64,
+    pub moves_to_go: Option<u32>, // Moves until the next time control, if there is one
+}
+
+/**
+ * Everything about the game a player can not see on the board.
+ */
+#[derive(Clone, Debug)]
+pub struct GameContext {
+    pub color: Color, // The side of the player
+    pub initial_fen: String,
+    pub moves: Vec<String>, // Moves in UCI notation played since the initial position
+    pub white_clock: Option<Clock>, // None in untimed games
+    pub black_clock: Option<Clock>,
+    pub overhead: u64, // Milliseconds lost per move outside of the player, e.g. network latency
+    pub opponent: Option<String>,
+    pub opponent_rating: Option<u32>,
+    pub draw_offered: bool, // The opponent offered a draw with its last move
+}
+
+impl GameContext {
+    pub fn new(color: Color, initial_fen: &str) -> GameContext {
+        GameContext {
+            color,
+            initial_fen: initial_fen.to_string(),
+            moves: Vec::new(),
+            white_clock: None,
+            black_clock: None,
+            overhead: 0,
+            opponent: None,
+            opponent_rating: None,
+            draw_offered: false,
+        }
+    }
+
+    pub fn clock(&self) -> Option<Clock> {
+        match self.color {
+            Color::White => self.white_clock,
+            Color::Black => self.black_clock,
+        }
+    }
+}
+
+/**
+ * What a player does on its turn.
+ */
+#[derive(Copy, Clone, Debug)]
+pub enum PlayerAction {
+    Move(Move),
+    OfferDraw(Move), // Plays the move and offers a draw
+    AcceptDraw, // Only valid if the opponent offered a draw with its last move
+    Resign,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Decision {
+    pub action: PlayerAction,
+    pub score: Option<Score>, // From the view of the player, None if it did not search (e.g. a book move)
 }
 
+impl Decision {
+    fn play(mov: Move, score: Option<Score>) -> Decision {
+        Decision {
+            action: PlayerAction::Move(mov),
+            score,
+        }
+    }
+}
+
+/**
+ * How a game ended.
+ */
+#[derive(Clone, Debug)]
+pub struct GameOutcome {
+    pub winner: Option<Color>, // None for a draw or an aborted game
+    pub reason: String, // E.g. "checkmate" or "resign"
+}
+
+impl GameOutcome {
+    pub fn new(winner: Option<Color>, reason: &str) -> GameOutcome {
+        GameOutcome {
+            winner,
+            reason: reason.to_string(),
+        }
+    }
+
+    /**
+     * The result as written in PGN.
+     */
+    pub fn result(&self) -> &'static str {
+        match self.winner {
+            Some(Color::White) => "1-0",
+            Some(Color::Black) => "0-1",
+            None => "1/2-1/2",
+        }
+    }
+}
+
+/**
+ * The move of a player. It fails if the player can not move at all, e.g. when an engine keeps crashing.
+ */
+pub type PlayFuture<'a> = Pin<Box<dyn Future<Output = Result<Decision, String>> + Send + 'a>>;
+
+/**
+ * Someone or something playing one side of a game. The same players are used by the local game loop
+ * and the Lichess clients. Players never block the async runtime while they think, so other games keep
+ * going, and dropping the future of a player whose clock ran out stops waiting for it.
+ */
+pub trait Player: Send {
+    fn name(&self) -> String;
 
+    /**
+     * Called once before the first move of a game.
+     */
+    fn new_game(&mut self, _context: &GameContext) {}
+
+    /**
+     * Chooses what to do in the position of the game, which is always the side of the player to move.
+     */
+    fn play<'a>(&'a mut self, game: &'a Game, context: &'a GameContext) -> PlayFuture<'a>;
+
+    /**
+     * Called after the move of the opponent was played. The move is already in the context.
+     */
+    fn opponent_moved(&mut self, _mov: Move, _context: &GameContext) {}
+
+    fn game_over(&mut self, _outcome: &GameOutcome) {}
+}
+
+/**
+ * Moves entered in the terminal. Besides moves it understands "resign", "draw" to accept a draw
+ * offer and "draw <move>" to play the move and offer a draw.
+ */
 pub struct HumanPlayer;
-pub struct BotPlayer;
+
+/**
+ * Plays a random legal move. The weakest possible opponent, and a quick way to test the other player.
+ */
+pub struct RandomPlayer;
+
+/**
+ * Our own search. It can use an opening book and ponder on the expected reply while the opponent thinks.
+ */
+pub struct BotPlayer {
+    pub settings: SearchSettings, // Searched to the depth of the settings in untimed games
+    pub book: Option<Arc<OpeningBook>>, // Consulted before searching
+    pub ponder: bool,
+    pondering: Option<Ponder>,
+}
+
+/**
+ * Search running on the position after the reply we expect from the opponent.
+ */
+struct Ponder {
+    expected_move: Move,
+    ply: usize, // Number of moves played after the expected move
+    handle: SearchHandle,
+}
+
+/**
+ * A UCI engine installed on this machine. The engine is restarted once if it crashes or hangs during a move.
+ */
+pub struct ExternalEnginePlayer {
+    pub path: String,
+    pub limit: GoLimit, // Used in untimed games
+    name: String,
+    engine: Option<UciEngine>, // None after a crash
+}
 
 impl Player for HumanPlayer {
-    fn play(&self, _: &mut Game) -> Move {
-        let mut input = String::new();
-        io::stdin().read_line(&mut input).expect("Failed to read line");
-        Move::from_string(input.as_str())
+    fn name(&self) -> String {
+        "Human".to_string()
+    }
+
+    fn play<'a>(&'a mut self, game: &'a Game, context: &'a GameContext) -> PlayFuture<'a> {
+        Box::pin(read_action(game.clone(), context.draw_offered))
     }
 }
 
-impl Player for BotPlayer {
-    fn play(&self, game: &mut Game) -> Move {
+/**
+ * Reads lines until one is a legal move or another valid action.
+ */
+async fn read_action(mut game: Game, draw_offered: bool) -> Result<Decision, String> {
+    loop {
+        let line = input::read_line_async().await;
+        if line.is_empty() {
+            return Err("The input was closed".to_string());
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let action = match words.as_slice() {
+            ["resign"] => Some(PlayerAction::Resign),
+            ["draw"] if draw_offered => Some(PlayerAction::AcceptDraw),
+            ["draw", mov] => Move::parse(mov).map(PlayerAction::OfferDraw),
+            [mov] => Move::parse(mov).map(PlayerAction::Move),
+            _ => None,
+        };
+        let legal = match action {
+            Some(PlayerAction::Move(mov)) | Some(PlayerAction::OfferDraw(mov)) => {
+                let legal = game.make_move(mov);
+                if legal {
+                    game.unmake_move();
+                }
+                legal
+            }
+            Some(_) => true,
+            None => false,
+        };
+        match action {
+            Some(action) if legal => return Ok(Decision { action, score: None }),
+            _ => println!("Invalid move!"),
+        }
+    }
+}
+
+impl Player for RandomPlayer {
+    fn name(&self) -> String {
+        "Random".to_string()
+    }
+
+    fn play<'a>(&'a mut self, game: &'a Game, context: &'a GameContext) -> PlayFuture<'a> {
         let moves = game.get_possible_team_moves(game.turn);
-        if moves.len() == 0 {
-            println!("No moves available");
-            return Move::invalid();
+        let pick = book::random(game.hash ^ context.moves.len() as u64) as usize;
+        Box::pin(async move {
+            let mov = moves.get(pick % moves.len().max(1)).ok_or("No moves available".to_string())?;
+            Ok(Decision::play(*mov, None))
+        })
+    }
+}
+
+impl BotPlayer {
+    pub fn new(settings: SearchSettings, book: Option<Arc<OpeningBook>>) -> BotPlayer {
+        BotPlayer {
+            settings,
+            book,
+            ponder: false,
+            pondering: None,
         }
-        
-        let mut search = Search::new(game);
-        let result = search.start();
-        println!("Bot moves: {}", result.to_string());
-        result
     }
-}
\ No newline at end of file
+
+    /**
+     * Search settings for our move with the time budget taken from our clock.
+     * Without a clock the search goes to the fixed depth of the settings.
+     */
+    fn clock_settings(&self, context: &GameContext) -> SearchSettings {
+        let clock = match context.clock() {
+            Some(clock) => clock,
+            None => return self.settings.clone(),
+        };
+        let budget = time_manager::allocate(
+            clock.time_left,
+            clock.increment,
+            clock.moves_to_go,
+            context.moves.len(),
+            context.overhead,
+        );
+        if budget.panic {
+            println!("Low on time ({}ms), searching {}ms", clock.time_left, budget.time);
+        }
+        time_manager::timed_settings(&self.settings, budget)
+    }
+
+    /**
+     * Finds our move. If we pondered on the move the opponent just played, the ponder search
+     * continues as the normal search, otherwise it gets thrown away.
+     */
+    async fn think(&mut self, game: &Game, context: &GameContext, settings: &SearchSettings) -> SearchResult {
+        if let Some(ponder) = self.pondering.take() {
+            let opponent_move = context.moves.last().and_then(|m| Move::parse(m));
+            let hit = ponder.ply == context.moves.len()
+                && opponent_move.is_some_and(|m| {
+                    m == ponder.expected_move
+                        && m.move_type.get_promotion_piece() == ponder.expected_move.move_type.get_promotion_piece()
+                });
+            if hit {
+                println!("Ponderhit on {}", ponder.expected_move.to_string());
+                ponder.handle.ponderhit();
+                return join_search(ponder.handle).await;
+            }
+            println!("Ponder miss, expected {}", ponder.expected_move.to_string());
+            ponder.handle.stop();
+            join_search(ponder.handle).await;
+        }
+        join_search(Search::spawn(game.clone(), settings.clone())).await
+    }
+
+    /**
+     * Starts pondering on the reply the search expects after our move.
+     */
+    fn start_ponder(&mut self, game: &Game, context: &GameContext, result: &SearchResult, settings: &SearchSettings) {
+        let expected_move = match result.ponder_move {
+            Some(expected_move) => expected_move,
+            None => return,
+        };
+        let mut ponder_game = game.clone();
+        if !ponder_game.make_move(result.best_move) || !ponder_game.make_move(expected_move) {
+            return;
+        }
+        println!("Pondering on {}", expected_move.to_string());
+        self.pondering = Some(Ponder {
+            expected_move,
+            ply: context.moves.len() + 2,
+            handle: Search::spawn_ponder(ponder_game, settings.clone()),
+        });
+    }
+
+    /**
+     * Stops pondering without waiting for the search, it ends on its own thread.
+     */
+    fn stop_ponder(&mut self) {
+        if let Some(ponder) = self.pondering.take() {
+            ponder.handle.stop();
+        }
+    }
+}
+
+impl Player for BotPlayer {
+    fn name(&self) -> String {
+        "Bot".to_string()
+    }
+
+    fn new_game(&mut self, _context: &GameContext) {
+        self.stop_ponder();
+    }
+
+    fn play<'a>(&'a mut self, game: &'a Game, context: &'a GameContext) -> PlayFuture<'a> {
+        Box::pin(async move {
+            if let Some(book_move) = self.book.as_ref().and_then(|book| book.probe(game, context.moves.len())) {
+                self.stop_ponder();
+                return Ok(Decision::play(book_move, None));
+            }
+
+            let settings = self.clock_settings(context);
+            let result = self.think(game, context, &settings).await;
+            if !result.best_move.is_valid() {
+                return Err("No moves available".to_string());
+            }
+            if self.ponder {
+                self.start_ponder(game, context, &result, &settings);
+            }
+            Ok(Decision::play(result.best_move, Some(result.score)))
+        })
+    }
+
+    fn opponent_moved(&mut self, mov: Move, _context: &GameContext) {
+        // A miss is only reported when we search, but the ponder search can stop right away
+        if let Some(ponder) = &self.pondering {
+            if mov != ponder.expected_move {
+                ponder.handle.stop();
+            }
+        }
+    }
+
+    fn game_over(&mut self, _outcome: &GameOutcome) {
+        self.stop_ponder();
+    }
+}
+
+impl ExternalEnginePlayer {
+    /**
+     * Starts the engine, so a wrong path is reported before the game.
+     */
+    pub fn new(path: &str, limit: GoLimit) -> Result<ExternalEnginePlayer, String> {
+        let engine = UciEngine::start(path)?;
+        println!("Started {}", engine.name);
+        Ok(ExternalEnginePlayer {
+            path: path.to_string(),
+            limit,
+            name: engine.name.clone(),
+            engine: Some(engine),
+        })
+    }
+}
+
+impl Player for ExternalEnginePlayer {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn new_game(&mut self, _context: &GameContext) {
+        if let Some(engine) = self.engine.as_mut() {
+            if let Err(err) = engine.new_game() {
+                println!("{}", err);
+                self.engine = None;
+            }
+        }
+    }
+
+    fn play<'a>(&'a mut self, _game: &'a Game, context: &'a GameContext) -> PlayFuture<'a> {
+        Box::pin(async move {
+            let engine = self.engine.take();
+            let path = self.path.clone();
+            let limit = match (context.white_clock, context.black_clock) {
+                (Some(white), Some(black)) => GoLimit::Clock {
+                    wtime: white.time_left,
+                    btime: black.time_left,
+                    winc: white.increment,
+                    binc: black.increment,
+                    moves_to_go: context.clock().and_then(|clock| clock.moves_to_go),
+                },
+                _ => self.limit,
+            };
+            let fen = context.initial_fen.clone();
+            let moves = context.moves.clone();
+            let (engine, answer) = tokio::task::spawn_blocking(move || go_with_restart(engine, &path, &fen, &moves, limit))
+                .await
+                .map_err(|err| format!("Engine task failed: {}", err))?;
+            self.engine = engine;
+            let answer = answer?;
+            let mov = Move::parse(&answer.best_move).ok_or(format!("{} sent the invalid move {}", self.name, answer.best_move))?;
+            Ok(Decision::play(mov, answer.score))
+        })
+    }
+}
+
+/**
+ * Searches with the engine, restarting it once if it is not running or fails.
+ * Returns the engine if it is still usable.
+ */
+fn go_with_restart(
+    mut engine: Option<UciEngine>,
+    path: &str,
+    fen: &str,
+    moves: &[String],
+    limit: GoLimit,
+) -> (Option<UciEngine>, Result<EngineMove, String>) {
+    let mut error = String::new();
+    for _ in 0..2 {
+        let mut running = match engine.take() {
+            Some(running) => running,
+            None => match UciEngine::start(path) {
+                Ok(started) => started,
+                Err(err) => return (None, Err(err)),
+            },
+        };
+        match running.go(fen, moves, limit) {
+            Ok(answer) => return (Some(running), Ok(answer)),
+            Err(err) => {
+                println!("{}, restarting it", err);
+                error = err;
+            }
+        }
+    }
+    (None, Err(error))
+}
+
+/**
+ * Waits for a search without blocking the other games. The future owns the search,
+ * dropping it (e.g. when the clock ran out) stops the search.
+ */
+pub async fn join_search(handle: SearchHandle) -> SearchResult {
+    let mut search = StopOnDrop(Some(handle));
+    while search.0.as_ref().is_some_and(|handle| !handle.is_finished()) {
+        tokio::time::sleep(SEARCH_POLL_INTERVAL).await;
+    }
+    search.0.take().expect("Search already joined").join()
+}
+
+/**
+ * Stops the search when the future waiting for it is dropped before it finished.
+ */
+struct StopOnDrop(Option<SearchHandle>);
+
+impl Drop for StopOnDrop {
+    fn drop(&mut self) {
+        if let Some(handle) = &self.0 {
+            handle.stop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{tt::TranspositionTable, STARTING_POS_FEN};
+
+    #[tokio::test]
+    async fn dropping_the_move_of_the_bot_stops_its_search() {
+        // The search holds on to the table until its thread ends
+        let tt = Arc::new(TranspositionTable::new(1));
+        let settings = SearchSettings {
+            depth: 64,
+            tt: Some(tt.clone()),
+            ..Default::default()
+        };
+        let mut bot = BotPlayer::new(settings, None);
+        let idle_count = Arc::strong_count(&tt);
+        let game = Game::from_fen(STARTING_POS_FEN);
+        let context = GameContext::new(Color::White, STARTING_POS_FEN);
+
+        let play = bot.play(&game, &context);
+        assert!(tokio::time::timeout(Duration::from_millis(100), play).await.is_err());
+        for _ in 0..100 {
+            if Arc::strong_count(&tt) == idle_count {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("The search still runs after its future was dropped");
+    }
+}
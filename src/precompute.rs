@@ -4,6 +4,16 @@ use crate::base_types::Position;
 pub static DIRECTION_OFFSETS: [i8; 8] = [8, -8, -1, 1, 7, -7, 9, -9];
 pub static NUM_SQUARES_TO_EDGE: [[u8; 8]; 64] = create_num_square_to_edge();
 
+/**
+ * Random keys for zobrist hashing. The first 12 * 64 keys are for the pieces (white pawn..king, black pawn..king),
+ * followed by the side to move key, 4 castling rights keys and 8 en passant file keys.
+ */
+pub static ZOBRIST_KEYS: [u64; ZOBRIST_KEY_COUNT] = create_zobrist_keys();
+pub const ZOBRIST_BLACK_TO_MOVE: usize = 12 * 64;
+pub const ZOBRIST_CASTLING: usize = ZOBRIST_BLACK_TO_MOVE + 1;
+pub const ZOBRIST_EN_PASSANT: usize = ZOBRIST_CASTLING + 4;
+const ZOBRIST_KEY_COUNT: usize = ZOBRIST_EN_PASSANT + 8;
+
 
 pub fn get_direction_index(start_pos : Position, end_pos : Position) -> usize {
 
@@ -77,4 +87,20 @@ const fn create_num_square_to_edge() -> [[u8; 8]; 64] {
         iter += 1;
     }
     num_squares_to_edge
-}
\ No newline at end of file
+}
+
+const fn create_zobrist_keys() -> [u64; ZOBRIST_KEY_COUNT] {
+    let mut keys = [0; ZOBRIST_KEY_COUNT];
+    // splitmix64 with a fixed seed so hashes are the same for every run
+    let mut state: u64 = 0x2545_F491_4F6C_DD1D;
+    let mut iter = 0;
+    while iter < ZOBRIST_KEY_COUNT {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        keys[iter] = z ^ (z >> 31);
+        iter += 1;
+    }
+    keys
+}
@@ -1,11 +1,49 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
 use std::time::Instant;
 
-use crate::{game::Game, moves::Move};
+use crate::{
+    base_types::PieceType,
+    game::Game,
+    moves::{Move, MoveType},
+    skill::{Skill, MAX_SKILL_LEVEL},
+    tablebase::{piece_count, Tablebase, Wdl},
+    tt::{Bound, TranspositionTable, TtEntry},
+};
 
+pub const MATE_SCORE: i32 = 1000000;
+// Scores above this are mate scores. The distance to MATE_SCORE is the number of plies to the mate
+const MATE_BOUND: i32 = MATE_SCORE - 1000;
+// Score of a tablebase win, below the mate scores. Closer wins score higher
+const TB_WIN_SCORE: i32 = MATE_BOUND - 1000;
+// Scores above this are tablebase wins or mates, both depend on the ply they are found on
+const TB_WIN_BOUND: i32 = TB_WIN_SCORE - 1000;
+// Centipawns reported for a tablebase win, more than any evaluation. Closer wins report more
+const TB_WIN_CP: i32 = 20000;
+// Time offset of a search that is pondering. The time limit does not apply until the ponderhit
+const PONDERING: u64 = u64::MAX;
+
+#[derive(Clone)]
 pub struct SearchSettings {
     pub depth: u8,
     pub move_order: bool,
     pub show_log: bool,
+    pub see: bool, // Use static exchange evaluation to prune losing captures and reduce losing quiet moves
+    pub qsearch_checks: bool, // Search quiet moves that give check on the first ply of the quiescence search
+    pub delta_margin: i32, // Captures that can't raise the score to alpha minus this margin are pruned, 0 disables
+    pub threads: usize, // Number of search threads. A single thread is deterministic, more use Lazy SMP
+    pub hash_size: usize, // Transposition table size in MB
+    pub time_limit: Option<u64>, // Stop the search after this many milliseconds
+    pub node_limit: Option<u64>, // Stop the search after this many nodes of all threads
+    pub multi_pv: usize, // Number of best lines to search
+    pub tablebase: Option<Arc<Tablebase>>,
+    pub tt: Option<Arc<TranspositionTable>>, // Table kept from earlier searches of the game, a new one is made if None
+    pub tb_probe_depth: u8, // Tablebases are only probed in the search with at least this depth left
+    pub tb_piece_limit: usize, // Positions with more pieces are not probed
+    pub skill_level: u8, // Playing strength from 0 to 20, below 20 the search gets weakened
 
     /**
      * Advanced settings. Should stay at default unless you know what you are doing.
@@ -15,6 +53,7 @@ pub struct SearchSettings {
     pub capture_multiplier: i32, // Multiplier for captures
     pub castle_reword: i32,      // Reword for castling
     pub promotion_bonus: i32,    // Bonus for promoting a pawn
+    pub positional_weight: i32,  // Percentage of the positional evaluation that is used
 }
 
 impl Default for SearchSettings {
@@ -23,40 +62,270 @@ impl Default for SearchSettings {
             depth: 4,
             move_order: true,
             show_log: false,
+            see: true,
+            qsearch_checks: true,
+            delta_margin: 200,
+            threads: 1,
+            hash_size: 16,
+            time_limit: None,
+            node_limit: None,
+            multi_pv: 1,
+            tablebase: None,
+            tt: None,
+            tb_probe_depth: 1,
+            tb_piece_limit: 7,
+            skill_level: MAX_SKILL_LEVEL,
             move_on_attacked_penalty: 200,
             capture_multiplier: 10,
             castle_reword: 10,
             promotion_bonus: 10,
+            positional_weight: 100,
+        }
+    }
+}
+
+/**
+ * Score from the view of the side to move. Either centipawns or the number of moves to a mate,
+ * negative if the side to move gets mated.
+ */
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Score {
+    Cp(i32),
+    Mate(i32),
+}
+
+impl Score {
+    pub fn from_search(score: i32) -> Score {
+        if score > MATE_BOUND {
+            Score::Mate((MATE_SCORE - score + 1) / 2)
+        } else if score < -MATE_BOUND {
+            Score::Mate(-(MATE_SCORE + score) / 2)
+        } else if score > TB_WIN_BOUND {
+            Score::Cp(TB_WIN_CP - (TB_WIN_SCORE - score))
+        } else if score < -TB_WIN_BOUND {
+            Score::Cp(-TB_WIN_CP + (TB_WIN_SCORE + score))
+        } else {
+            Score::Cp(score)
         }
     }
 }
 
+impl fmt::Display for Score {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Score::Cp(cp) => write!(f, "cp {}", cp),
+            Score::Mate(moves) => write!(f, "mate {}", moves),
+        }
+    }
+}
+
+/**
+ * One of the best lines found at the root.
+ */
+#[derive(Clone, Debug)]
+pub struct PvLine {
+    pub mov: Move,
+    pub score: Score,
+    pub depth: u8,
+    pub pv: Vec<Move>,
+}
+
+/**
+ * Progress of a running search, sent for every line after every completed iteration.
+ */
+#[derive(Clone, Debug)]
+pub struct SearchInfo {
+    pub depth: u8,
+    pub seldepth: u8,
+    pub multi_pv: usize, // Number of the line, starting at 1
+    pub score: Score,
+    pub nodes: u64,
+    pub nps: u64,
+    pub time: u64, // Milliseconds since the search started
+    pub hashfull: u32, // Permille of the transposition table in use
+    pub pv: Vec<Move>,
+}
+
+impl fmt::Display for SearchInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "depth {} seldepth {} multipv {} score {} nodes {} nps {} time {} hashfull {} pv {}",
+            self.depth,
+            self.seldepth,
+            self.multi_pv,
+            self.score,
+            self.nodes,
+            self.nps,
+            self.time,
+            self.hashfull,
+            pv_to_string(&self.pv)
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SearchResult {
+    pub best_move: Move,
+    pub ponder_move: Option<Move>, // The expected reply of the opponent
+    pub score: Score,
+    pub depth: u8,
+    pub nodes: u64,
+    pub pv: Vec<Move>,
+    pub lines: Vec<PvLine>, // All searched lines, the best first. Contains more than one with multi pv
+}
+
+/**
+ * A search running on its own thread. It can be stopped at any time, the result is then
+ * the one of the last completed iteration.
+ */
+pub struct SearchHandle {
+    stop: Arc<AtomicBool>,
+    time_offset: Arc<AtomicU64>,
+    start_time: Instant,
+    info: Receiver<SearchInfo>,
+    thread: JoinHandle<SearchResult>,
+}
+
+impl SearchHandle {
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+
+    /**
+     * The opponent played the expected move. The ponder search continues as a normal search,
+     * its time limit starts now.
+     */
+    pub fn ponderhit(&self) {
+        let elapsed = self.start_time.elapsed().as_millis() as u64;
+        self.time_offset.store(elapsed, Ordering::Relaxed);
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    /**
+     * Returns the next progress info if there is one, without blocking.
+     */
+    pub fn try_info(&self) -> Option<SearchInfo> {
+        self.info.try_recv().ok()
+    }
+
+    /**
+     * Waits for the search to finish.
+     */
+    pub fn join(self) -> SearchResult {
+        self.thread.join().expect("Search thread panicked")
+    }
+}
+
 pub struct Search<'a> {
     pub best_move: Move,
     pub moves_searched: u64,
     pub moves_skipped: u64,
     pub settings: SearchSettings,
     game: &'a mut Game,
+    tt: Arc<TranspositionTable>,
+    stop: Arc<AtomicBool>,
+    time_offset: Arc<AtomicU64>, // Milliseconds after the start from which the time limit counts
+    nodes: Arc<AtomicU64>, // Moves searched by all threads
+    info_sender: Option<Sender<SearchInfo>>,
+    start_time: Instant,
+    seldepth: u8,
+    completed_depth: u8,
+    lines: Vec<PvLine>, // Lines of the last completed iteration
+    root_excluded: Vec<Move>, // Root moves that are already part of a better line
+    root_moves: Vec<Move>, // Tablebase optimal root moves, only these are searched. Empty to search all
+    pv_table: Vec<Vec<Move>>, // Principal variation of every ply of the current line
 }
 
 impl<'a> Search<'a> {
-    pub fn new(game: &'a mut Game) -> Search {
+    pub fn new(game: &'a mut Game) -> Search<'a> {
         Search {
             best_move: Move::invalid(),
             moves_searched: 0,
             moves_skipped: 0,
             settings: Default::default(),
             game,
+            tt: Arc::new(TranspositionTable::empty()),
+            stop: Arc::new(AtomicBool::new(false)),
+            time_offset: Arc::new(AtomicU64::new(0)),
+            nodes: Arc::new(AtomicU64::new(0)),
+            info_sender: None,
+            start_time: Instant::now(),
+            seldepth: 0,
+            completed_depth: 0,
+            lines: Vec::new(),
+            root_excluded: Vec::new(),
+            root_moves: Vec::new(),
+            pv_table: Vec::new(),
+        }
+    }
+
+    /**
+     * Starts a search on a copy of the game on a background thread. Progress info is sent
+     * after every completed iteration.
+     */
+    pub fn spawn(game: Game, settings: SearchSettings) -> SearchHandle {
+        Search::spawn_with_offset(game, settings, 0)
+    }
+
+    /**
+     * Starts a search of the position after the expected reply of the opponent. It ignores the time limit
+     * until `ponderhit` is called on the handle. On a different reply it should be stopped and discarded.
+     */
+    pub fn spawn_ponder(game: Game, settings: SearchSettings) -> SearchHandle {
+        Search::spawn_with_offset(game, settings, PONDERING)
+    }
+
+    fn spawn_with_offset(mut game: Game, settings: SearchSettings, time_offset: u64) -> SearchHandle {
+        let stop = Arc::new(AtomicBool::new(false));
+        let time_offset = Arc::new(AtomicU64::new(time_offset));
+        let (sender, info) = mpsc::channel();
+        let thread_stop = stop.clone();
+        let thread_time_offset = time_offset.clone();
+        let thread = std::thread::spawn(move || {
+            let mut search = Search::new(&mut game);
+            search.settings = settings;
+            search.stop = thread_stop;
+            search.time_offset = thread_time_offset;
+            search.info_sender = Some(sender);
+            search.run()
+        });
+        SearchHandle {
+            stop,
+            time_offset,
+            start_time: Instant::now(),
+            info,
+            thread,
         }
     }
 
-    pub fn start(&mut self) -> Move {
+    pub fn run(&mut self) -> SearchResult {
+        let skill = Skill::new(self.settings.skill_level);
+        let full_settings = self.settings.clone();
+        self.settings = skill.limit(&self.settings);
+
         if self.settings.show_log {
             println!("---------------------------------");
             println!("Starting best move search!");
             println!("Team = {}", self.game.turn.to_string());
             println!("Depth = {}", self.settings.depth);
+            println!("Threads = {}", self.settings.threads);
+            println!("Multi PV = {}", self.settings.multi_pv);
             println!("Move order enabled = {}", self.settings.move_order);
+            println!("SEE enabled = {}", self.settings.see);
+            if !skill.is_full_strength() {
+                println!("Skill level = {} (about {} Elo)", skill.level, skill.elo());
+            }
+            if let Some(tablebase) = &self.settings.tablebase {
+                println!(
+                    "Tablebase = up to {} pieces, probe depth {}",
+                    self.settings.tb_piece_limit.min(tablebase.max_pieces),
+                    self.settings.tb_probe_depth
+                );
+            }
             if self.settings.move_order {
                 println!("Move order settings:");
                 println!(
@@ -70,42 +339,335 @@ impl<'a> Search<'a> {
             println!("Running Search...");
         }
 
-        let start = std::time::Instant::now();
-        self.search(0, self.settings.depth, -1000000, 1000000);
+        if let Some(tt) = &self.settings.tt {
+            self.tt = tt.clone();
+        } else if self.tt.is_empty() {
+            self.tt = Arc::new(TranspositionTable::new(self.settings.hash_size));
+        }
+
+        self.start_time = Instant::now();
+        self.nodes.store(0, Ordering::Relaxed);
+        self.root_moves = self.tablebase_root_moves();
+        self.run_threads();
+        let total_nodes = self.nodes.load(Ordering::Relaxed);
 
         if self.settings.show_log {
+            let elapsed = self.start_time.elapsed();
             println!(
-                "Searched {} moves in {}ms. Skipped {}",
-                self.moves_searched,
-                start.elapsed().as_millis(),
+                "Searched {} moves in {}ms ({} nps, {} threads). Skipped {}",
+                total_nodes,
+                elapsed.as_millis(),
+                (total_nodes as f64 / elapsed.as_secs_f64().max(0.001)) as u64,
+                self.settings.threads.max(1),
                 self.moves_skipped
             );
             println!("---------------------------------");
         }
 
-        self.best_move
+        let (score, pv) = match self.lines.first() {
+            Some(line) if line.mov == self.best_move => (line.score, line.pv.clone()),
+            _ => (Score::Cp(0), vec![self.best_move]),
+        };
+        let mut result = SearchResult {
+            best_move: self.best_move,
+            ponder_move: pv.get(1).copied(),
+            score,
+            depth: self.completed_depth,
+            nodes: total_nodes,
+            pv,
+            lines: self.lines.clone(),
+        };
+        self.settings = full_settings;
+        if !skill.is_full_strength() {
+            self.weaken_result(&skill, &mut result);
+        }
+        result
+    }
+
+    /**
+     * Replaces the best move with the one the skill level picks. The lines stay as searched.
+     */
+    fn weaken_result(&mut self, skill: &Skill, result: &mut SearchResult) {
+        let legal_moves = self.game.get_possible_team_moves(self.game.turn);
+        match skill.pick(&result.lines, &legal_moves, self.game.hash) {
+            Ok(i) => {
+                if let Some(line) = result.lines.get(i) {
+                    result.best_move = line.mov;
+                    result.score = line.score;
+                    result.pv = line.pv.clone();
+                }
+            }
+            Err(mov) => {
+                if let Some(line) = result.lines.iter().find(|line| line.mov == mov) {
+                    result.score = line.score;
+                    result.pv = line.pv.clone();
+                } else {
+                    result.pv = vec![mov];
+                }
+                result.best_move = mov;
+            }
+        }
+        result.ponder_move = result.pv.get(1).copied();
+        self.best_move = result.best_move;
+    }
+
+    /**
+     * Lazy SMP. The helper threads search the same position on their own copy of the game and only
+     * communicate through the shared transposition table. The result of this thread is the one that is used,
+     * the helpers get stopped as soon as it is done.
+     */
+    fn run_threads(&mut self) {
+        let helper_stop = Arc::new(AtomicBool::new(false));
+        // Helpers only fill the transposition table, so they don't need the other lines.
+        // They get stopped by this thread, so they don't need a time limit either
+        let helper_settings = SearchSettings {
+            multi_pv: 1,
+            time_limit: None,
+            ..self.settings.clone()
+        };
+
+        std::thread::scope(|scope| {
+            let helpers = (1..self.settings.threads.max(1))
+                .map(|thread_id| {
+                    let mut game = self.game.clone();
+                    let settings = helper_settings.clone();
+                    let tt = self.tt.clone();
+                    let stop = helper_stop.clone();
+                    let nodes = self.nodes.clone();
+                    let start_time = self.start_time;
+                    let root_moves = self.root_moves.clone();
+                    scope.spawn(move || {
+                        let mut helper = Search::new(&mut game);
+                        helper.settings = settings;
+                        helper.root_moves = root_moves;
+                        helper.tt = tt;
+                        helper.stop = stop;
+                        helper.nodes = nodes;
+                        helper.start_time = start_time;
+                        helper.iterative_deepening(thread_id);
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            self.iterative_deepening(0);
+
+            helper_stop.store(true, Ordering::Relaxed);
+            for helper in helpers {
+                let _ = helper.join();
+            }
+        });
+    }
+
+    fn iterative_deepening(&mut self, thread_id: usize) {
+        let mut completed_best_move = Move::invalid();
+        self.completed_depth = 0;
+        self.seldepth = 0;
+        self.lines.clear();
+
+        let root_move_count = if self.root_moves.is_empty() {
+            self.game.get_possible_team_moves(self.game.turn).len()
+        } else {
+            self.root_moves.len()
+        };
+        let line_count = self.settings.multi_pv.clamp(1, root_move_count.max(1));
+
+        'deepening: for depth in 1..=self.settings.depth {
+            // Every other helper skips the odd depths, so the threads are spread over different depths
+            if thread_id % 2 == 1 && depth % 2 == 1 && depth < self.settings.depth {
+                continue;
+            }
+
+            // Every line is searched with the root moves of the better lines excluded
+            let mut lines = Vec::with_capacity(line_count);
+            self.root_excluded.clear();
+            while lines.len() < line_count {
+                let score = self.search(0, depth, -MATE_SCORE, MATE_SCORE);
+                if self.stopped() {
+                    break 'deepening;
+                }
+                let pv = match self.pv_table.first() {
+                    Some(pv) if pv.first() == Some(&self.best_move) => pv.clone(),
+                    _ => vec![self.best_move],
+                };
+                lines.push(PvLine {
+                    mov: self.best_move,
+                    score: Score::from_search(score),
+                    depth,
+                    pv,
+                });
+                self.root_excluded.push(self.best_move);
+            }
+            self.root_excluded.clear();
+
+            completed_best_move = lines[0].mov;
+            self.completed_depth = depth;
+            self.lines = lines;
+            self.send_info();
+
+            // The next iteration takes longer than all before, it would most likely not finish in time
+            if self.time_used() > self.settings.time_limit.map_or(u64::MAX, |limit| limit / 2) {
+                break;
+            }
+        }
+        self.root_excluded.clear();
+        if completed_best_move.is_valid() {
+            self.best_move = completed_best_move;
+        } else {
+            // Stopped before depth 1 was done. Any legal move is better than none
+            let moves = if self.root_moves.is_empty() {
+                self.game.get_possible_team_moves(self.game.turn)
+            } else {
+                self.root_moves.clone()
+            };
+            if let Some(mov) = moves.first() {
+                self.best_move = *mov;
+            }
+        }
+    }
+
+    /**
+     * The root moves that keep the tablebase result, if the position is in the tablebase.
+     */
+    fn tablebase_root_moves(&mut self) -> Vec<Move> {
+        let tablebase = match &self.settings.tablebase {
+            Some(tablebase) if piece_count(self.game) <= self.settings.tb_piece_limit.min(tablebase.max_pieces) => tablebase,
+            _ => return Vec::new(),
+        };
+        match tablebase.best_root_moves(self.game) {
+            Some(moves) => {
+                if self.settings.show_log {
+                    println!("Tablebase moves = {}", pv_to_string(&moves));
+                }
+                moves
+            }
+            None => Vec::new(),
+        }
+    }
+
+    fn send_info(&self) {
+        if let Some(sender) = &self.info_sender {
+            let time = self.start_time.elapsed();
+            let nodes = self.nodes.load(Ordering::Relaxed);
+            for (i, line) in self.lines.iter().enumerate() {
+                let _ = sender.send(SearchInfo {
+                    depth: self.completed_depth,
+                    seldepth: self.seldepth,
+                    multi_pv: i + 1,
+                    score: line.score,
+                    nodes,
+                    nps: (nodes as f64 / time.as_secs_f64().max(0.001)) as u64,
+                    time: time.as_millis() as u64,
+                    hashfull: self.tt.hashfull(),
+                    pv: line.pv.clone(),
+                });
+            }
+        }
+    }
+
+    fn stopped(&self) -> bool {
+        if self.stop.load(Ordering::Relaxed) {
+            return true;
+        }
+        if let Some(limit) = self.settings.node_limit {
+            if self.nodes.load(Ordering::Relaxed) >= limit {
+                return true;
+            }
+        }
+        match self.settings.time_limit {
+            Some(limit) => self.time_used() >= limit,
+            None => false,
+        }
+    }
+
+    /**
+     * Milliseconds used of the time limit. Always 0 while pondering.
+     */
+    fn time_used(&self) -> u64 {
+        let time_offset = self.time_offset.load(Ordering::Relaxed);
+        if time_offset == PONDERING {
+            return 0;
+        }
+        (self.start_time.elapsed().as_millis() as u64).saturating_sub(time_offset)
+    }
+
+    fn count_node(&mut self) {
+        self.moves_searched += 1;
+        self.nodes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /**
+     * Resets the principal variation of the ply and updates the selective depth.
+     */
+    fn enter_ply(&mut self, count_from_root: u8) {
+        let ply = count_from_root as usize;
+        if self.pv_table.len() <= ply + 1 {
+            self.pv_table.resize(ply + 2, Vec::new());
+        }
+        self.pv_table[ply].clear();
+        self.seldepth = self.seldepth.max(count_from_root);
+    }
+
+    fn update_pv(&mut self, count_from_root: u8, m: Move) {
+        let ply = count_from_root as usize;
+        let mut line = vec![m];
+        line.extend_from_slice(&self.pv_table[ply + 1]);
+        self.pv_table[ply] = line;
     }
 
     fn search(&mut self, count_from_root: u8, depth: u8, alpha: i32, beta: i32) -> i32 {
+        if self.stopped() {
+            return 0;
+        }
+        self.enter_ply(count_from_root);
         if depth == 0 {
-            return self.search_captures(alpha, beta);
+            return self.search_captures(count_from_root, 0, alpha, beta);
         }
 
         let mut alpha = alpha;
+        let hash = self.game.hash;
+
+        let mut tt_move = None;
+        if let Some(entry) = self.tt.probe(hash) {
+            tt_move = entry.best_move;
+            // The root always gets searched, so we get a best move
+            if count_from_root > 0 && entry.depth >= depth {
+                let score = score_from_tt(entry.score, count_from_root);
+                match entry.bound {
+                    Bound::Exact => return score,
+                    Bound::Lower if score >= beta => return beta,
+                    Bound::Upper if score <= alpha => return alpha,
+                    _ => {}
+                }
+            }
+        }
+
+        if count_from_root > 0 {
+            if let Some(score) = self.probe_tablebase(count_from_root, depth, alpha, beta) {
+                return score;
+            }
+        }
 
         let mut moves = self.game.get_possible_team_moves(self.game.turn);
 
         // If no moves, checkmate or stalemate
         if moves.len() == 0 {
             if self.game.king_check > 0 {
-                return -1000000 + count_from_root as i32;
+                return -MATE_SCORE + count_from_root as i32;
             } else {
                 return 0;
             }
         }
 
-        if count_from_root == 0 && moves.len() > 0 {
-            self.best_move = moves[0];
+        if count_from_root == 0 {
+            let excluded = &self.root_excluded;
+            let allowed = &self.root_moves;
+            moves.retain(|m| {
+                !excluded.iter().any(|e| is_same_move(*e, *m))
+                    && (allowed.is_empty() || allowed.iter().any(|a| is_same_move(*a, *m)))
+            });
+            if let Some(first) = moves.first() {
+                self.best_move = *first;
+            }
         }
 
         // Oder moves
@@ -113,48 +675,183 @@ impl<'a> Search<'a> {
             moves = self.oder_moves(moves);
         }
 
-        for m in moves {
+        // The best move from the transposition table gets searched first
+        if let Some(tt_move) = tt_move {
+            if let Some(index) = moves.iter().position(|m| is_same_move(*m, tt_move)) {
+                let m = moves.remove(index);
+                moves.insert(0, m);
+            }
+        }
+
+        let mut best_move = None;
+
+        let in_check = self.game.king_check != 0;
+        for (i, m) in moves.into_iter().enumerate() {
+            // Quiet moves that lose material are searched one ply shallower. They get a full depth
+            // re-search if they turn out to raise alpha anyway.
+            let reduce = self.settings.see
+                && depth >= 3
+                && i > 0
+                && !in_check
+                && !m.move_type.is_capture()
+                && !m.move_type.is_promotion()
+                && self.game.see(m) < 0;
+
             self.game.make_move(m);
-            let score = -self.search(count_from_root + 1, depth - 1, -beta, -alpha);
+            let mut score = if reduce {
+                -self.search(count_from_root + 1, depth - 2, -beta, -alpha)
+            } else {
+                alpha + 1
+            };
+            if score > alpha {
+                score = -self.search(count_from_root + 1, depth - 1, -beta, -alpha);
+            }
             self.game.unmake_move();
 
-            self.moves_searched += 1;
+            // The score of an interrupted search is meaningless
+            if self.stopped() {
+                return 0;
+            }
+
+            self.count_node();
 
             if score >= beta {
                 self.moves_skipped += 1;
+                self.store_tt(hash, depth, beta, Bound::Lower, Some(m), count_from_root);
                 return beta;
             }
             if score > alpha {
                 alpha = score;
+                best_move = Some(m);
+                self.update_pv(count_from_root, m);
                 if count_from_root == 0 {
                     self.best_move = m;
                 }
             }
         }
 
+        let bound = if best_move.is_some() { Bound::Exact } else { Bound::Upper };
+        self.store_tt(hash, depth, alpha, bound, best_move, count_from_root);
+
         return alpha;
     }
 
-    fn search_captures(&mut self, alpha: i32, beta: i32) -> i32 {
-        let mut alpha = alpha;
-        let eval = self.game.evaluate();
-        self.moves_searched += 1;
-        if eval >= beta {
-            return beta;
+    /**
+     * Tablebase win, draw or loss of the position. Returns the score if it cuts the search off.
+     * Cursed wins and blessed losses are draws by the 50 move rule.
+     */
+    fn probe_tablebase(&mut self, count_from_root: u8, depth: u8, alpha: i32, beta: i32) -> Option<i32> {
+        let tablebase = self.settings.tablebase.as_ref()?;
+        if depth < self.settings.tb_probe_depth
+            || piece_count(self.game) > self.settings.tb_piece_limit.min(tablebase.max_pieces)
+        {
+            return None;
         }
-        if eval > alpha {
-            alpha = eval;
+        let (score, bound) = match tablebase.probe_wdl(self.game)? {
+            Wdl::Win => (TB_WIN_SCORE - count_from_root as i32, Bound::Lower),
+            Wdl::Loss => (-TB_WIN_SCORE + count_from_root as i32, Bound::Upper),
+            _ => (0, Bound::Exact),
+        };
+        let hash = self.game.hash;
+        match bound {
+            Bound::Exact => {
+                self.store_tt(hash, depth, score, bound, None, count_from_root);
+                Some(score)
+            }
+            Bound::Lower if score >= beta => {
+                self.store_tt(hash, depth, score, bound, None, count_from_root);
+                Some(beta)
+            }
+            Bound::Upper if score <= alpha => {
+                self.store_tt(hash, depth, score, bound, None, count_from_root);
+                Some(alpha)
+            }
+            _ => None,
         }
+    }
 
-        let possible_moves = self.game.get_possible_team_moves(self.game.turn);
-        let capture_moves = possible_moves
-            .iter()
-            .filter(|m| m.move_type.is_capture())
-            .collect::<Vec<&Move>>();
+    fn store_tt(&self, hash: u64, depth: u8, score: i32, bound: Bound, best_move: Option<Move>, count_from_root: u8) {
+        // The result of a root search with excluded moves is not the result of the position
+        if count_from_root == 0 && !self.root_excluded.is_empty() {
+            return;
+        }
+        self.tt.store(
+            hash,
+            TtEntry {
+                depth,
+                score: score_to_tt(score, count_from_root),
+                bound,
+                best_move,
+            },
+        );
+    }
+
+    /**
+     * Quiescence search: only captures and promotions until the position is quiet. In check all evasions
+     * are searched, and on the first ply also quiet moves that give check. `q_ply` counts the plies since
+     * the end of the main search.
+     */
+    fn search_captures(&mut self, count_from_root: u8, q_ply: u8, alpha: i32, beta: i32) -> i32 {
+        if self.stopped() {
+            return 0;
+        }
+        self.enter_ply(count_from_root);
+        self.count_node();
+        let mut alpha = alpha;
+        let in_check = self.game.king_check != 0;
+
+        let mut eval = 0;
+        let moves = if in_check {
+            // No standing pat in check, every evasion has to be searched
+            let evasions = self.game.get_possible_team_moves(self.game.turn);
+            if evasions.is_empty() {
+                return -MATE_SCORE + count_from_root as i32;
+            }
+            evasions
+        } else {
+            eval = self.game.evaluate_with_knowledge(self.settings.positional_weight);
+            if eval >= beta {
+                return beta;
+            }
+            if eval > alpha {
+                alpha = eval;
+            }
+            if q_ply == 0 && self.settings.qsearch_checks {
+                // Only the quiet moves that can give check are made, the rest is dropped here
+                let mut moves = self.game.get_possible_team_moves(self.game.turn);
+                moves.retain(|m| m.move_type.is_capture() || m.move_type.is_promotion() || self.game.may_give_check(*m));
+                moves
+            } else {
+                self.game.get_possible_team_captures(self.game.turn)
+            }
+        };
+        let moves = if self.settings.move_order { self.oder_moves(moves) } else { moves };
 
-        for m in capture_moves {
-            self.game.make_move(*m);
-            let score = -self.search_captures(-beta, -alpha);
+        for m in moves {
+            let is_tactical = m.move_type.is_capture() || m.move_type.is_promotion();
+            if !in_check && is_tactical {
+                // Delta pruning: even winning the piece would not get the score close to alpha
+                let gain = self.capture_value(m)
+                    + if m.move_type.is_promotion() {
+                        m.move_type.get_promotion_piece().get_value() - PieceType::Pawn.get_value()
+                    } else {
+                        0
+                    };
+                if self.settings.delta_margin > 0 && eval + gain + self.settings.delta_margin < alpha {
+                    continue;
+                }
+                if self.settings.see && self.game.see(m) < 0 {
+                    continue;
+                }
+            }
+
+            self.game.make_move(m);
+            // Quiet moves are only searched if they give check, or as evasions. Castling is only a candidate
+            if !in_check && !is_tactical && self.game.king_check == 0 {
+                self.game.unmake_move();
+                continue;
+            }
+            let score = -self.search_captures(count_from_root + 1, q_ply + 1, -beta, -alpha);
             self.game.unmake_move();
 
             if score >= beta {
@@ -162,19 +859,36 @@ impl<'a> Search<'a> {
             }
             if score > alpha {
                 alpha = score;
+                self.update_pv(count_from_root, m);
             }
         }
 
         return alpha;
     }
 
+    /**
+     * Value of the piece a move captures, 0 for quiet moves.
+     */
+    fn capture_value(&self, m: Move) -> i32 {
+        match m.move_type {
+            MoveType::EnPassantCapture => PieceType::Pawn.get_value(),
+            _ => self.game.board.get_piece(m.to).map_or(0, |piece| piece.piece_type.get_value()),
+        }
+    }
+
     fn move_order_score(&self, m: Move) -> i32 {
         let mut score = 0;
         let moving_piece = self.game.board.get_piece(m.from).unwrap();
 
         if let Some(capture_piece) = self.game.board.get_piece(m.to) {
-            score += self.settings.capture_multiplier * capture_piece.piece_type.get_value()
-                - moving_piece.piece_type.get_value();
+            let see = if self.settings.see { self.game.see(m) } else { 0 };
+            // Losing captures are ordered behind the quiet moves
+            score += if see < 0 {
+                see
+            } else {
+                self.settings.capture_multiplier * capture_piece.piece_type.get_value()
+                    - moving_piece.piece_type.get_value()
+            };
         }
         if m.move_type.is_promotion() {
             score += self.settings.promotion_bonus + m.move_type.get_promotion_piece().get_value()
@@ -199,3 +913,118 @@ impl<'a> Search<'a> {
         res.iter().map(|m| *m.0).collect()
     }
 }
+
+pub fn pv_to_string(pv: &[Move]) -> String {
+    pv.iter().map(|m| m.to_string()).collect::<Vec<String>>().join(" ")
+}
+
+/**
+ * Moves are equal if they go between the same squares, this also compares the promotion piece.
+ */
+pub fn is_same_move(a: Move, b: Move) -> bool {
+    a == b && a.move_type.get_promotion_piece() == b.move_type.get_promotion_piece()
+}
+
+/**
+ * Mate and tablebase scores are stored relative to the position in the transposition table instead of relative
+ * to the root, so they stay correct when the position is reached on a different ply.
+ */
+fn score_to_tt(score: i32, count_from_root: u8) -> i32 {
+    if score > TB_WIN_BOUND {
+        score + count_from_root as i32
+    } else if score < -TB_WIN_BOUND {
+        score - count_from_root as i32
+    } else {
+        score
+    }
+}
+
+fn score_from_tt(score: i32, count_from_root: u8) -> i32 {
+    if score > TB_WIN_BOUND {
+        score - count_from_root as i32
+    } else if score < -TB_WIN_BOUND {
+        score + count_from_root as i32
+    } else {
+        score
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mate_and_tablebase_scores_are_stored_relative_to_the_position() {
+        for score in [MATE_SCORE - 7, TB_WIN_SCORE - 7, -MATE_SCORE + 7, -TB_WIN_SCORE + 7] {
+            let stored = score_to_tt(score, 3);
+            // The same position found two plies deeper is two plies further from the root
+            let deeper = score_from_tt(stored, 5);
+            assert_eq!(deeper, score - 2 * score.signum(), "{}", score);
+        }
+        assert_eq!(score_to_tt(350, 3), 350);
+        assert_eq!(score_from_tt(-350, 5), -350);
+    }
+
+    #[test]
+    fn reports_tablebase_wins_in_centipawns() {
+        assert_eq!(Score::from_search(TB_WIN_SCORE - 5), Score::Cp(TB_WIN_CP - 5));
+        assert_eq!(Score::from_search(-TB_WIN_SCORE + 5), Score::Cp(-TB_WIN_CP + 5));
+        assert_eq!(Score::from_search(MATE_SCORE - 3), Score::Mate(2));
+        assert_eq!(Score::from_search(-MATE_SCORE + 4), Score::Mate(-2));
+    }
+
+    fn quiescence(fen: &str, settings: SearchSettings, q_ply: u8, alpha: i32) -> (i32, u64) {
+        let mut game = Game::from_fen(fen);
+        let mut search = Search::new(&mut game);
+        search.settings = settings;
+        let score = search.search_captures(0, q_ply, alpha, MATE_SCORE);
+        (score, search.moves_searched)
+    }
+
+    #[test]
+    fn quiescence_searches_every_evasion_in_check() {
+        let settings = SearchSettings::default();
+        // Fool's mate, no evasion left
+        let mated = "rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3";
+        assert_eq!(quiescence(mated, settings.clone(), 3, -MATE_SCORE).0, -MATE_SCORE);
+        // Only quiet king moves escape the check, standing pat would not count them
+        let (score, nodes) = quiescence("4k3/8/8/8/8/8/8/r3K3 w - - 0 1", settings, 3, -MATE_SCORE);
+        assert!(score > -MATE_BOUND && score < 0);
+        assert!(nodes > 1);
+    }
+
+    #[test]
+    fn quiescence_finds_quiet_checks_on_the_first_ply_only() {
+        let back_rank = "6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1";
+        assert_eq!(quiescence(back_rank, SearchSettings::default(), 0, -MATE_SCORE).0, MATE_SCORE - 1);
+        assert!(quiescence(back_rank, SearchSettings::default(), 1, -MATE_SCORE).0 < MATE_BOUND);
+        let settings = SearchSettings {
+            qsearch_checks: false,
+            ..Default::default()
+        };
+        assert!(quiescence(back_rank, settings, 0, -MATE_SCORE).0 < MATE_BOUND);
+    }
+
+    #[test]
+    fn quiescence_prunes_hopeless_and_losing_captures() {
+        let settings = SearchSettings {
+            qsearch_checks: false,
+            delta_margin: 0,
+            see: false,
+            ..Default::default()
+        };
+
+        // Winning the free pawn can't get within the margin of alpha
+        let free_pawn = "4k2q/8/8/3p4/8/8/8/3RK3 w - - 0 1";
+        let alpha = Game::from_fen(free_pawn).evaluate() + 400;
+        assert_eq!(quiescence(free_pawn, settings.clone(), 0, alpha), (alpha, 2));
+        let delta = SearchSettings { delta_margin: 200, ..settings.clone() };
+        assert_eq!(quiescence(free_pawn, delta, 0, alpha), (alpha, 1));
+
+        // The queen takes a defended pawn
+        let defended_pawn = "4k3/2p5/3p4/8/8/8/8/3QK3 w - - 0 1";
+        assert!(quiescence(defended_pawn, settings.clone(), 0, -MATE_SCORE).1 > 1);
+        let see = SearchSettings { see: true, ..settings };
+        assert_eq!(quiescence(defended_pawn, see, 0, -MATE_SCORE).1, 1);
+    }
+}
@@ -0,0 +1,122 @@
+use crate::{
+    book::random,
+    moves::Move,
+    search::{PvLine, Score, SearchSettings},
+};
+
+pub const MAX_SKILL_LEVEL: u8 = 20;
+
+// Lines the weakened search chooses from
+const SKILL_MULTI_PV: usize = 4;
+// Chance of a random move at level 0, it falls to 0 at full strength
+const MAX_BLUNDER_CHANCE: f64 = 0.2;
+// Temperature in centipawns per level below full strength
+const TEMPERATURE_PER_LEVEL: f64 = 12.0;
+
+// Elo of the levels, measured with the match runner between neighbouring levels at 50ms per move, 40 games each
+// (`match a.skill=<level> b.skill=<level + 4> games=40 time=50`). The differences were 0-4: -470 +/- 210,
+// 4-8: -382 +/- 306, 8-12: -147 +/- 125, 12-16: -147 +/- 121, 16-20: -382 +/- 243, chained from full strength
+// set to 2000. Against full strength directly (30 games) level 16 scored -301 +/- 198 and level 12 -459 +/- 453,
+// every lower level lost all but one or two games. The error bars are large, the values are only a rough scale
+const LEVEL_ELO: [(u8, u32); 6] = [(0, 470), (4, 940), (8, 1320), (12, 1470), (16, 1620), (20, 2000)];
+
+/**
+ * Reduced playing strength for weaker players, from level 0 (a beginner) to 20 (full strength).
+ * The search is limited in nodes and depth, knows less about the position, and instead of the best
+ * move it picks one of the near-best lines, sometimes even a random move.
+ */
+#[derive(Copy, Clone, Debug)]
+pub struct Skill {
+    pub level: u8,
+}
+
+impl Skill {
+    pub fn new(level: u8) -> Skill {
+        Skill {
+            level: level.min(MAX_SKILL_LEVEL),
+        }
+    }
+
+    /**
+     * The level that plays closest to the Elo.
+     */
+    pub fn from_elo(elo: u32) -> Skill {
+        let level = (0..=MAX_SKILL_LEVEL).min_by_key(|level| Skill::new(*level).elo().abs_diff(elo)).unwrap_or(MAX_SKILL_LEVEL);
+        Skill::new(level)
+    }
+
+    pub fn elo(&self) -> u32 {
+        let upper = LEVEL_ELO.iter().position(|(level, _)| *level >= self.level).unwrap_or(LEVEL_ELO.len() - 1);
+        if upper == 0 {
+            return LEVEL_ELO[0].1;
+        }
+        let (low_level, low_elo) = LEVEL_ELO[upper - 1];
+        let (high_level, high_elo) = LEVEL_ELO[upper];
+        low_elo + (high_elo - low_elo) * (self.level - low_level) as u32 / (high_level - low_level) as u32
+    }
+
+    pub fn is_full_strength(&self) -> bool {
+        self.level >= MAX_SKILL_LEVEL
+    }
+
+    /**
+     * The settings limited to the level: fewer nodes, lower depth, less positional knowledge
+     * and more lines to choose from.
+     */
+    pub fn limit(&self, settings: &SearchSettings) -> SearchSettings {
+        if self.is_full_strength() {
+            return settings.clone();
+        }
+        let node_limit = (200.0 * 1.6f64.powi(self.level as i32)) as u64;
+        SearchSettings {
+            depth: settings.depth.min(1 + self.level / 2),
+            node_limit: Some(settings.node_limit.map_or(node_limit, |limit| limit.min(node_limit))),
+            multi_pv: settings.multi_pv.max(SKILL_MULTI_PV),
+            positional_weight: settings.positional_weight.min(self.level as i32 * 100 / MAX_SKILL_LEVEL as i32),
+            ..settings.clone()
+        }
+    }
+
+    fn blunder_chance(&self) -> f64 {
+        let weakness = (MAX_SKILL_LEVEL - self.level) as f64 / MAX_SKILL_LEVEL as f64;
+        MAX_BLUNDER_CHANCE * weakness * weakness
+    }
+
+    /**
+     * Picks the move to play from the lines of the search. Lines are chosen with a softmax of their
+     * scores, the lower the level the more likely a worse line. With the blunder chance it is any legal move.
+     * Returns the index of the line, or the random move.
+     */
+    pub fn pick(&self, lines: &[PvLine], legal_moves: &[Move], seed: u64) -> Result<usize, Move> {
+        let chance = || random(seed) as f64 / u32::MAX as f64;
+        if !legal_moves.is_empty() && chance() < self.blunder_chance() {
+            return Err(legal_moves[random(seed ^ 1) as usize % legal_moves.len()]);
+        }
+        if lines.is_empty() {
+            return Ok(0);
+        }
+
+        let temperature = (MAX_SKILL_LEVEL - self.level) as f64 * TEMPERATURE_PER_LEVEL;
+        let best = centipawns(lines[0].score);
+        let weights: Vec<f64> = lines.iter().map(|line| ((centipawns(line.score) - best) / temperature.max(1.0)).exp()).collect();
+        let mut pick = chance() * weights.iter().sum::<f64>();
+        for (i, weight) in weights.iter().enumerate() {
+            if pick < *weight {
+                return Ok(i);
+            }
+            pick -= weight;
+        }
+        Ok(0)
+    }
+}
+
+/**
+ * Mates count as very large scores, shorter mates higher.
+ */
+fn centipawns(score: Score) -> f64 {
+    match score {
+        Score::Cp(cp) => cp as f64,
+        Score::Mate(moves) if moves > 0 => 100000.0 - moves as f64,
+        Score::Mate(moves) => -100000.0 - moves as f64,
+    }
+}
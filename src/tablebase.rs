@@ -0,0 +1,1233 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::Neg;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use crate::{
+    base_types::{Color, PieceType},
+    endgame::Material,
+    game::Game,
+    moves::Move,
+};
+
+const WDL_MAGIC: [u8; 4] = [0x71, 0xE8, 0x23, 0x5D];
+const DTZ_MAGIC: [u8; 4] = [0xD7, 0x66, 0x0C, 0xA5];
+const MAX_PIECES: usize = 7;
+// Size of the first read of a table file. Bigger headers are read again with more
+const HEADER_READ_SIZE: u64 = 1 << 16;
+
+// Flags of the table file
+const FILE_SPLIT: u8 = 1;
+const FILE_HAS_PAWNS: u8 = 2;
+
+// Flags of a single table
+const FLAG_STM: u8 = 1;
+const FLAG_MAPPED: u8 = 2;
+const FLAG_WIN_PLIES: u8 = 4;
+const FLAG_LOSS_PLIES: u8 = 8;
+const FLAG_WIDE: u8 = 16;
+const FLAG_SINGLE_VALUE: u8 = 128;
+
+/**
+ * Win, draw or loss from the view of the side to move. Cursed wins and blessed losses are
+ * wins and losses that are drawn by the 50 move rule.
+ */
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Wdl {
+    Loss = -2,
+    BlessedLoss = -1,
+    Draw = 0,
+    CursedWin = 1,
+    Win = 2,
+}
+
+impl Wdl {
+    fn from_value(value: i32) -> Wdl {
+        match value {
+            v if v <= -2 => Wdl::Loss,
+            -1 => Wdl::BlessedLoss,
+            0 => Wdl::Draw,
+            1 => Wdl::CursedWin,
+            _ => Wdl::Win,
+        }
+    }
+
+    fn signum(self) -> i32 {
+        (self as i32).signum()
+    }
+}
+
+impl Neg for Wdl {
+    type Output = Wdl;
+
+    fn neg(self) -> Wdl {
+        Wdl::from_value(-(self as i32))
+    }
+}
+
+impl fmt::Display for Wdl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Wdl::Loss => "loss",
+            Wdl::BlessedLoss => "blessed loss",
+            Wdl::Draw => "draw",
+            Wdl::CursedWin => "cursed win",
+            Wdl::Win => "win",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/**
+ * A legal move of the root position with its tablebase result. The DTZ is counted in plies from the root,
+ * positive if we win. It is missing if the DTZ table is not available.
+ */
+#[derive(Copy, Clone, Debug)]
+pub struct RootMove {
+    pub mov: Move,
+    pub wdl: Wdl,
+    pub dtz: Option<i32>,
+}
+
+/**
+ * Syzygy endgame tablebases read from the .rtbw (win/draw/loss) and .rtbz (distance to zeroing move) files
+ * of a directory. Tables are opened when they are first probed. Only the headers are kept in memory,
+ * the compressed blocks are read from the file on every probe.
+ *
+ * The probing follows the reference implementation by Ronald de Man (and its port in Stockfish):
+ * the position is mirrored into the canonical form of the table, encoded to an index and the value at the index
+ * is decompressed from its block. Positions with castling rights are not in the tables, en passant captures are
+ * resolved by searching the captures before probing.
+ */
+pub struct Tablebase {
+    wdl: HashMap<u64, Arc<TableEntry>>, // Keyed by material, each table is stored for both colors
+    dtz: HashMap<u64, Arc<TableEntry>>,
+    pub max_pieces: usize, // Most pieces of any WDL table, positions with more are not probed
+}
+
+/**
+ * A table file, known from its name.
+ */
+struct TableEntry {
+    path: PathBuf,
+    is_dtz: bool,
+    key: u64, // Material key with the stronger side (first in the name) as white
+    key2: u64, // Material key with the colors switched
+    piece_count: usize,
+    has_pawns: bool,
+    has_unique_pieces: bool, // A piece other than the king exists only once for one color
+    pawn_count: [u8; 2], // Pawns of the leading color and of the other color
+    table: OnceLock<Option<Table>>,
+}
+
+/**
+ * An opened table file.
+ */
+struct Table {
+    file: Mutex<File>,
+    header: Vec<u8>, // Start of the file, contains everything up to the sparse indices
+    items: Vec<Vec<PairsData>>, // By side to move (only one for DTZ and symmetric tables) and leading pawn file
+    map_offset: usize, // Start of the DTZ value maps in the header
+}
+
+/**
+ * One compressed table of a file. Values are compressed with recursive pairing and the pair symbols are stored
+ * in blocks with a canonical Huffman code. Offsets are positions in the file.
+ */
+#[derive(Clone, Default)]
+struct PairsData {
+    flags: u8,
+    pieces: [u8; MAX_PIECES], // Order of the pieces in the encoding, the groups are formed by equal pieces
+    group_len: [usize; MAX_PIECES + 1], // Zero terminated
+    group_idx: [u64; MAX_PIECES + 1], // Multiplier of the index of each group, the last one is the table size
+    block_size: u64,
+    span: u64, // Every span values there is an entry in the sparse index
+    num_blocks: u64,
+    block_length_size: u64,
+    sparse_index_size: u64,
+    min_sym_len: u8, // The value itself in single value tables
+    lowest_sym: Vec<u16>, // Lowest symbol of each length
+    base64: Vec<u64>, // Lowest symbol of each length, left aligned in 64 bits
+    btree: Vec<(u16, u16)>, // Left and right symbol each symbol expands to
+    symlen: Vec<u32>, // Number of values (minus one) of each symbol
+    sparse_index: u64,
+    block_length: u64,
+    data: u64,
+    map_idx: [u16; 4], // Start of the DTZ value map of win, loss, cursed win and blessed loss
+}
+
+enum TableValue {
+    Value(i32),
+    ChangeStm, // DTZ tables only store one side to move
+}
+
+enum HeaderError {
+    Truncated, // The header needs more of the file
+    Invalid,
+}
+
+struct HeaderReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> HeaderReader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], HeaderError> {
+        let bytes = self.data.get(self.pos..self.pos + len).ok_or(HeaderError::Truncated)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, HeaderError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, HeaderError> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, HeaderError> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn peek(&self, offset: usize) -> Result<u8, HeaderError> {
+        self.data.get(self.pos + offset).copied().ok_or(HeaderError::Truncated)
+    }
+
+    fn align(&mut self, alignment: usize) {
+        self.pos = self.pos.div_ceil(alignment) * alignment;
+    }
+}
+
+impl Tablebase {
+    pub fn load(path: &str) -> Result<Tablebase, String> {
+        let files = std::fs::read_dir(path).map_err(|err| format!("Cant read {}: {}", path, err))?;
+        let mut tablebase = Tablebase {
+            wdl: HashMap::new(),
+            dtz: HashMap::new(),
+            max_pieces: 0,
+        };
+        for file in files.filter_map(|file| file.ok()).map(|file| file.path()) {
+            let is_dtz = match file.extension().and_then(|extension| extension.to_str()) {
+                Some("rtbw") => false,
+                Some("rtbz") => true,
+                _ => continue,
+            };
+            let Some(entry) = file.file_stem().and_then(|stem| stem.to_str()).and_then(TableEntry::new) else {
+                continue;
+            };
+            let entry = Arc::new(TableEntry {
+                path: file.clone(),
+                is_dtz,
+                ..entry
+            });
+            if !is_dtz {
+                tablebase.max_pieces = tablebase.max_pieces.max(entry.piece_count);
+            }
+            let tables = if is_dtz { &mut tablebase.dtz } else { &mut tablebase.wdl };
+            tables.insert(entry.key, entry.clone());
+            tables.insert(entry.key2, entry);
+        }
+        if tablebase.wdl.is_empty() {
+            return Err(format!("No Syzygy tables found in {}", path));
+        }
+        println!(
+            "Found {} WDL and {} DTZ tables in {}, up to {} pieces",
+            count_tables(&tablebase.wdl),
+            count_tables(&tablebase.dtz),
+            path,
+            tablebase.max_pieces
+        );
+        Ok(tablebase)
+    }
+
+    /**
+     * Win, draw or loss of the position for the side to move. None if the position is not in the tables.
+     */
+    pub fn probe_wdl(&self, game: &mut Game) -> Option<Wdl> {
+        if !can_probe(game) {
+            return None;
+        }
+        self.search_wdl(game, false).map(|(wdl, _)| wdl)
+    }
+
+    /**
+     * Plies to the next capture or pawn move (zeroing the 50 move counter) with optimal play,
+     * positive if the side to move wins, 0 for draws. Cursed wins and blessed losses are 100 plies further away.
+     */
+    pub fn probe_dtz(&self, game: &mut Game) -> Option<i32> {
+        if !can_probe(game) {
+            return None;
+        }
+        self.dtz(game)
+    }
+
+    /**
+     * Tablebase results of all legal moves of the position.
+     */
+    pub fn root_moves(&self, game: &mut Game) -> Option<Vec<RootMove>> {
+        if !can_probe(game) {
+            return None;
+        }
+        let mut root_moves = Vec::new();
+        for mov in game.get_possible_team_moves(game.turn) {
+            let zeroing = is_zeroing(game, mov);
+            game.make_move(mov);
+            let wdl = self.search_wdl(game, false).map(|(wdl, _)| -wdl);
+            let dtz = if zeroing {
+                wdl.map(dtz_before_zeroing)
+            } else {
+                // One ply further away than from the position after the move
+                self.dtz(game).map(|dtz| -dtz - dtz.signum())
+            };
+            let mates = game.king_check != 0 && game.get_possible_team_moves(game.turn).is_empty();
+            game.unmake_move();
+
+            root_moves.push(RootMove {
+                mov,
+                wdl: wdl?,
+                dtz: if mates && dtz == Some(2) { Some(1) } else { dtz },
+            });
+        }
+        Some(root_moves)
+    }
+
+    /**
+     * The moves that keep the best result: the wins that reach the next zeroing move the fastest,
+     * the draws, or the losses that take the longest.
+     */
+    pub fn best_root_moves(&self, game: &mut Game) -> Option<Vec<Move>> {
+        let root_moves = self.root_moves(game)?;
+        let rank = |root_move: &RootMove| (root_move.wdl, -root_move.dtz.unwrap_or(0));
+        let best = root_moves.iter().map(rank).max()?;
+        Some(root_moves.iter().filter(|root_move| rank(root_move) == best).map(|root_move| root_move.mov).collect())
+    }
+
+    /**
+     * Searches the captures (and pawn moves if `zeroing_moves` is set) before probing the table, the table values of
+     * positions with en passant or with a winning capture are not reliable. Returns the result and whether the best
+     * move is one of the searched moves.
+     */
+    fn search_wdl(&self, game: &mut Game, zeroing_moves: bool) -> Option<(Wdl, bool)> {
+        let moves = game.get_possible_team_moves(game.turn);
+        let mut best = Wdl::Loss;
+        let mut searched = 0;
+        for mov in &moves {
+            if !(mov.move_type.is_capture() || (zeroing_moves && is_zeroing(game, *mov))) {
+                continue;
+            }
+            searched += 1;
+            game.make_move(*mov);
+            let value = self.search_wdl(game, false);
+            game.unmake_move();
+
+            let value = -value?.0;
+            if value > best {
+                best = value;
+                if value == Wdl::Win {
+                    return Some((value, true));
+                }
+            }
+        }
+
+        let no_more_moves = searched > 0 && searched == moves.len();
+        let value = if no_more_moves {
+            best
+        } else {
+            match self.probe_table(game, false, Wdl::Draw)? {
+                TableValue::Value(value) => Wdl::from_value(value),
+                TableValue::ChangeStm => return None,
+            }
+        };
+        if best >= value {
+            Some((best, best > Wdl::Draw || no_more_moves))
+        } else {
+            Some((value, false))
+        }
+    }
+
+    fn dtz(&self, game: &mut Game) -> Option<i32> {
+        let (wdl, zeroing_best_move) = self.search_wdl(game, true)?;
+        // DTZ tables don't store draws
+        if wdl == Wdl::Draw {
+            return Some(0);
+        }
+        if zeroing_best_move {
+            return Some(dtz_before_zeroing(wdl));
+        }
+
+        if let TableValue::Value(dtz) = self.probe_table(game, true, wdl)? {
+            let cursed = if matches!(wdl, Wdl::CursedWin | Wdl::BlessedLoss) { 100 } else { 0 };
+            return Some((dtz + cursed) * wdl.signum());
+        }
+
+        // The table is stored for the other side to move, take the best of all moves
+        let mut min_dtz = i32::MAX;
+        for mov in game.get_possible_team_moves(game.turn) {
+            let zeroing = is_zeroing(game, mov);
+            game.make_move(mov);
+            // For zeroing moves the DTZ is the one before the move, the result after the move only gives the sign
+            let dtz = if zeroing {
+                self.search_wdl(game, false).map(|(wdl, _)| -dtz_before_zeroing(wdl))
+            } else {
+                self.dtz(game).map(|dtz| -dtz)
+            };
+            let mates = game.king_check != 0 && game.get_possible_team_moves(game.turn).is_empty();
+            game.unmake_move();
+
+            let mut dtz = dtz?;
+            if dtz == 1 && mates {
+                min_dtz = 1;
+            }
+            if !zeroing {
+                dtz += dtz.signum();
+            }
+            if dtz < min_dtz && dtz.signum() == wdl.signum() {
+                min_dtz = dtz;
+            }
+        }
+        // Without legal moves we are mated
+        Some(if min_dtz == i32::MAX { -1 } else { min_dtz })
+    }
+
+    /**
+     * Looks up the position in its WDL or DTZ table. `wdl` is the result of the position, needed to read DTZ values.
+     */
+    fn probe_table(&self, game: &Game, is_dtz: bool, wdl: Wdl) -> Option<TableValue> {
+        let material = Material::new(game);
+        if material.piece_count() == 2 {
+            return Some(TableValue::Value(0));
+        }
+        let key = material.key();
+        let entry = if is_dtz { self.dtz.get(&key)? } else { self.wdl.get(&key)? };
+        let table = entry.table()?;
+
+        // Tables are stored with the stronger side as white. With the same material on both sides only
+        // white to move is stored. Otherwise the colors are switched and the board flipped before the lookup
+        let flip = (entry.key == entry.key2 && game.turn == Color::Black) || key != entry.key;
+        let flip_color = if flip { 8 } else { 0 };
+        let flip_squares = if flip { 56 } else { 0 };
+        let stm = flip as usize ^ (game.turn == Color::Black) as usize;
+
+        let mut squares = Vec::with_capacity(MAX_PIECES);
+        let mut pieces = Vec::with_capacity(MAX_PIECES);
+        let mut lead_pawns = 0u64;
+        let mut lead_pawn_count = 0;
+        let mut tb_file = 0;
+
+        // Pawn tables are split by the file of the leading pawn, the one closest to the a or h file
+        // and on the lowest rank. The leading pawns come first in the encoding
+        if entry.has_pawns {
+            let lead_pawn = table.items[0][0].pieces[0] ^ flip_color;
+            for square in 0..64 {
+                if piece_code(game, square) == Some(lead_pawn) {
+                    lead_pawns |= 1 << square;
+                    squares.push(square ^ flip_squares);
+                    pieces.push(lead_pawn ^ flip_color);
+                }
+            }
+            lead_pawn_count = squares.len();
+            let leading = (0..squares.len()).max_by_key(|i| MAP_PAWNS[squares[*i]])?;
+            squares.swap(0, leading);
+            tb_file = (squares[0] % 8).min(7 - squares[0] % 8);
+        }
+
+        if is_dtz {
+            // Symmetric tables without pawns are the same for both sides
+            let both_sides = entry.key == entry.key2 && !entry.has_pawns;
+            if (table.items[0][tb_file].flags & FLAG_STM) as usize != stm && !both_sides {
+                return Some(TableValue::ChangeStm);
+            }
+        }
+
+        for square in 0..64 {
+            if lead_pawns & (1 << square) != 0 {
+                continue;
+            }
+            if let Some(code) = piece_code(game, square) {
+                squares.push(square ^ flip_squares);
+                pieces.push(code ^ flip_color);
+            }
+        }
+
+        let d = &table.items[stm % table.items.len()][tb_file];
+        let index = encode(entry, d, &mut squares, &mut pieces, lead_pawn_count)?;
+        let value = table.decompress_pairs(d, index)?;
+        if !is_dtz {
+            return Some(TableValue::Value(value as i32 - 2));
+        }
+
+        // DTZ values can be mapped to save space, and are stored in moves instead of plies where it makes no difference
+        let d = &table.items[0][tb_file];
+        let mut value = value as usize;
+        if d.flags & FLAG_MAPPED != 0 {
+            let map_idx = d.map_idx[[1, 3, 0, 2, 0][(wdl as i32 + 2) as usize]] as usize;
+            value = if d.flags & FLAG_WIDE != 0 {
+                let offset = table.map_offset + 2 * (map_idx + value);
+                u16::from_le_bytes(table.header.get(offset..offset + 2)?.try_into().unwrap()) as usize
+            } else {
+                *table.header.get(table.map_offset + map_idx + value)? as usize
+            };
+        }
+        let in_moves = match wdl {
+            Wdl::Win => d.flags & FLAG_WIN_PLIES == 0,
+            Wdl::Loss => d.flags & FLAG_LOSS_PLIES == 0,
+            _ => true,
+        };
+        if in_moves {
+            value *= 2;
+        }
+        Some(TableValue::Value(value as i32 + 1))
+    }
+}
+
+impl TableEntry {
+    /**
+     * Entry for a table name like KRPvKR. Each side starts with its king.
+     */
+    fn new(name: &str) -> Option<TableEntry> {
+        let material = Material::from_signature(name)?;
+        let (white, black) = (material.white, material.black);
+        let piece_count = material.piece_count();
+        if piece_count > MAX_PIECES {
+            return None;
+        }
+
+        // The leading color is the one with fewer pawns, if both have pawns
+        let white_leads = black[0] == 0 || (white[0] > 0 && black[0] >= white[0]);
+        Some(TableEntry {
+            path: PathBuf::new(),
+            is_dtz: false,
+            key: material.key(),
+            key2: material.flipped().key(),
+            piece_count,
+            has_pawns: white[0] + black[0] > 0,
+            has_unique_pieces: white[..5].contains(&1) || black[..5].contains(&1),
+            pawn_count: if white_leads { [white[0], black[0]] } else { [black[0], white[0]] },
+            table: OnceLock::new(),
+        })
+    }
+
+    fn table(&self) -> Option<&Table> {
+        self.table.get_or_init(|| Table::open(self)).as_ref()
+    }
+}
+
+impl Table {
+    fn open(entry: &TableEntry) -> Option<Table> {
+        let result = (|| {
+            let mut file = File::open(&entry.path).map_err(|err| err.to_string())?;
+            let file_len = file.metadata().map_err(|err| err.to_string())?.len();
+            let mut read_size = HEADER_READ_SIZE.min(file_len);
+            loop {
+                let header = read_at(&mut file, 0, read_size as usize).ok_or("Read failed".to_string())?;
+                match Table::parse(entry, &header) {
+                    Ok((items, map_offset)) => {
+                        return Ok(Table {
+                            file: Mutex::new(file),
+                            header,
+                            items,
+                            map_offset,
+                        })
+                    }
+                    Err(HeaderError::Truncated) if read_size < file_len => read_size = (read_size * 4).min(file_len),
+                    Err(_) => return Err("Invalid table".to_string()),
+                }
+            }
+        })();
+        match result {
+            Ok(table) => Some(table),
+            Err(err) => {
+                println!("Cant open tablebase file {}: {}", entry.path.display(), err);
+                None
+            }
+        }
+    }
+
+    /**
+     * Reads the tables of the file. Returns them with the start of the DTZ value maps.
+     */
+    fn parse(entry: &TableEntry, header: &[u8]) -> Result<(Vec<Vec<PairsData>>, usize), HeaderError> {
+        let mut reader = HeaderReader { data: header, pos: 0 };
+        let magic = if entry.is_dtz { DTZ_MAGIC } else { WDL_MAGIC };
+        if reader.bytes(4)? != magic {
+            return Err(HeaderError::Invalid);
+        }
+        let flags = reader.u8()?;
+        if (flags & FILE_HAS_PAWNS != 0) != entry.has_pawns || (flags & FILE_SPLIT != 0) != (entry.key != entry.key2) {
+            return Err(HeaderError::Invalid);
+        }
+
+        let sides = if !entry.is_dtz && entry.key != entry.key2 { 2 } else { 1 };
+        let files = if entry.has_pawns { 4 } else { 1 };
+        let both_pawns = entry.has_pawns && entry.pawn_count[1] > 0;
+        let mut items = vec![vec![PairsData::default(); files]; sides];
+
+        for file in 0..files {
+            // Order of the groups in the encoding, for both sides in the two halves of the byte
+            let first = reader.peek(0)?;
+            let second = if both_pawns { reader.peek(1)? } else { 0xFF };
+            let order = [[first & 0xF, second & 0xF], [first >> 4, second >> 4]];
+            reader.bytes(1 + both_pawns as usize)?;
+
+            for k in 0..entry.piece_count {
+                let byte = reader.u8()?;
+                for (side, side_items) in items.iter_mut().enumerate() {
+                    side_items[file].pieces[k] = if side == 1 { byte >> 4 } else { byte & 0xF };
+                }
+            }
+            for (side, side_items) in items.iter_mut().enumerate() {
+                set_groups(entry, &mut side_items[file], order[side], file);
+            }
+        }
+        reader.align(2);
+
+        for file in 0..files {
+            for side_items in items.iter_mut() {
+                set_sizes(&mut side_items[file], &mut reader)?;
+            }
+        }
+
+        let map_offset = reader.pos;
+        if entry.is_dtz {
+            for d in items[0].iter_mut() {
+                if d.flags & FLAG_MAPPED == 0 {
+                    continue;
+                }
+                // Maps of win, loss, cursed win and blessed loss, each starting with its length
+                if d.flags & FLAG_WIDE != 0 {
+                    reader.align(2);
+                    for i in 0..4 {
+                        d.map_idx[i] = ((reader.pos - map_offset) / 2 + 1) as u16;
+                        let len = reader.u16()? as usize;
+                        reader.bytes(2 * len)?;
+                    }
+                } else {
+                    for i in 0..4 {
+                        d.map_idx[i] = (reader.pos - map_offset + 1) as u16;
+                        let len = reader.u8()? as usize;
+                        reader.bytes(len)?;
+                    }
+                }
+            }
+            reader.align(2);
+        }
+
+        // The rest of the file is not read now, only the offsets are needed
+        let mut offset = reader.pos as u64;
+        for file in 0..files {
+            for side_items in items.iter_mut() {
+                side_items[file].sparse_index = offset;
+                offset += side_items[file].sparse_index_size * 6;
+            }
+        }
+        for file in 0..files {
+            for side_items in items.iter_mut() {
+                side_items[file].block_length = offset;
+                offset += side_items[file].block_length_size * 2;
+            }
+        }
+        for file in 0..files {
+            for side_items in items.iter_mut() {
+                offset = offset.div_ceil(64) * 64;
+                side_items[file].data = offset;
+                offset += side_items[file].num_blocks * side_items[file].block_size;
+            }
+        }
+        Ok((items, map_offset))
+    }
+
+    fn read(&self, offset: u64, len: usize) -> Option<Vec<u8>> {
+        let mut file = self.file.lock().ok()?;
+        read_at(&mut file, offset, len)
+    }
+
+    fn block_length(&self, d: &PairsData, block: u64) -> Option<i64> {
+        if block >= d.block_length_size {
+            return None;
+        }
+        let bytes = self.read(d.block_length + 2 * block, 2)?;
+        Some(u16::from_le_bytes(bytes.try_into().unwrap()) as i64)
+    }
+
+    /**
+     * Decompresses the value at the index. The sparse index points close to the block of the value,
+     * the block is then decoded symbol by symbol and the pair symbol containing the value is expanded.
+     */
+    fn decompress_pairs(&self, d: &PairsData, index: u64) -> Option<u32> {
+        if d.flags & FLAG_SINGLE_VALUE != 0 {
+            return Some(d.min_sym_len as u32);
+        }
+
+        let sparse = self.read(d.sparse_index + index / d.span * 6, 6)?;
+        let mut block = u32::from_le_bytes(sparse[0..4].try_into().unwrap()) as u64;
+        let mut offset = u16::from_le_bytes(sparse[4..6].try_into().unwrap()) as i64;
+        // The sparse entry is for the value in the middle of the span
+        offset += (index % d.span) as i64 - (d.span / 2) as i64;
+        while offset < 0 {
+            block = block.checked_sub(1)?;
+            offset += self.block_length(d, block)? + 1;
+        }
+        loop {
+            let length = self.block_length(d, block)?;
+            if offset <= length {
+                break;
+            }
+            offset -= length + 1;
+            block += 1;
+        }
+
+        let data = self.read(d.data + block * d.block_size, d.block_size as usize)?;
+        let word = |pos: usize| data.get(pos..pos + 4).map_or(0, |bytes| u32::from_be_bytes(bytes.try_into().unwrap()) as u64);
+        let min_sym_len = d.min_sym_len as usize;
+        let mut buffer = word(0) << 32 | word(4);
+        let mut buffer_bits: u32 = 64;
+        let mut pos = 8;
+        let mut sym;
+        loop {
+            // Longer symbols have lower values, the length is found by comparing with the lowest symbol of each length
+            let mut len = 0;
+            while len + 1 < d.base64.len() && buffer < d.base64[len] {
+                len += 1;
+            }
+            let shift = 64u32.checked_sub((len + min_sym_len) as u32)?;
+            sym = (buffer - d.base64[len]).checked_shr(shift).unwrap_or(0) as usize + d.lowest_sym[len] as usize;
+            let values = *d.symlen.get(sym)? as i64 + 1;
+            if offset < values {
+                break;
+            }
+            offset -= values;
+            let bits = (len + min_sym_len) as u32;
+            buffer = buffer.checked_shl(bits).unwrap_or(0);
+            buffer_bits = buffer_bits.checked_sub(bits)?;
+            if buffer_bits <= 32 {
+                buffer_bits += 32;
+                buffer |= word(pos) << (64 - buffer_bits);
+                pos += 4;
+            }
+        }
+
+        while d.symlen[sym] != 0 {
+            let (left, right) = d.btree[sym];
+            let left_values = *d.symlen.get(left as usize)? as i64 + 1;
+            if offset < left_values {
+                sym = left as usize;
+            } else {
+                offset -= left_values;
+                sym = right as usize;
+            }
+        }
+        Some(d.btree.get(sym)?.0 as u32)
+    }
+}
+
+/**
+ * Splits the pieces into groups and computes the index multiplier of each group. The first group are the leading
+ * pawns, or the kings and one more unique piece. Equal pieces next to each other form a group.
+ */
+fn set_groups(entry: &TableEntry, d: &mut PairsData, order: [u8; 2], file: usize) {
+    let mut n = 0;
+    let mut first_len: i32 = if entry.has_pawns { 0 } else if entry.has_unique_pieces { 3 } else { 2 };
+    d.group_len[0] = 1;
+    for i in 1..entry.piece_count {
+        first_len -= 1;
+        if first_len > 0 || d.pieces[i] == d.pieces[i - 1] {
+            d.group_len[n] += 1;
+        } else {
+            n += 1;
+            d.group_len[n] = 1;
+        }
+    }
+    n += 1;
+    d.group_len[n] = 0;
+
+    let both_pawns = entry.has_pawns && entry.pawn_count[1] > 0;
+    let mut next = if both_pawns { 2 } else { 1 };
+    let mut free_squares = 64 - d.group_len[0] - if both_pawns { d.group_len[1] } else { 0 };
+    let mut index = 1u64;
+    let mut k = 0;
+    while next < n || k == order[0] as usize || k == order[1] as usize {
+        if k == order[0] as usize {
+            // Leading pawns or pieces
+            d.group_idx[0] = index;
+            index *= if entry.has_pawns {
+                LEAD_PAWNS_SIZE[d.group_len[0]][file]
+            } else if entry.has_unique_pieces {
+                31332
+            } else {
+                462
+            };
+        } else if k == order[1] as usize {
+            // Remaining pawns
+            d.group_idx[1] = index;
+            index *= BINOMIAL[d.group_len[1]][48 - d.group_len[0]];
+        } else {
+            d.group_idx[next] = index;
+            index *= BINOMIAL[d.group_len[next]][free_squares];
+            free_squares -= d.group_len[next];
+            next += 1;
+        }
+        k += 1;
+    }
+    d.group_idx[n] = index;
+}
+
+/**
+ * Reads the block sizes and the Huffman code of a table.
+ */
+fn set_sizes(d: &mut PairsData, reader: &mut HeaderReader) -> Result<(), HeaderError> {
+    d.flags = reader.u8()?;
+    if d.flags & FLAG_SINGLE_VALUE != 0 {
+        d.min_sym_len = reader.u8()?;
+        return Ok(());
+    }
+
+    let groups = d.group_len.iter().position(|len| *len == 0).unwrap_or(MAX_PIECES);
+    let table_size = d.group_idx[groups];
+    d.block_size = 1 << reader.u8()?;
+    d.span = 1 << reader.u8()?;
+    d.sparse_index_size = table_size.div_ceil(d.span);
+    let padding = reader.u8()? as u64;
+    d.num_blocks = reader.u32()? as u64;
+    d.block_length_size = d.num_blocks + padding;
+    let max_sym_len = reader.u8()?;
+    d.min_sym_len = reader.u8()?;
+    if max_sym_len < d.min_sym_len || d.block_size > 1 << 20 {
+        return Err(HeaderError::Invalid);
+    }
+
+    let lengths = (max_sym_len - d.min_sym_len + 1) as usize;
+    d.lowest_sym = (0..lengths).map(|_| reader.u16()).collect::<Result<_, _>>()?;
+    d.base64 = vec![0; lengths];
+    for i in (0..lengths - 1).rev() {
+        d.base64[i] = d.base64[i + 1].wrapping_add(d.lowest_sym[i] as u64).wrapping_sub(d.lowest_sym[i + 1] as u64) / 2;
+    }
+    for (i, base) in d.base64.iter_mut().enumerate() {
+        let shift = 64u32.saturating_sub(i as u32 + d.min_sym_len as u32);
+        *base = base.checked_shl(shift).unwrap_or(0);
+    }
+
+    let symbols = reader.u16()? as usize;
+    d.btree = (0..symbols)
+        .map(|_| {
+            let lr = reader.bytes(3)?;
+            let left = (lr[1] as u16 & 0xF) << 8 | lr[0] as u16;
+            let right = (lr[2] as u16) << 4 | (lr[1] as u16) >> 4;
+            Ok((left, right))
+        })
+        .collect::<Result<_, _>>()?;
+    reader.bytes(symbols & 1)?;
+
+    d.symlen = vec![0; symbols];
+    let mut visited = vec![false; symbols];
+    for sym in 0..symbols {
+        if !visited[sym] {
+            set_symlen(d, sym, &mut visited)?;
+        }
+    }
+    Ok(())
+}
+
+fn set_symlen(d: &mut PairsData, sym: usize, visited: &mut [bool]) -> Result<(), HeaderError> {
+    visited[sym] = true;
+    let (left, right) = (d.btree[sym].0 as usize, d.btree[sym].1 as usize);
+    // Symbols that are not pairs have a right symbol of 0xFFF
+    if right == 0xFFF {
+        return Ok(());
+    }
+    if left >= d.symlen.len() || right >= d.symlen.len() {
+        return Err(HeaderError::Invalid);
+    }
+    if !visited[left] {
+        set_symlen(d, left, visited)?;
+    }
+    if !visited[right] {
+        set_symlen(d, right, visited)?;
+    }
+    d.symlen[sym] = d.symlen[left] + d.symlen[right] + 1;
+    Ok(())
+}
+
+/**
+ * Index of the position in the table. The squares are mirrored so the leading piece is in the a1-d1-d4
+ * triangle (or the leading pawn on the a-d files), then every group is encoded as a combination of squares.
+ */
+fn encode(entry: &TableEntry, d: &PairsData, squares: &mut [usize], pieces: &mut [u8], lead_pawn_count: usize) -> Option<u64> {
+    let size = squares.len();
+
+    // Same order of the pieces as in the table
+    for i in lead_pawn_count..size.saturating_sub(1) {
+        for j in i + 1..size {
+            if d.pieces[i] == pieces[j] {
+                pieces.swap(i, j);
+                squares.swap(i, j);
+                break;
+            }
+        }
+    }
+
+    if squares[0] % 8 > 3 {
+        for square in squares.iter_mut() {
+            *square ^= 7;
+        }
+    }
+
+    let mut index;
+    if entry.has_pawns {
+        index = LEAD_PAWN_IDX[lead_pawn_count][squares[0]];
+        squares[1..lead_pawn_count].sort_by_key(|square| MAP_PAWNS[*square]);
+        for i in 1..lead_pawn_count {
+            index += BINOMIAL[i][MAP_PAWNS[squares[i]] as usize];
+        }
+    } else {
+        if squares[0] / 8 > 3 {
+            for square in squares.iter_mut() {
+                *square ^= 56;
+            }
+        }
+        // The first piece of the leading group not on the a1-h8 diagonal is mirrored below it
+        for i in 0..d.group_len[0] {
+            let diagonal = off_diagonal(squares[i]);
+            if diagonal == 0 {
+                continue;
+            }
+            if diagonal > 0 {
+                for square in squares[i..].iter_mut() {
+                    *square = ((*square >> 3) | (*square << 3)) & 63;
+                }
+            }
+            break;
+        }
+
+        if entry.has_unique_pieces {
+            let (s0, s1, s2) = (squares[0], squares[1], squares[2]);
+            let adjust1 = (s1 > s0) as u64;
+            let adjust2 = (s2 > s0) as u64 + (s2 > s1) as u64;
+            let rank = |square: usize| (square / 8) as u64;
+            index = if off_diagonal(s0) != 0 {
+                (MAP_A1D1D4[s0] * 63 + (s1 as u64 - adjust1)) * 62 + s2 as u64 - adjust2
+            } else if off_diagonal(s1) != 0 {
+                (6 * 63 + rank(s0) * 28 + MAP_B1H1H7[s1]) * 62 + s2 as u64 - adjust2
+            } else if off_diagonal(s2) != 0 {
+                6 * 63 * 62 + 4 * 28 * 62 + rank(s0) * 7 * 28 + (rank(s1) - adjust1) * 28 + MAP_B1H1H7[s2]
+            } else {
+                6 * 63 * 62 + 4 * 28 * 62 + 4 * 7 * 28 + rank(s0) * 7 * 6 + (rank(s1) - adjust1) * 6 + (rank(s2) - adjust2)
+            };
+        } else {
+            index = MAP_KK[MAP_A1D1D4[squares[0]] as usize][squares[1]];
+        }
+    }
+
+    index *= d.group_idx[0];
+    let mut group_start = d.group_len[0];
+    // The other pawns can't be on the first and last rank
+    let mut remaining_pawns = entry.has_pawns && entry.pawn_count[1] > 0;
+    let mut next = 1;
+    while d.group_len[next] != 0 {
+        let len = d.group_len[next];
+        squares[group_start..group_start + len].sort();
+        let mut n = 0;
+        for i in 0..len {
+            let square = squares[group_start + i];
+            // Squares of earlier groups are not available
+            let taken = squares[..group_start].iter().filter(|earlier| square > **earlier).count();
+            let available = square.checked_sub(taken + if remaining_pawns { 8 } else { 0 })?;
+            n += BINOMIAL[i + 1][available];
+        }
+        remaining_pawns = false;
+        index += n * d.group_idx[next];
+        group_start += len;
+        next += 1;
+    }
+    Some(index)
+}
+
+fn read_at(file: &mut File, offset: u64, len: usize) -> Option<Vec<u8>> {
+    let mut buffer = vec![0; len];
+    file.seek(SeekFrom::Start(offset)).ok()?;
+    file.read_exact(&mut buffer).ok()?;
+    Some(buffer)
+}
+
+fn count_tables(tables: &HashMap<u64, Arc<TableEntry>>) -> usize {
+    tables.iter().filter(|(key, entry)| **key == entry.key).count()
+}
+
+fn can_probe(game: &Game) -> bool {
+    let state = &game.state;
+    !(state.white_can_castle_kingside
+        || state.white_can_castle_queenside
+        || state.black_can_castle_kingside
+        || state.black_can_castle_queenside)
+}
+
+fn is_zeroing(game: &Game, mov: Move) -> bool {
+    mov.move_type.is_capture() || game.board.get_piece(mov.from).is_some_and(|piece| piece.piece_type == PieceType::Pawn)
+}
+
+fn dtz_before_zeroing(wdl: Wdl) -> i32 {
+    match wdl {
+        Wdl::Win => 1,
+        Wdl::CursedWin => 101,
+        Wdl::Draw => 0,
+        Wdl::BlessedLoss => -101,
+        Wdl::Loss => -1,
+    }
+}
+
+pub fn piece_count(game: &Game) -> usize {
+    game.board.pieces.iter().flatten().count()
+}
+
+/**
+ * Piece as stored in the tables: 1 to 6 for the white pawn to king, 9 to 14 for black.
+ */
+fn piece_code(game: &Game, square: usize) -> Option<u8> {
+    let piece = game.board.pieces[square]?;
+    let color = if let Color::Black = piece.color { 8 } else { 0 };
+    Some(piece.piece_type as u8 + 1 + color)
+}
+
+const fn off_diagonal(square: usize) -> i32 {
+    (square / 8) as i32 - (square % 8) as i32
+}
+
+/**
+ * Tables of the index encoding, the same as in the generator of the files.
+ */
+static MAP_B1H1H7: [u64; 64] = create_map_b1h1h7();
+static MAP_A1D1D4: [u64; 64] = create_map_a1d1d4();
+static MAP_KK: [[u64; 64]; 10] = create_map_kk();
+static BINOMIAL: [[u64; 64]; MAX_PIECES] = create_binomial();
+static MAP_PAWNS: [u64; 64] = create_pawn_index().0;
+static LEAD_PAWN_IDX: [[u64; 64]; 6] = create_pawn_index().1;
+static LEAD_PAWNS_SIZE: [[u64; 4]; 6] = create_pawn_index().2;
+
+/**
+ * Squares below the a1-h8 diagonal to 0..27.
+ */
+const fn create_map_b1h1h7() -> [u64; 64] {
+    let mut map = [0; 64];
+    let mut code = 0;
+    let mut square = 0;
+    while square < 64 {
+        if off_diagonal(square) < 0 {
+            map[square] = code;
+            code += 1;
+        }
+        square += 1;
+    }
+    map
+}
+
+/**
+ * Squares of the a1-d1-d4 triangle to 0..9, the diagonal squares last.
+ */
+const fn create_map_a1d1d4() -> [u64; 64] {
+    let mut map = [0; 64];
+    let mut code = 0;
+    let mut diagonal = 0;
+    while diagonal < 2 {
+        let mut square = 0;
+        while square <= 27 {
+            let on_diagonal = off_diagonal(square) == 0;
+            if square % 8 <= 3 && off_diagonal(square) <= 0 && on_diagonal == (diagonal == 1) {
+                map[square] = code;
+                code += 1;
+            }
+            square += 1;
+        }
+        diagonal += 1;
+    }
+    map
+}
+
+/**
+ * The 462 legal placements of two kings with the first in the a1-d1-d4 triangle. If the first king is on
+ * the diagonal the second is not above it. Placements with both kings on the diagonal come last.
+ */
+const fn create_map_kk() -> [[u64; 64]; 10] {
+    let map_a1d1d4 = create_map_a1d1d4();
+    let mut map = [[0; 64]; 10];
+    let mut code = 0;
+    let mut both_on_diagonal = 0;
+    while both_on_diagonal < 2 {
+        let mut idx = 0;
+        while idx < 10 {
+            let mut s1 = 0;
+            while s1 <= 27 {
+                // b1 is mapped to 0, like all squares outside the triangle
+                if map_a1d1d4[s1] == idx as u64 && (idx != 0 || s1 == 1) {
+                    let mut s2 = 0;
+                    while s2 < 64 {
+                        let touching = (s1 / 8).abs_diff(s2 / 8) <= 1 && (s1 % 8).abs_diff(s2 % 8) <= 1;
+                        let above = off_diagonal(s1) == 0 && off_diagonal(s2) > 0;
+                        let on_diagonal = off_diagonal(s1) == 0 && off_diagonal(s2) == 0;
+                        if !touching && !above && on_diagonal == (both_on_diagonal == 1) {
+                            map[idx][s2] = code;
+                            code += 1;
+                        }
+                        s2 += 1;
+                    }
+                }
+                s1 += 1;
+            }
+            idx += 1;
+        }
+        both_on_diagonal += 1;
+    }
+    map
+}
+
+/**
+ * BINOMIAL[k][n] is the number of ways to choose k of n squares.
+ */
+const fn create_binomial() -> [[u64; 64]; MAX_PIECES] {
+    let mut binomial = [[0; 64]; MAX_PIECES];
+    binomial[0][0] = 1;
+    let mut n = 1;
+    while n < 64 {
+        let mut k = 0;
+        while k < MAX_PIECES && k <= n {
+            let with = if k > 0 { binomial[k - 1][n - 1] } else { 0 };
+            let without = if k < n { binomial[k][n - 1] } else { 0 };
+            binomial[k][n] = with + without;
+            k += 1;
+        }
+        n += 1;
+    }
+    binomial
+}
+
+/**
+ * Encoding of the leading pawns. MAP_PAWNS orders the squares a2-h7 so the leading pawn has the highest value,
+ * it is the number of squares left for the other leading pawns. LEAD_PAWN_IDX is the index of the leading pawn square
+ * for each number of leading pawns, LEAD_PAWNS_SIZE the number of indices per file.
+ */
+const fn create_pawn_index() -> ([u64; 64], [[u64; 64]; 6], [[u64; 4]; 6]) {
+    let binomial = create_binomial();
+    let mut map_pawns = [0; 64];
+    let mut lead_pawn_idx = [[0; 64]; 6];
+    let mut lead_pawns_size = [[0; 4]; 6];
+    let mut available = 47;
+    let mut count = 1;
+    while count <= 5 {
+        let mut file = 0;
+        while file < 4 {
+            let mut idx = 0;
+            let mut rank = 1;
+            while rank <= 6 {
+                let square = rank * 8 + file;
+                if count == 1 {
+                    map_pawns[square] = available as u64;
+                    map_pawns[square ^ 7] = (available - 1) as u64;
+                    available -= 2;
+                }
+                lead_pawn_idx[count][square] = idx;
+                idx += binomial[count - 1][map_pawns[square] as usize];
+                rank += 1;
+            }
+            lead_pawns_size[count][file] = idx;
+            file += 1;
+        }
+        count += 1;
+    }
+    (map_pawns, lead_pawn_idx, lead_pawns_size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // KQvK and KRvK tables written by testdata/syzygy/generate.py
+    fn tablebase() -> Tablebase {
+        Tablebase::load(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/syzygy")).unwrap()
+    }
+
+    fn moves(list: &[&str]) -> Vec<String> {
+        list.iter().map(|mov| mov.to_string()).collect()
+    }
+
+    fn best_moves(tablebase: &Tablebase, fen: &str) -> Vec<String> {
+        let mut moves: Vec<String> = tablebase.best_root_moves(&mut Game::from_fen(fen)).unwrap().iter().map(|mov| mov.to_string()).collect();
+        moves.sort();
+        moves
+    }
+
+    #[test]
+    fn loads_the_tables() {
+        let tablebase = tablebase();
+        assert_eq!(tablebase.max_pieces, 3);
+        assert_eq!(count_tables(&tablebase.wdl), 2);
+        assert_eq!(count_tables(&tablebase.dtz), 2);
+    }
+
+    #[test]
+    fn probes_wdl() {
+        let tablebase = tablebase();
+        let wdl = |fen: &str| tablebase.probe_wdl(&mut Game::from_fen(fen));
+        assert_eq!(wdl("8/8/8/3k4/8/8/7Q/K7 w - - 0 1"), Some(Wdl::Win));
+        assert_eq!(wdl("8/8/8/3k4/8/8/7Q/K7 b - - 0 1"), Some(Wdl::Loss));
+        // Stalemate
+        assert_eq!(wdl("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1"), Some(Wdl::Draw));
+        // The black king takes the rook, unless the white king defends it
+        assert_eq!(wdl("8/8/8/8/8/2k5/3R4/7K b - - 0 1"), Some(Wdl::Draw));
+        assert_eq!(wdl("8/8/8/8/8/2k5/3R4/4K3 b - - 0 1"), Some(Wdl::Loss));
+        // The stronger side is black
+        assert_eq!(wdl("8/8/8/3K4/8/8/7q/k7 b - - 0 1"), Some(Wdl::Win));
+        assert_eq!(wdl("8/8/8/3K4/8/8/8/k6r w - - 0 1"), Some(Wdl::Loss));
+        // Not in the tables
+        assert_eq!(wdl("8/8/8/3k4/8/8/8/K5QQ w - - 0 1"), None);
+    }
+
+    #[test]
+    fn probes_dtz() {
+        let tablebase = tablebase();
+        let dtz = |fen: &str| tablebase.probe_dtz(&mut Game::from_fen(fen));
+        // Mate in one, and mated
+        assert_eq!(dtz("k7/8/1K6/8/8/8/8/2Q5 w - - 0 1"), Some(1));
+        assert_eq!(dtz("k1Q5/8/1K6/8/8/8/8/8 b - - 0 1"), Some(-1));
+        assert_eq!(dtz("k7/8/1K6/8/8/8/8/3R4 w - - 0 1"), Some(1));
+        // Black can only go to h7, then Qh5 mates
+        assert_eq!(dtz("7k/5K2/8/8/8/8/8/5Q2 b - - 0 1"), Some(-2));
+        assert_eq!(dtz("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1"), Some(0));
+        // Wins take an odd number of plies, losses an even number
+        let win = dtz("8/8/8/3k4/8/8/8/K6R w - - 0 1").unwrap();
+        assert!((3..=31).contains(&win) && win % 2 == 1, "DTZ {}", win);
+        let loss = dtz("8/8/8/3k4/8/8/8/K6R b - - 0 1").unwrap();
+        assert!((-32..=-2).contains(&loss) && loss % 2 == 0, "DTZ {}", loss);
+    }
+
+    #[test]
+    fn chooses_the_fastest_win_and_avoids_stalemate() {
+        let tablebase = tablebase();
+        // Qc7 stalemates, Qc8 mates
+        assert_eq!(best_moves(&tablebase, "k7/8/1K6/8/8/8/8/2Q5 w - - 0 1"), moves(&["c1c8"]));
+        let root_moves = tablebase.root_moves(&mut Game::from_fen("k7/8/1K6/8/8/8/8/2Q5 w - - 0 1")).unwrap();
+        let stalemate = root_moves.iter().find(|root_move| root_move.mov.to_string() == "c1c7").unwrap();
+        assert_eq!(stalemate.wdl, Wdl::Draw);
+        assert_eq!(best_moves(&tablebase, "K7/8/1k6/8/8/8/8/2q5 b - - 0 1"), moves(&["c1c8"]));
+        assert_eq!(best_moves(&tablebase, "k7/8/1K6/8/8/8/8/3R4 w - - 0 1"), moves(&["d1d8"]));
+        // Taking the undefended rook draws
+        assert_eq!(best_moves(&tablebase, "8/8/8/8/8/2k5/3R4/7K b - - 0 1"), moves(&["c3d2"]));
+    }
+
+    #[test]
+    fn best_moves_mate_in_the_dtz() {
+        let tablebase = tablebase();
+        let mut game = Game::from_fen("8/8/8/3k4/8/8/8/K6R w - - 0 1");
+        let dtz = tablebase.probe_dtz(&mut game).unwrap();
+        let mut plies = 0;
+        while !game.get_possible_team_moves(game.turn).is_empty() {
+            let mov = tablebase.best_root_moves(&mut game).unwrap()[0];
+            assert!(game.make_move(mov));
+            plies += 1;
+            assert!(plies <= dtz, "No mate after {} plies", plies);
+        }
+        assert_ne!(game.king_check, 0);
+        assert_eq!(plies, dtz);
+    }
+}
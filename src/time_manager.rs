@@ -0,0 +1,54 @@
+use crate::search::SearchSettings;
+
+// Below this many milliseconds on the clock we are in panic mode and only search shallow
+const PANIC_TIME: u64 = 5000;
+const PANIC_DEPTH: u8 = 3;
+// With a time limit the depth is only limited by the time
+pub const MAX_DEPTH: u8 = 64;
+// Never think longer than this on a single move, even in very long games
+const MAX_MOVE_TIME: u64 = 60000;
+// Always search a little, even with almost no time left. Not moving at all loses on time for sure
+const MIN_MOVE_TIME: u64 = 10;
+
+#[derive(Copy, Clone, Debug)]
+pub struct TimeBudget {
+    pub time: u64, // Milliseconds for this move
+    pub panic: bool,
+}
+
+/**
+ * Splits the remaining time over the rest of the game. All times are in milliseconds.
+ * `overhead` is the time lost per move outside of the search (e.g. network latency), it is kept in reserve.
+ * `moves_to_go` is the number of moves until the next time control, if there is one.
+ */
+pub fn allocate(time_left: u64, increment: u64, moves_to_go: Option<u32>, plies_played: usize, overhead: u64) -> TimeBudget {
+    let available = time_left.saturating_sub(overhead);
+    let panic = time_left < PANIC_TIME.max(overhead * 10);
+
+    // Expect the game to last at least 20 more moves, more at the start of the game
+    let moves_to_go = match moves_to_go {
+        Some(moves) => moves.max(1) as u64,
+        None => (40 - (plies_played / 2).min(20)) as u64,
+    };
+    let time = if panic {
+        available / (moves_to_go * 3) + increment / 4
+    } else {
+        available / moves_to_go + increment * 3 / 4
+    };
+
+    TimeBudget {
+        time: time.min(available / 3).clamp(MIN_MOVE_TIME, MAX_MOVE_TIME),
+        panic,
+    }
+}
+
+/**
+ * Search settings that search for the time of the budget instead of to a fixed depth.
+ */
+pub fn timed_settings(settings: &SearchSettings, budget: TimeBudget) -> SearchSettings {
+    SearchSettings {
+        depth: if budget.panic { settings.depth.min(PANIC_DEPTH) } else { MAX_DEPTH },
+        time_limit: Some(budget.time),
+        ..settings.clone()
+    }
+}
@@ -0,0 +1,149 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{base_types::{PieceType, Position}, moves::{Move, MoveType}};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Bound {
+    Exact,
+    Lower, // Score is at least this value (beta cutoff)
+    Upper, // Score is at most this value (no move raised alpha)
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct TtEntry {
+    pub depth: u8,
+    pub score: i32,
+    pub bound: Bound,
+    pub best_move: Option<Move>,
+}
+
+impl TtEntry {
+    fn pack(&self) -> u64 {
+        let bound = match self.bound {
+            Bound::Exact => 0,
+            Bound::Lower => 1,
+            Bound::Upper => 2,
+        };
+        // Move bits: valid flag, from, to and promotion piece
+        let move_bits = match self.best_move {
+            Some(m) => {
+                let promotion = match m.move_type.get_promotion_piece() {
+                    PieceType::Knight => 1,
+                    PieceType::Bishop => 2,
+                    PieceType::Rook => 3,
+                    PieceType::Queen => 4,
+                    _ => 0,
+                };
+                1 << 15 | (m.from.index() as u64) << 9 | (m.to.index() as u64) << 3 | promotion
+            }
+            None => 0,
+        };
+        (self.score as u32 as u64) << 32 | (self.depth as u64) << 24 | bound << 16 | move_bits
+    }
+
+    fn unpack(data: u64) -> TtEntry {
+        let bound = match (data >> 16) & 0xFF {
+            0 => Bound::Exact,
+            1 => Bound::Lower,
+            _ => Bound::Upper,
+        };
+        let best_move = if data & (1 << 15) != 0 {
+            let move_type = match data & 0x7 {
+                1 => MoveType::KnightPromotion,
+                2 => MoveType::BishopPromotion,
+                3 => MoveType::RookPromotion,
+                4 => MoveType::QueenPromotion,
+                _ => MoveType::Quite,
+            };
+            Some(Move {
+                from: Position::new(((data >> 9) & 0x3F) as u8),
+                to: Position::new(((data >> 3) & 0x3F) as u8),
+                move_type,
+            })
+        } else {
+            None
+        };
+        TtEntry {
+            depth: ((data >> 24) & 0xFF) as u8,
+            score: (data >> 32) as u32 as i32,
+            bound,
+            best_move,
+        }
+    }
+}
+
+/**
+ * Transposition table that can be shared between search threads without locking.
+ * Every slot stores the hash xor'ed with the data next to the data itself. A slot that got torn by two
+ * threads writing at the same time will not verify on probing and is treated as a miss.
+ * The stored move only contains from, to and promotion piece. It has to be matched against the generated moves.
+ */
+pub struct TranspositionTable {
+    slots: Vec<(AtomicU64, AtomicU64)>,
+}
+
+impl TranspositionTable {
+    pub fn new(size_mb: usize) -> TranspositionTable {
+        let slot_count = (size_mb.max(1) * 1024 * 1024 / std::mem::size_of::<(AtomicU64, AtomicU64)>()).max(1);
+        TranspositionTable {
+            slots: (0..slot_count).map(|_| (AtomicU64::new(0), AtomicU64::new(0))).collect(),
+        }
+    }
+
+    /**
+     * Table without any slots. Probing always misses and storing does nothing.
+     */
+    pub fn empty() -> TranspositionTable {
+        TranspositionTable { slots: Vec::new() }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    /**
+     * Permille of the table in use, estimated from the first thousand slots.
+     */
+    pub fn hashfull(&self) -> u32 {
+        let sample = self.slots.len().min(1000);
+        if sample == 0 {
+            return 0;
+        }
+        let used = self.slots[..sample].iter().filter(|(_, data)| data.load(Ordering::Relaxed) != 0).count();
+        (used * 1000 / sample) as u32
+    }
+
+    fn slot(&self, hash: u64) -> &(AtomicU64, AtomicU64) {
+        &self.slots[(hash % self.slots.len() as u64) as usize]
+    }
+
+    pub fn probe(&self, hash: u64) -> Option<TtEntry> {
+        if self.is_empty() {
+            return None;
+        }
+        let (key, data) = self.slot(hash);
+        let key = key.load(Ordering::Relaxed);
+        let data = data.load(Ordering::Relaxed);
+        if data == 0 || key ^ data != hash {
+            return None;
+        }
+        Some(TtEntry::unpack(data))
+    }
+
+    pub fn store(&self, hash: u64, entry: TtEntry) {
+        if self.is_empty() {
+            return;
+        }
+        let (key, data) = self.slot(hash);
+
+        // Keep deeper results of the same position, always replace other positions
+        let old_data = data.load(Ordering::Relaxed);
+        if old_data != 0 && key.load(Ordering::Relaxed) ^ old_data == hash && TtEntry::unpack(old_data).depth > entry.depth {
+            return;
+        }
+
+        let new_data = entry.pack();
+        key.store(hash ^ new_data, Ordering::Relaxed);
+        data.store(new_data, Ordering::Relaxed);
+    }
+}
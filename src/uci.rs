@@ -0,0 +1,323 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::{
+    base_types::Color,
+    game::Game,
+    input,
+    moves::Move,
+    search::{is_same_move, Search, SearchHandle, SearchSettings},
+    skill::{Skill, MAX_SKILL_LEVEL},
+    tablebase::Tablebase,
+    time_manager::{self, MAX_DEPTH},
+    tt::TranspositionTable,
+    STARTING_POS_FEN,
+};
+
+// How often a running search is checked for new info and new commands
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/**
+ * The engine as a UCI process for chess GUIs, or for the match runner to play two builds against each other.
+ * Reads commands from stdin until quit or until stdin is closed.
+ */
+pub struct Uci {
+    settings: SearchSettings,
+    game: Game,
+    plies_played: usize,
+    search: Option<SearchHandle>,
+    wait_for_stop: bool, // Infinite and ponder searches only send their best move after stop or ponderhit
+    limit_strength: bool, // UCI_LimitStrength, the skill level is then taken from the Elo
+    elo: u32,
+    skill_level: u8,
+}
+
+impl Uci {
+    pub fn new(tablebase: Option<Arc<Tablebase>>) -> Uci {
+        Uci {
+            settings: SearchSettings {
+                tablebase,
+                ..Default::default()
+            },
+            game: Game::from_fen(STARTING_POS_FEN),
+            plies_played: 0,
+            search: None,
+            wait_for_stop: false,
+            limit_strength: false,
+            elo: Skill::new(MAX_SKILL_LEVEL).elo(),
+            skill_level: MAX_SKILL_LEVEL,
+        }
+    }
+
+    /**
+     * Answers the uci command that started the mode, then handles the commands that follow.
+     */
+    pub fn run(&mut self) {
+        self.handle_command("uci");
+        loop {
+            let line = match &self.search {
+                Some(_) => match input::try_read_line() {
+                    Some(line) => line,
+                    None => {
+                        self.poll_search();
+                        std::thread::sleep(POLL_INTERVAL);
+                        continue;
+                    }
+                },
+                None => input::read_line(),
+            };
+            // Stdin closed
+            if line.is_empty() {
+                self.stop_search();
+                return;
+            }
+            if !self.handle_command(line.trim()) {
+                self.stop_search();
+                return;
+            }
+        }
+    }
+
+    /**
+     * Returns false on quit.
+     */
+    fn handle_command(&mut self, command: &str) -> bool {
+        let words: Vec<&str> = command.split_whitespace().collect();
+        match words.first().copied() {
+            Some("uci") => {
+                println!("id name {} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+                println!("option name Hash type spin default {} min 1 max 4096", SearchSettings::default().hash_size);
+                println!("option name Threads type spin default 1 min 1 max 256");
+                println!("option name MultiPV type spin default 1 min 1 max 64");
+                println!("option name Skill Level type spin default {} min 0 max {}", MAX_SKILL_LEVEL, MAX_SKILL_LEVEL);
+                println!("option name UCI_LimitStrength type check default false");
+                println!(
+                    "option name UCI_Elo type spin default {} min {} max {}",
+                    self.elo,
+                    Skill::new(0).elo(),
+                    Skill::new(MAX_SKILL_LEVEL).elo()
+                );
+                println!("option name SyzygyPath type string default <empty>");
+                println!("option name Ponder type check default false");
+                println!("uciok");
+            }
+            Some("isready") => println!("readyok"),
+            Some("setoption") => self.set_option(command),
+            Some("ucinewgame") => {
+                self.stop_search();
+                self.settings.tt = None;
+                self.set_position(STARTING_POS_FEN, &[]);
+            }
+            Some("position") => {
+                let moves_at = words.iter().position(|word| *word == "moves");
+                let moves = moves_at.map_or(&[][..], |i| &words[i + 1..]);
+                let setup = &words[1..moves_at.unwrap_or(words.len())];
+                match setup.first() {
+                    Some(&"startpos") => self.set_position(STARTING_POS_FEN, moves),
+                    Some(&"fen") => self.set_position(&setup[1..].join(" "), moves),
+                    _ => println!("info string Invalid position command"),
+                }
+            }
+            Some("go") => self.go(&words[1..]),
+            Some("stop") => {
+                self.wait_for_stop = false;
+                if let Some(search) = &self.search {
+                    search.stop();
+                }
+            }
+            Some("ponderhit") => {
+                self.wait_for_stop = false;
+                if let Some(search) = &self.search {
+                    search.ponderhit();
+                }
+            }
+            Some("quit") => return false,
+            _ => {}
+        }
+        true
+    }
+
+    /**
+     * Handles "setoption name <name> value <value>", the name can have spaces.
+     */
+    fn set_option(&mut self, command: &str) {
+        let option = match command.split_once("name ") {
+            Some((_, option)) => option,
+            None => return,
+        };
+        let (name, value) = option.split_once(" value ").unwrap_or((option, ""));
+        let value = value.trim();
+        match name.trim().to_lowercase().as_str() {
+            "hash" => {
+                self.settings.hash_size = value.parse().unwrap_or(self.settings.hash_size);
+                self.settings.tt = None;
+            }
+            "threads" => self.settings.threads = value.parse().unwrap_or(self.settings.threads),
+            "multipv" => self.settings.multi_pv = value.parse().unwrap_or(self.settings.multi_pv),
+            "skill level" => self.skill_level = Skill::new(value.parse().unwrap_or(self.skill_level)).level,
+            "uci_limitstrength" => self.limit_strength = value == "true",
+            "uci_elo" => self.elo = value.parse().unwrap_or(self.elo),
+            "syzygypath" => {
+                self.settings.tablebase = match value {
+                    "" | "<empty>" => None,
+                    path => match Tablebase::load(path) {
+                        Ok(tablebase) => Some(Arc::new(tablebase)),
+                        Err(err) => {
+                            println!("info string {}", err);
+                            None
+                        }
+                    },
+                }
+            }
+            _ => {}
+        }
+        self.settings.skill_level = if self.limit_strength { Skill::from_elo(self.elo).level } else { self.skill_level };
+    }
+
+    fn set_position(&mut self, fen: &str, moves: &[&str]) {
+        self.game = Game::from_fen(fen);
+        self.plies_played = 0;
+        for uci_move in moves {
+            let legal = Move::parse(uci_move).and_then(|mov| {
+                self.game.get_possible_team_moves(self.game.turn).into_iter().find(|m| is_same_move(*m, mov))
+            });
+            match legal {
+                Some(mov) => {
+                    self.game.make_move(mov);
+                    self.plies_played += 1;
+                }
+                None => {
+                    println!("info string Illegal move {}", uci_move);
+                    return;
+                }
+            }
+        }
+    }
+
+    /**
+     * Starts the search with the limits of the go command. Without any limit it runs until stop.
+     */
+    fn go(&mut self, args: &[&str]) {
+        self.stop_search();
+        let value = |name: &str| -> Option<u64> {
+            let i = args.iter().position(|arg| *arg == name)?;
+            args.get(i + 1)?.parse().ok()
+        };
+
+        let mut settings = SearchSettings {
+            depth: MAX_DEPTH,
+            tt: Some(self.settings.tt.clone().unwrap_or_else(|| Arc::new(TranspositionTable::new(self.settings.hash_size)))),
+            ..self.settings.clone()
+        };
+        self.settings.tt = settings.tt.clone();
+        let (time_left, increment) = match self.game.turn {
+            Color::White => (value("wtime"), value("winc")),
+            Color::Black => (value("btime"), value("binc")),
+        };
+        if let Some(time_left) = time_left {
+            let moves_to_go = value("movestogo").map(|moves| moves as u32);
+            let budget = time_manager::allocate(time_left, increment.unwrap_or(0), moves_to_go, self.plies_played, 0);
+            settings = time_manager::timed_settings(&settings, budget);
+        }
+        if let Some(time) = value("movetime") {
+            settings.time_limit = Some(time);
+        }
+        if let Some(nodes) = value("nodes") {
+            settings.node_limit = Some(nodes);
+        }
+        if let Some(depth) = value("depth") {
+            settings.depth = depth.min(MAX_DEPTH as u64) as u8;
+        }
+
+        self.wait_for_stop = args.contains(&"infinite") || args.contains(&"ponder");
+        self.search = Some(if args.contains(&"ponder") {
+            Search::spawn_ponder(self.game.clone(), settings)
+        } else {
+            Search::spawn(self.game.clone(), settings)
+        });
+    }
+
+    /**
+     * Prints the info of the running search, and the best move once it is finished.
+     */
+    fn poll_search(&mut self) {
+        let search = match &self.search {
+            Some(search) => search,
+            None => return,
+        };
+        let finished = search.is_finished();
+        while let Some(info) = search.try_info() {
+            println!("info {}", info);
+        }
+        if !finished || self.wait_for_stop {
+            return;
+        }
+        let result = self.search.take().unwrap().join();
+        match result.ponder_move {
+            Some(ponder) => println!("bestmove {} ponder {}", result.best_move.to_string(), ponder.to_string()),
+            None => println!("bestmove {}", result.best_move.to_string()),
+        }
+    }
+
+    /**
+     * Stops a running search and waits for its best move.
+     */
+    fn stop_search(&mut self) {
+        self.wait_for_stop = false;
+        if let Some(search) = &self.search {
+            search.stop();
+        }
+        while self.search.is_some() {
+            self.poll_search();
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sets_up_the_position_with_moves() {
+        let mut uci = Uci::new(None);
+        uci.handle_command("position startpos moves e2e4 e7e5 g1f3");
+        assert_eq!(uci.game.to_fen(), Game::from_fen("rnbqkbnr/pppp1ppp/8/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2").to_fen());
+        assert_eq!(uci.plies_played, 3);
+        uci.handle_command("position fen 6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1 moves a1a8");
+        assert_eq!(uci.game.to_fen(), Game::from_fen("R5k1/5ppp/8/8/8/8/8/6K1 b - - 1 1").to_fen());
+    }
+
+    #[test]
+    fn limits_the_strength_by_elo() {
+        let mut uci = Uci::new(None);
+        uci.handle_command("setoption name Skill Level value 8");
+        assert_eq!(uci.settings.skill_level, 8);
+        uci.handle_command("setoption name UCI_Elo value 500");
+        assert_eq!(uci.settings.skill_level, 8);
+        uci.handle_command("setoption name UCI_LimitStrength value true");
+        assert_eq!(uci.settings.skill_level, 0);
+        uci.handle_command("setoption name Hash value 1");
+        assert_eq!(uci.settings.hash_size, 1);
+    }
+
+    #[test]
+    fn searches_until_stop_and_keeps_the_table() {
+        let mut uci = Uci::new(None);
+        uci.handle_command("position startpos");
+        uci.handle_command("go infinite");
+        std::thread::sleep(Duration::from_millis(50));
+        uci.poll_search();
+        assert!(uci.search.is_some());
+        let tt = uci.settings.tt.clone().unwrap();
+        uci.handle_command("stop");
+        uci.stop_search();
+        assert!(uci.search.is_none());
+
+        uci.handle_command("go depth 2");
+        uci.stop_search();
+        assert!(Arc::ptr_eq(&tt, uci.settings.tt.as_ref().unwrap()));
+        uci.handle_command("ucinewgame");
+        assert!(uci.settings.tt.is_none());
+    }
+}
@@ -0,0 +1,226 @@
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::search::Score;
+
+/**
+ * Limit of a single search of an external engine.
+ */
+#[derive(Copy, Clone, Debug)]
+pub enum GoLimit {
+    MoveTime(u64), // Milliseconds
+    Nodes(u64),
+    Depth(u8),
+    Clock {
+        // Milliseconds left on the clocks and the increments
+        wtime: u64,
+        btime: u64,
+        winc: u64,
+        binc: u64,
+        moves_to_go: Option<u32>,
+    },
+}
+
+/**
+ * Answer of an engine to a go command.
+ */
+#[derive(Clone, Debug)]
+pub struct EngineMove {
+    pub best_move: String, // UCI notation
+    pub score: Option<Score>, // Score of the last info line, from the view of the side to move
+}
+
+// Time the engine gets to answer the handshake and isready
+const READY_TIMEOUT: Duration = Duration::from_secs(10);
+// Time the engine gets to send its best move after the time of the search is up. Short in tests with hanging engines
+const MOVE_GRACE: Duration = Duration::from_millis(if cfg!(test) { 200 } else { 5000 });
+
+/**
+ * An external chess engine running as a child process that speaks UCI over stdin and stdout.
+ * The output is read on its own thread, so an engine that hangs runs into a timeout,
+ * and one that crashes closes the channel.
+ */
+pub struct UciEngine {
+    pub name: String,
+    child: Child,
+    stdin: ChildStdin,
+    lines: Receiver<String>,
+}
+
+impl UciEngine {
+    /**
+     * Starts the engine and waits until it is ready.
+     */
+    pub fn start(path: &str) -> Result<UciEngine, String> {
+        let mut child = Command::new(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|err| format!("Cant start {}: {}", path, err))?;
+        let stdin = child.stdin.take().ok_or("No stdin of the engine".to_string())?;
+        let stdout = BufReader::new(child.stdout.take().ok_or("No stdout of the engine".to_string())?);
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in stdout.lines().map_while(Result::ok) {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        let mut engine = UciEngine {
+            name: path.to_string(),
+            child,
+            stdin,
+            lines,
+        };
+
+        engine.send("uci")?;
+        let deadline = Some(Instant::now() + READY_TIMEOUT);
+        loop {
+            let line = engine.read_line(deadline)?;
+            if let Some(name) = line.strip_prefix("id name ") {
+                engine.name = name.trim().to_string();
+            }
+            if line.trim() == "uciok" {
+                break;
+            }
+        }
+        engine.wait_ready()?;
+        Ok(engine)
+    }
+
+    pub fn send(&mut self, command: &str) -> Result<(), String> {
+        writeln!(self.stdin, "{}", command)
+            .and_then(|_| self.stdin.flush())
+            .map_err(|err| format!("Cant write to {}: {}", self.name, err))
+    }
+
+    /**
+     * Waits for the next line of the engine, until the deadline if there is one.
+     */
+    fn read_line(&mut self, deadline: Option<Instant>) -> Result<String, String> {
+        let line = match deadline {
+            Some(deadline) => self.lines.recv_timeout(deadline.saturating_duration_since(Instant::now())),
+            None => self.lines.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match line {
+            Ok(line) => Ok(line),
+            Err(RecvTimeoutError::Timeout) => Err(format!("{} did not answer in time", self.name)),
+            Err(RecvTimeoutError::Disconnected) => {
+                // The output closes just before the process exits
+                let exit_deadline = Instant::now() + Duration::from_millis(100);
+                while self.is_running() && Instant::now() < exit_deadline {
+                    thread::sleep(Duration::from_millis(5));
+                }
+                match self.child.try_wait() {
+                    Ok(Some(status)) => Err(format!("{} crashed ({})", self.name, status)),
+                    _ => Err(format!("{} closed its output", self.name)),
+                }
+            }
+        }
+    }
+
+    pub fn is_running(&mut self) -> bool {
+        matches!(self.child.try_wait(), Ok(None))
+    }
+
+    pub fn wait_ready(&mut self) -> Result<(), String> {
+        self.send("isready")?;
+        let deadline = Some(Instant::now() + READY_TIMEOUT);
+        while self.read_line(deadline)?.trim() != "readyok" {}
+        Ok(())
+    }
+
+    pub fn new_game(&mut self) -> Result<(), String> {
+        self.send("ucinewgame")?;
+        self.wait_ready()
+    }
+
+    /**
+     * Searches the position after the moves (in UCI notation) and waits for the best move.
+     * An engine that does not answer in time is told to stop, and fails if it does not move even then.
+     */
+    pub fn go(&mut self, fen: &str, moves: &[String], limit: GoLimit) -> Result<EngineMove, String> {
+        let mut position = format!("position fen {}", fen);
+        if !moves.is_empty() {
+            position += &format!(" moves {}", moves.join(" "));
+        }
+        self.send(&position)?;
+        self.send(&match limit {
+            GoLimit::MoveTime(time) => format!("go movetime {}", time),
+            GoLimit::Nodes(nodes) => format!("go nodes {}", nodes),
+            GoLimit::Depth(depth) => format!("go depth {}", depth),
+            GoLimit::Clock { wtime, btime, winc, binc, moves_to_go } => {
+                let moves_to_go = moves_to_go.map_or(String::new(), |moves| format!(" movestogo {}", moves));
+                format!("go wtime {} btime {} winc {} binc {}{}", wtime, btime, winc, binc, moves_to_go)
+            }
+        })?;
+
+        // Searches limited by nodes or depth have no time limit we know of. On a clock the engine
+        // can't take longer than the time of the side to move, which is at most the larger one
+        let search_time = match limit {
+            GoLimit::MoveTime(time) => Some(Duration::from_millis(time)),
+            GoLimit::Clock { wtime, btime, .. } => Some(Duration::from_millis(wtime.max(btime))),
+            _ => None,
+        };
+        let mut deadline = search_time.map(|time| Instant::now() + time + MOVE_GRACE);
+        let mut stopped = false;
+        let mut score = None;
+        loop {
+            let line = match self.read_line(deadline) {
+                Err(_) if !stopped && deadline.is_some() && self.is_running() => {
+                    self.send("stop")?;
+                    stopped = true;
+                    deadline = Some(Instant::now() + MOVE_GRACE);
+                    continue;
+                }
+                line => line?,
+            };
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.first() {
+                Some(&"info") => {
+                    // Lines of other multipv lines than the best are ignored
+                    if let Some(i) = words.iter().position(|word| *word == "multipv") {
+                        if words.get(i + 1) != Some(&"1") {
+                            continue;
+                        }
+                    }
+                    if let Some(i) = words.iter().position(|word| *word == "score") {
+                        let value = words.get(i + 2).and_then(|value| value.parse::<i32>().ok());
+                        score = match (words.get(i + 1), value) {
+                            (Some(&"cp"), Some(value)) => Some(Score::Cp(value)),
+                            (Some(&"mate"), Some(value)) => Some(Score::Mate(value)),
+                            _ => score,
+                        };
+                    }
+                }
+                Some(&"bestmove") => {
+                    return Ok(EngineMove {
+                        best_move: words.get(1).ok_or(format!("{} sent no best move", self.name))?.to_string(),
+                        score,
+                    });
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Drop for UciEngine {
+    /**
+     * Asks the engine to quit, and kills it if it is still running after a second.
+     */
+    fn drop(&mut self) {
+        let _ = self.send("quit");
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while self.is_running() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
@@ -0,0 +1,349 @@
+#!/usr/bin/env python3
+"""
+Generates the KQvK and KRvK Syzygy tables used by the tablebase tests.
+
+The official files can't be downloaded in the test environment, so this writes small tables in the same format:
+a retrograde analysis of the endgame, the index encoding of the reference implementation, a few rounds of
+pair compression and a canonical Huffman code. The analysis is checked against the known longest mates
+(10 moves for KQvK, 16 for KRvK), and every index must hold the same value for all positions mapped to it.
+
+Usage: python3 testdata/syzygy/generate.py [output directory]
+"""
+
+import heapq
+import os
+import struct
+import sys
+
+WDL_MAGIC = bytes([0x71, 0xE8, 0x23, 0x5D])
+DTZ_MAGIC = bytes([0xD7, 0x66, 0x0C, 0xA5])
+BLOCK_SIZE_BITS = 6  # 64 byte blocks
+SPAN_BITS = 8
+MAX_BLOCK_VALUES = 60000
+PAIR_ROUNDS = 40
+
+WHITE_KING, BLACK_KING = 6, 14
+LONGEST_MATE = {"Q": 10, "R": 16}
+PIECE_CODE = {"Q": 5, "R": 4}
+
+
+def rank(square):
+    return square // 8
+
+
+def file(square):
+    return square % 8
+
+
+def adjacent(a, b):
+    return abs(rank(a) - rank(b)) <= 1 and abs(file(a) - file(b)) <= 1
+
+
+KING_STEPS = [(1, 0), (-1, 0), (0, 1), (0, -1), (1, 1), (1, -1), (-1, 1), (-1, -1)]
+SLIDES = {"Q": KING_STEPS, "R": [(1, 0), (-1, 0), (0, 1), (0, -1)]}
+
+
+def steps(square, directions, blockers, slide):
+    """Squares reached from the square, stopping at the first blocker (which is included)."""
+    for df, dr in directions:
+        f, r = file(square) + df, rank(square) + dr
+        while 0 <= f < 8 and 0 <= r < 8:
+            target = r * 8 + f
+            yield target
+            if target in blockers or not slide:
+                break
+            f, r = f + df, r + dr
+
+
+def attacked_by_piece(piece, square, target, blockers):
+    return target in steps(square, SLIDES[piece], blockers, True)
+
+
+def position_index(wk, wx, bk, stm):
+    return ((wk * 64 + wx) * 64 + bk) * 2 + stm
+
+
+def analyse(piece):
+    """
+    Retrograde analysis. Returns the result of every legal position (wk, wx, bk, stm) in plies to mate from the
+    view of the side to move: positive for a win, negative for a loss, None for a draw.
+    """
+    def legal(wk, wx, bk, stm):
+        if len({wk, wx, bk}) < 3 or adjacent(wk, bk):
+            return False
+        # The side not to move can't be in check. Only the black king can be in check
+        return stm == 1 or not attacked_by_piece(piece, wx, bk, {wk})
+
+    def white_moves(wk, wx, bk):
+        for to in steps(wk, KING_STEPS, set(), False):
+            if to != wx and not adjacent(to, bk):
+                yield position_index(to, wx, bk, 1)
+        for to in steps(wx, SLIDES[piece], {wk, bk}, True):
+            if to not in (wk, bk):
+                yield position_index(wk, to, bk, 1)
+
+    def black_moves(wk, wx, bk):
+        """Moves of the black king. A capture of the piece is a draw and yields -1."""
+        for to in steps(bk, KING_STEPS, set(), False):
+            if adjacent(to, wk):
+                continue
+            if to == wx:
+                yield -1
+            elif not attacked_by_piece(piece, wx, to, {wk}):
+                yield position_index(wk, wx, to, 0)
+
+    positions = [(wk, wx, bk, stm) for wk in range(64) for wx in range(64) for bk in range(64) for stm in (0, 1)]
+    positions = [position for position in positions if legal(*position)]
+    predecessors = {}
+    remaining = {}  # Moves of black that don't lose yet, including captures
+    result = {}
+    lost = []
+    for wk, wx, bk, stm in positions:
+        index = position_index(wk, wx, bk, stm)
+        moves = list(white_moves(wk, wx, bk) if stm == 0 else black_moves(wk, wx, bk))
+        for move in moves:
+            if move >= 0:
+                predecessors.setdefault(move, []).append(index)
+        if stm == 1:
+            remaining[index] = len(moves)
+            if not moves and attacked_by_piece(piece, wx, bk, {wk}):
+                result[index] = 0  # Mated
+                lost.append(index)
+
+    # Breadth first from the mates: wins are found at their shortest and losses at their longest distance
+    ply = 0
+    while lost:
+        ply += 1
+        won = []
+        for index in lost:
+            for previous in predecessors.get(index, []):
+                if previous not in result:
+                    result[previous] = ply
+                    won.append(previous)
+        ply += 1
+        lost = []
+        for index in won:
+            for previous in predecessors.get(index, []):
+                remaining[previous] -= 1
+                if remaining[previous] == 0:
+                    result[previous] = -ply
+                    lost.append(previous)
+
+    longest = (max(result.values()) + 1) // 2
+    assert longest == LONGEST_MATE[piece], f"Longest K{piece}vK mate is {longest} moves"
+    return {position: result.get(position_index(*position)) for position in positions}
+
+
+def off_diagonal(square):
+    return rank(square) - file(square)
+
+
+MAP_A1D1D4 = {}
+for diagonal in (False, True):
+    for square in range(28):
+        if file(square) <= 3 and off_diagonal(square) <= 0 and (off_diagonal(square) == 0) == diagonal:
+            MAP_A1D1D4[square] = len(MAP_A1D1D4)
+MAP_B1H1H7 = {square: i for i, square in enumerate(s for s in range(64) if off_diagonal(s) < 0)}
+
+
+def encode(squares):
+    """Index of three unique pieces, as in the reference implementation."""
+    squares = list(squares)
+    if file(squares[0]) > 3:
+        squares = [square ^ 7 for square in squares]
+    if rank(squares[0]) > 3:
+        squares = [square ^ 56 for square in squares]
+    for i in range(3):
+        if off_diagonal(squares[i]) == 0:
+            continue
+        if off_diagonal(squares[i]) > 0:
+            squares = squares[:i] + [((square >> 3) | (square << 3)) & 63 for square in squares[i:]]
+        break
+    s0, s1, s2 = squares
+    adjust1 = int(s1 > s0)
+    adjust2 = int(s2 > s0) + int(s2 > s1)
+    if off_diagonal(s0) != 0:
+        return (MAP_A1D1D4[s0] * 63 + (s1 - adjust1)) * 62 + s2 - adjust2
+    if off_diagonal(s1) != 0:
+        return (6 * 63 + rank(s0) * 28 + MAP_B1H1H7[s1]) * 62 + s2 - adjust2
+    if off_diagonal(s2) != 0:
+        return 6 * 63 * 62 + 4 * 28 * 62 + rank(s0) * 7 * 28 + (rank(s1) - adjust1) * 28 + MAP_B1H1H7[s2]
+    return 6 * 63 * 62 + 4 * 28 * 62 + 4 * 7 * 28 + rank(s0) * 7 * 6 + (rank(s1) - adjust1) * 6 + (rank(s2) - adjust2)
+
+
+TABLE_SIZE = 31332
+
+
+def fill(values_by_position, default):
+    """Values by index. Indices of illegal positions get the default."""
+    table = [None] * TABLE_SIZE
+    for (wk, wx, bk), value in values_by_position.items():
+        # The encoding order of the pieces is the strong king, the piece, the weak king
+        index = encode((wk, wx, bk))
+        assert table[index] in (None, value), f"Index {index} has two values"
+        table[index] = value
+    return [default if value is None else value for value in table]
+
+
+def pair_compress(values):
+    """Replaces the most frequent pairs of symbols by new symbols. Returns the symbols and their tree."""
+    tree = {value: (value, 0xFFF) for value in sorted(set(values))}
+    next_symbol = max(tree) + 1
+    sequence = list(values)
+    for _ in range(PAIR_ROUNDS):
+        counts = {}
+        for pair in zip(sequence, sequence[1:]):
+            counts[pair] = counts.get(pair, 0) + 1
+        if not counts:
+            break
+        pair, count = max(counts.items(), key=lambda item: item[1])
+        if count < 4:
+            break
+        tree[next_symbol] = pair
+        replaced = []
+        i = 0
+        while i < len(sequence):
+            if i + 1 < len(sequence) and (sequence[i], sequence[i + 1]) == pair:
+                replaced.append(next_symbol)
+                i += 2
+            else:
+                replaced.append(sequence[i])
+                i += 1
+        sequence = replaced
+        next_symbol += 1
+    return sequence, tree
+
+
+def huffman_lengths(frequencies):
+    heap = [(frequency, i, [symbol]) for i, (symbol, frequency) in enumerate(frequencies.items())]
+    heapq.heapify(heap)
+    lengths = {symbol: 0 for symbol in frequencies}
+    counter = len(heap)
+    while len(heap) > 1:
+        f1, _, s1 = heapq.heappop(heap)
+        f2, _, s2 = heapq.heappop(heap)
+        for symbol in s1 + s2:
+            lengths[symbol] += 1
+        heapq.heappush(heap, (f1 + f2, counter, s1 + s2))
+        counter += 1
+    return lengths
+
+
+def symbol_values(tree, symbol):
+    left, right = tree[symbol]
+    return 1 if right == 0xFFF else symbol_values(tree, left) + symbol_values(tree, right)
+
+
+def compress(values):
+    """Header sizes data and the sparse index, block lengths and blocks of one table."""
+    sequence, tree = pair_compress(values)
+    frequencies = {symbol: 1 for symbol in tree}
+    for symbol in sequence:
+        frequencies[symbol] += 1
+    lengths = huffman_lengths(frequencies)
+
+    # Canonical code: longer codes get the lower symbol numbers and the lower code values
+    order = sorted(tree, key=lambda symbol: (-lengths[symbol], symbol))
+    number = {symbol: i for i, symbol in enumerate(order)}
+    min_len, max_len = min(lengths.values()), max(lengths.values())
+    count = [sum(1 for s in tree if lengths[s] == min_len + i) for i in range(max_len - min_len + 1)]
+    lowest = [0] * len(count)
+    base = [0] * len(count)
+    for i in range(len(count) - 2, -1, -1):
+        lowest[i] = lowest[i + 1] + count[i + 1]
+        assert (base[i + 1] + count[i + 1]) % 2 == 0
+        base[i] = (base[i + 1] + count[i + 1]) // 2
+    code = {}
+    for symbol in order:
+        i = lengths[symbol] - min_len
+        code[symbol] = (base[i] + number[symbol] - lowest[i], lengths[symbol])
+
+    blocks, block_values = [], []
+    bits, values_in_block, block_bits = [], 0, 0
+    for symbol in sequence:
+        value, length = code[symbol]
+        symbol_count = symbol_values(tree, symbol)
+        if block_bits + length > (8 << BLOCK_SIZE_BITS) or values_in_block + symbol_count > MAX_BLOCK_VALUES:
+            blocks.append(bits)
+            block_values.append(values_in_block)
+            bits, values_in_block, block_bits = [], 0, 0
+        bits.append((value, length))
+        block_bits += length
+        values_in_block += symbol_count
+    blocks.append(bits)
+    block_values.append(values_in_block)
+
+    data = bytearray()
+    for block in blocks:
+        number_bits = "".join(format(value, f"0{length}b") for value, length in block)
+        number_bits = number_bits.ljust(8 << BLOCK_SIZE_BITS, "0")
+        data += int(number_bits, 2).to_bytes(1 << BLOCK_SIZE_BITS, "big")
+
+    starts = [sum(block_values[:i]) for i in range(len(block_values))]
+    span = 1 << SPAN_BITS
+    sparse = bytearray()
+    for k in range((len(values) + span - 1) // span):
+        index = k * span + span // 2
+        block = max(i for i, start in enumerate(starts) if start <= index)
+        sparse += struct.pack("<IH", block, index - starts[block])
+
+    sizes = bytearray([0, BLOCK_SIZE_BITS, SPAN_BITS, 0])
+    sizes += struct.pack("<I", len(blocks))
+    sizes += bytes([max_len, min_len])
+    for value in lowest:
+        sizes += struct.pack("<H", value)
+    sizes += struct.pack("<H", len(tree))
+    for symbol in order:
+        left, right = tree[symbol]
+        if right != 0xFFF:
+            left, right = number[left], number[right]
+        sizes += bytes([left & 0xFF, (left >> 8) | ((right & 0xF) << 4), right >> 4])
+    if len(tree) % 2:
+        sizes += b"\0"
+    block_lengths = b"".join(struct.pack("<H", values - 1) for values in block_values)
+    return sizes, sparse, block_lengths, bytes(data)
+
+
+def write_table(path, magic, piece, tables):
+    """Writes the tables of the sides to move (one for DTZ) of a file."""
+    pieces = [WHITE_KING, PIECE_CODE[piece], BLACK_KING]
+    header = bytearray(magic)
+    header += bytes([1])  # Split: the tables are for white with the piece
+    header += bytes([0])  # The leading group is the first for both sides
+    header += bytes(code | code << 4 for code in pieces)
+    if len(header) % 2:
+        header += b"\0"
+    compressed = [compress(values) for values in tables]
+    for sizes, _, _, _ in compressed:
+        header += sizes
+    for _, sparse, _, _ in compressed:
+        header += sparse
+    for _, _, block_lengths, _ in compressed:
+        header += block_lengths
+    for _, _, _, data in compressed:
+        header += b"\0" * (-len(header) % 64)
+        header += data
+    with open(path, "wb") as output:
+        output.write(header)
+
+
+def generate(piece, directory):
+    result = analyse(piece)
+    wdl = []
+    for stm in (0, 1):
+        values = {(wk, wx, bk): 2 if value is None else (4 if value > 0 else 0)
+                  for (wk, wx, bk, side), value in result.items() if side == stm}
+        wdl.append(fill(values, 2))
+    # DTZ of white to move in moves: a win in n plies is stored as (n - 1) / 2
+    dtz = {(wk, wx, bk): (value - 1) // 2
+           for (wk, wx, bk, side), value in result.items() if side == 0 and value is not None and value > 0}
+    name = f"K{piece}vK"
+    write_table(os.path.join(directory, name + ".rtbw"), WDL_MAGIC, piece, wdl)
+    write_table(os.path.join(directory, name + ".rtbz"), DTZ_MAGIC, piece, [fill(dtz, 0)])
+    print(f"Wrote {name}")
+
+
+if __name__ == "__main__":
+    directory = sys.argv[1] if len(sys.argv) > 1 else os.path.dirname(os.path.abspath(__file__))
+    for piece in ("Q", "R"):
+        generate(piece, directory)